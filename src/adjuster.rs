@@ -1,5 +1,31 @@
-use image::Rgba;
+use std::{collections::HashMap, sync::{Arc, RwLock}};
+
+use image::{Pixel, Rgba};
+use lazy_static::lazy_static;
 use palette::{FromColor, Hsl, Srgb};
+use serde::{de::DeserializeOwned, Deserialize, Serialize};
+use serde_json::{Map, Value};
+
+use crate::error::{BlendError, Result};
+
+/// 图像增强
+///
+/// 只调整 RGB, 不改变透明度. 调整链按顺序对每个像素调用 adjust_pixel.
+pub trait Adjuster: Send + Sync {
+    /// 不改变任何像素时返回 true, 调整链会跳过
+    fn is_noop(&self) -> bool {
+        false
+    }
+
+    fn adjust_pixel(&self, pixel: &mut Rgba<u8>);
+
+    /// 调整一行 RGBA 字节
+    fn adjust_row(&self, row: &mut [u8]) {
+        for pixel in row.chunks_exact_mut(4) {
+            self.adjust_pixel(Rgba::from_slice_mut(pixel));
+        }
+    }
+}
 
 #[derive(Debug)]
 pub struct BrightnessGammaContrastAdjuster {
@@ -13,7 +39,7 @@ impl BrightnessGammaContrastAdjuster {
     pub fn new(brightness: f32, contrast: f32, gamma: f32) -> Self {
         let contrast = ((contrast + 100.0) / 100.0).powi(2);
         let gamma = 1.0 / gamma;
-        let no_affect = brightness == 0.0 && gamma == 1.0 && contrast == 0.0;
+        let no_affect = brightness == 0.0 && gamma == 1.0 && contrast == 1.0;
         BrightnessGammaContrastAdjuster { 
             brightness,
             contrast,
@@ -67,7 +93,7 @@ impl HueSaturationAdjuster {
     pub fn new(saturation: f32, colorize_on: bool, colorize_color: &Option<Vec<u8>>, colorize_strength: u8) -> Self {
         
         let saturation = saturation / 100.0 + 1.0;
-        let no_affect = saturation == 0.0 && colorize_on == false;
+        let no_affect = saturation == 1.0 && colorize_on == false;
 
         let (colorize_color, colorize_h, colorize_s) = 
             if let Some(color) = colorize_color {
@@ -158,3 +184,304 @@ impl HueSaturationAdjuster {
     }
 }
 
+impl Adjuster for BrightnessGammaContrastAdjuster {
+    fn is_noop(&self) -> bool {
+        self.no_affect
+    }
+
+    fn adjust_pixel(&self, pixel: &mut Rgba<u8>) {
+        BrightnessGammaContrastAdjuster::adjust_pixel(self, pixel)
+    }
+}
+
+impl Adjuster for HueSaturationAdjuster {
+    fn is_noop(&self) -> bool {
+        self.no_affect
+    }
+
+    fn adjust_pixel(&self, pixel: &mut Rgba<u8>) {
+        HueSaturationAdjuster::adjust_pixel(self, pixel)
+    }
+}
+
+/// 查找表调整, 色阶和曲线都转换为 256 项的查找表
+pub struct LutAdjuster {
+    lut: [u8; 256],
+}
+
+impl LutAdjuster {
+    pub fn new(lut: [u8; 256]) -> Self {
+        Self { lut }
+    }
+
+    /// 色阶: 输入黑白场之间的值经 gamma 校正后映射到输出黑白场
+    pub fn levels(black: u8, white: u8, gamma: f32, out_black: u8, out_white: u8) -> Result<Self> {
+        if black >= white {
+            return Err(BlendError::InvalidParameter(format!("levels black {} must be less than white {}", black, white)));
+        }
+        if gamma <= 0.0 {
+            return Err(BlendError::InvalidParameter(format!("levels gamma {} must be positive", gamma)));
+        }
+
+        let mut lut = [0; 256];
+        for (c, value) in lut.iter_mut().enumerate() {
+            let v = ((c as f32 - black as f32) / (white as f32 - black as f32)).clamp(0.0, 1.0);
+            let v = v.powf(1.0 / gamma);
+            *value = (out_black as f32 + v * (out_white as f32 - out_black as f32)).round() as u8;
+        }
+        Ok(Self::new(lut))
+    }
+
+    /// 曲线: 控制点之间线性插值, 第一个控制点之前和最后一个控制点之后保持端点的值
+    pub fn curves(points: &[[u8; 2]]) -> Result<Self> {
+        if points.len() < 2 {
+            return Err(BlendError::InvalidParameter("curves needs at least 2 points".to_string()));
+        }
+        let mut points = points.to_vec();
+        points.sort_by_key(|point| point[0]);
+
+        let mut lut = [0; 256];
+        for (c, value) in lut.iter_mut().enumerate() {
+            let c = c as u8;
+            let upper = points.iter().position(|point| point[0] >= c).unwrap_or(points.len() - 1);
+            let [x1, y1] = points[upper];
+            let [x0, y0] = points[upper.saturating_sub(1)];
+            *value = if c >= x1 || x1 == x0 {
+                y1
+            } else if c <= x0 {
+                y0
+            } else {
+                let t = (c - x0) as f32 / (x1 - x0) as f32;
+                (y0 as f32 + t * (y1 as f32 - y0 as f32)).round() as u8
+            };
+        }
+        Ok(Self::new(lut))
+    }
+}
+
+impl Adjuster for LutAdjuster {
+    fn is_noop(&self) -> bool {
+        self.lut.iter().enumerate().all(|(c, value)| c == *value as usize)
+    }
+
+    fn adjust_pixel(&self, pixel: &mut Rgba<u8>) {
+        pixel[0] = self.lut[pixel[0] as usize];
+        pixel[1] = self.lut[pixel[1] as usize];
+        pixel[2] = self.lut[pixel[2] as usize];
+    }
+}
+
+/// 按顺序执行的调整链, 添加时跳过不改变像素的调整
+#[derive(Default)]
+pub struct AdjusterChain {
+    adjusters: Vec<Box<dyn Adjuster>>,
+}
+
+impl AdjusterChain {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn push<T: Adjuster + 'static>(&mut self, adjuster: T) {
+        self.push_boxed(Box::new(adjuster));
+    }
+
+    pub fn push_boxed(&mut self, adjuster: Box<dyn Adjuster>) {
+        if !adjuster.is_noop() {
+            self.adjusters.push(adjuster);
+        }
+    }
+
+    /// 由 --adjust 或 recipe 中的调整步骤构建
+    pub fn from_steps(steps: &[AdjustStep]) -> Result<Self> {
+        let mut chain = Self::new();
+        for step in steps {
+            chain.push_boxed(step.build()?);
+        }
+        Ok(chain)
+    }
+
+    pub fn len(&self) -> usize {
+        self.adjusters.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.adjusters.is_empty()
+    }
+
+    pub fn adjust_pixel(&self, pixel: &mut Rgba<u8>) {
+        for adjuster in &self.adjusters {
+            adjuster.adjust_pixel(pixel);
+        }
+    }
+
+    pub fn adjust_row(&self, row: &mut [u8]) {
+        for pixel in row.chunks_exact_mut(4) {
+            self.adjust_pixel(Rgba::from_slice_mut(pixel));
+        }
+    }
+}
+
+/// 调整链中的一步: 调整的名称和参数
+///
+/// recipe 中写作 `{ "type": "levels", "black": 10, "white": 240 }`,
+/// 命令行中写作 `levels:black=10;white=240`, 参数值按 json 解析, 不是合法的 json 时作为字符串.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct AdjustStep {
+    #[serde(rename = "type")]
+    pub name: String,
+    #[serde(flatten)]
+    pub params: Map<String, Value>,
+}
+
+impl AdjustStep {
+    pub fn parse(step: &str) -> Result<AdjustStep> {
+        let (name, params_str) = step.split_once(':').unwrap_or((step, ""));
+        let mut params = Map::new();
+        for param in params_str.split(';').map(str::trim).filter(|param| !param.is_empty()) {
+            let (key, value) = param
+                .split_once('=')
+                .ok_or_else(|| BlendError::InvalidParameter(format!("Invalid adjust parameter {}, expected key=value", param)))?;
+            let value = serde_json::from_str(value.trim()).unwrap_or_else(|_| Value::String(value.trim().to_string()));
+            params.insert(key.trim().to_string(), value);
+        }
+
+        let step = AdjustStep {
+            name: name.trim().to_string(),
+            params,
+        };
+        step.build()?;
+        Ok(step)
+    }
+
+    pub fn build(&self) -> Result<Box<dyn Adjuster>> {
+        let factory = {
+            let factories = ADJUSTERS.read().unwrap_or_else(|e| e.into_inner());
+            factories
+                .get(&self.name.to_lowercase())
+                .cloned()
+                .ok_or_else(|| BlendError::InvalidParameter(format!("unknown adjuster: {}", self.name)))?
+        };
+        factory(&self.params)
+    }
+
+    /// 把参数解析为 T, 供自定义调整的构造函数使用
+    pub fn parse_params<T: DeserializeOwned>(name: &str, params: &Map<String, Value>) -> Result<T> {
+        serde_json::from_value(Value::Object(params.clone()))
+            .map_err(|e| BlendError::InvalidParameter(format!("Invalid {} parameters: {}", name, e)))
+    }
+}
+
+/// 由参数构造调整的函数
+pub type AdjusterFactory = Arc<dyn Fn(&Map<String, Value>) -> Result<Box<dyn Adjuster>> + Send + Sync>;
+
+lazy_static!(
+    static ref ADJUSTERS: RwLock<HashMap<String, AdjusterFactory>> = RwLock::new(builtin_adjusters());
+);
+
+/// 注册自定义调整, 注册后可以在 --adjust 和 recipe 中使用, 名称不区分大小写且不能与已有的重复
+pub fn register_adjuster<F>(name: &str, factory: F) -> Result<()>
+where
+    F: Fn(&Map<String, Value>) -> Result<Box<dyn Adjuster>> + Send + Sync + 'static,
+{
+    let name = name.to_lowercase();
+    let mut factories = ADJUSTERS.write().unwrap_or_else(|e| e.into_inner());
+    if factories.contains_key(&name) {
+        return Err(BlendError::InvalidParameter(format!("adjuster {} is already registered", name)));
+    }
+    factories.insert(name, Arc::new(factory));
+    Ok(())
+}
+
+/// 所有已注册的调整名称, 按字母排序
+pub fn adjuster_names() -> Vec<String> {
+    let factories = ADJUSTERS.read().unwrap_or_else(|e| e.into_inner());
+    let mut names: Vec<String> = factories.keys().cloned().collect();
+    names.sort();
+    names
+}
+
+#[derive(Deserialize)]
+#[serde(default, deny_unknown_fields)]
+struct BrightnessContrastParams {
+    brightness: f32,
+    contrast: f32,
+    gamma: f32,
+}
+
+impl Default for BrightnessContrastParams {
+    fn default() -> Self {
+        Self { brightness: 0.0, contrast: 0.0, gamma: 1.0 }
+    }
+}
+
+#[derive(Deserialize)]
+#[serde(default, deny_unknown_fields)]
+struct LevelsParams {
+    black: u8,
+    white: u8,
+    gamma: f32,
+    out_black: u8,
+    out_white: u8,
+}
+
+impl Default for LevelsParams {
+    fn default() -> Self {
+        Self { black: 0, white: 255, gamma: 1.0, out_black: 0, out_white: 255 }
+    }
+}
+
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+struct CurvesParams {
+    points: Vec<[u8; 2]>,
+}
+
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+struct SaturationParams {
+    saturation: f32,
+}
+
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+struct ColorizeParams {
+    color: [u8; 3],
+    #[serde(default = "default_colorize_strength")]
+    strength: u8,
+}
+
+fn default_colorize_strength() -> u8 {
+    100
+}
+
+fn builtin_adjusters() -> HashMap<String, AdjusterFactory> {
+    let mut factories: HashMap<String, AdjusterFactory> = HashMap::new();
+    factories.insert("brightness-contrast".to_string(), Arc::new(|params| {
+        let p: BrightnessContrastParams = AdjustStep::parse_params("brightness-contrast", params)?;
+        Ok(Box::new(BrightnessGammaContrastAdjuster::new(p.brightness, p.contrast, p.gamma)))
+    }));
+    factories.insert("levels".to_string(), Arc::new(|params| {
+        let p: LevelsParams = AdjustStep::parse_params("levels", params)?;
+        Ok(Box::new(LutAdjuster::levels(p.black, p.white, p.gamma, p.out_black, p.out_white)?))
+    }));
+    factories.insert("curves".to_string(), Arc::new(|params| {
+        let p: CurvesParams = AdjustStep::parse_params("curves", params)?;
+        Ok(Box::new(LutAdjuster::curves(&p.points)?))
+    }));
+    factories.insert("saturation".to_string(), Arc::new(|params| {
+        let p: SaturationParams = AdjustStep::parse_params("saturation", params)?;
+        if !(-100.0..=100.0).contains(&p.saturation) {
+            return Err(BlendError::InvalidParameter(format!("saturation {} is out of range [-100, 100]", p.saturation)));
+        }
+        Ok(Box::new(HueSaturationAdjuster::new(p.saturation, false, &None, 100)))
+    }));
+    factories.insert("colorize".to_string(), Arc::new(|params| {
+        let p: ColorizeParams = AdjustStep::parse_params("colorize", params)?;
+        if p.strength > 100 {
+            return Err(BlendError::InvalidParameter(format!("colorize strength {} is out of range [0, 100]", p.strength)));
+        }
+        Ok(Box::new(HueSaturationAdjuster::new(0.0, true, &Some(p.color.to_vec()), p.strength)))
+    }));
+    factories
+}
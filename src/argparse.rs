@@ -1,56 +1,26 @@
 use std::env;
 
-use anyhow::{Result, anyhow};
-use clap::{Parser,ValueEnum};
+use chrono::{DateTime, FixedOffset};
+use clap::{Args, Parser, Subcommand, ValueEnum};
 use serde::{Deserialize, Serialize};
 
-#[derive(Debug, Clone, ValueEnum, Serialize, Deserialize)]
-pub enum BlendMode {
-    Overlay,
-    Over,
-    Atop,
-    Xor,
-    Multiply,
-    Burn,
-    Softlight,
-    Hardlight,
-    Difference,
-    Lighten,
-    Darken,
-    Dodge,
-    Plus,
-    Addition,
-    Exclusion,
-    Subtract,
-    Screen,
-}
-
-impl BlendMode{
-
-    pub fn blend_name(&self) -> String{
+use std::path::Path;
 
-        match self {
-            Self::Overlay => "overlay".to_string(),
-            Self::Over => "over".to_string(),
-            Self::Atop => "atop".to_string(),
-            Self::Xor => "xor".to_string(),
-            Self::Multiply => "multiply".to_string(),
-            Self::Burn => "burn".to_string(),
-            Self::Softlight => "softlight".to_string(),
-            Self::Hardlight => "hardlight".to_string(),
-            Self::Difference => "difference".to_string(),
-            Self::Lighten => "lighten".to_string(),
-            Self::Darken => "darken".to_string(),
-            Self::Dodge => "dodge".to_string(),
-            Self::Plus => "plus".to_string(),
-            Self::Addition => "addition".to_string(),
-            Self::Exclusion => "exclusion".to_string(),
-            Self::Subtract => "subtract".to_string(),
-            Self::Screen => "screen".to_string(),
-            
-        }
-    }
-}
+use image::ImageFormat;
+
+use crate::output::{ExistsPolicy, OutputSpec, OutputTarget, DEFAULT_BATCH_TEMPLATE, DEFAULT_TEMPLATE};
+use crate::adjuster::{adjuster_names, AdjustStep};
+use crate::blend_op::{blend_op, blend_op_names};
+use crate::error::{BlendError, Result};
+use crate::expr::EXPR_PREFIX;
+use crate::georef::{GeorefSource, GridMismatch};
+use crate::geotiff::{parse_creation_option, Alpha, BigTiff, Compression, Photometric, Predictor, Resampling, TiffOptions};
+use crate::bands::{BandMapping, Stretch};
+use crate::pipeline::{Adjustments, LayerSource};
+use crate::recipe::Recipe;
+use crate::calc::DEFAULT_NODATA;
+use crate::terrain::{Derivative, SlopeUnit, TerrainStyle};
+use crate::tiles::{TileScheme, ZoomRange};
 
 #[derive(Debug, Clone, ValueEnum, Serialize, Deserialize, PartialEq, Eq)]
 pub enum Format {
@@ -69,6 +39,49 @@ impl Format{
             Self::TIFF => "tiff".to_string(),
         }
     }
+
+    /// 由文件扩展名推断输出格式
+    pub fn from_path(path: &str) -> Option<Format> {
+        let extension = Path::new(path).extension()?.to_str()?.to_lowercase();
+        match extension.as_str() {
+            "png" => Some(Self::PNG),
+            "webp" => Some(Self::WEBP),
+            "jpg" | "jpeg" => Some(Self::JPEG),
+            "tif" | "tiff" => Some(Self::TIFF),
+            _ => None,
+        }
+    }
+
+    pub fn image_format(&self) -> ImageFormat {
+        match self {
+            Self::PNG => ImageFormat::Png,
+            Self::WEBP => ImageFormat::WebP,
+            Self::JPEG => ImageFormat::Jpeg,
+            Self::TIFF => ImageFormat::Tiff,
+        }
+    }
+}
+
+fn blend_mode_parser(s: &str) -> Result<String, String> {
+    blend_op(s).map(|op| op.name().to_string()).map_err(|e| {
+        if s.to_lowercase().starts_with(EXPR_PREFIX) {
+            e.to_string()
+        } else {
+            format!("`{}` is not a blend mode, available: {}, or {}<expression>", s, blend_op_names().join(", "), EXPR_PREFIX)
+        }
+    })
+}
+
+fn adjust_step_parser(s: &str) -> Result<AdjustStep, String> {
+    AdjustStep::parse(s).map_err(|e| {
+        let name = s.split(':').next().unwrap_or("").trim().to_lowercase();
+        let names = adjuster_names();
+        if names.contains(&name) {
+            e.to_string()
+        } else {
+            format!("{}, available: {}", e, names.join(", "))
+        }
+    })
 }
 
 fn gamma_value_parser(s: &str) -> Result<f32, String> {
@@ -120,31 +133,413 @@ fn colorize_strength_parse(s: &str) -> Result<u8, String>{
 
 pub trait ArgParseProcess {
     type Error;
-    fn output_folder(&self) -> Result<String>;
+    fn output_target(&self) -> Result<OutputTarget>;
     fn parse_color(&self) -> Result<Option<Vec<u8>>>;
+    fn output_template(&self) -> &str;
+    fn exists_policy(&self) -> ExistsPolicy;
+    fn adjustments(&self, recipe: &Recipe) -> Result<Adjustments>;
+    fn output_spec(&self, recipe: &Recipe) -> Result<OutputSpec>;
+    fn recipe(&self) -> Result<Recipe>;
+    fn layers(&self, image: &str, image2: &str) -> (LayerSource, LayerSource);
+    fn tiff_options(&self, recipe: &Recipe) -> Result<TiffOptions>;
+}
+
+
+/// How batch mode pairs the files of --base-dir and --overlay-dir
+#[derive(Debug, Clone, ValueEnum, Serialize, Deserialize, PartialEq, Eq)]
+pub enum PairBy {
+    /// Same file name without extension
+    Stem,
+    /// Same path relative to the folder, e.g. 12/3456/789.png
+    Path,
+    /// Same capture groups of --base-regex / --overlay-regex
+    Regex,
+    /// Overlapping georeferenced footprints
+    Overlap,
+}
+
+#[derive(Subcommand, Debug, Clone, Serialize, Deserialize)]
+pub enum Command {
+    /// Blend many image pairs in parallel, listed in a manifest or paired across two directories
+    Batch(BatchArgs),
+    /// Evaluate a per-pixel expression over bands of several rasters, writing a GeoTIFF or a grayscale layer
+    Calc(CalcArgs),
+    /// Compute a DEM derivative: slope, aspect, curvature, TRI, TPI, roughness, sky-view factor, ambient occlusion,
+    /// hillshade, cast shadows or texture shading, written as raw values to a
+    /// Float32 GeoTIFF, or coloured into an RGBA layer for blending when the output is png, webp, jpeg or --style is given
+    Terrain(TerrainArgs),
+    /// Blend image and image2, then cut the georeferenced result into a Web Mercator tile pyramid,
+    /// written to a z/x/y folder, or a single .mbtiles or .gpkg file given by -o
+    Tiles(TilesArgs),
+    /// Serve XYZ tiles on localhost, blending windows of image and image2 per tile, with blend parameters
+    /// taken from the query string, e.g. /12/3370/1552.png?mode=multiply&gamma=1.2
+    Serve(ServeArgs),
+    /// Run an HTTP blending service on localhost: POST /blend with uploaded images or server paths, GET /health
+    Api(ApiArgs),
+}
+
+fn calc_input_parser(s: &str) -> Result<(String, String), String> {
+    let (name, path) = s.split_once('=').ok_or_else(|| format!("expected NAME=PATH, got '{}'", s))?;
+    if name.is_empty() || !name.chars().all(|c| c.is_ascii_alphabetic()) {
+        return Err(format!("input name must be letters only, got '{}'", name));
+    }
+    Ok((name.to_lowercase(), path.to_string()))
+}
+
+fn time_parser(s: &str) -> Result<DateTime<FixedOffset>, String> {
+    DateTime::parse_from_rfc3339(s).map_err(|e| format!("expected an RFC 3339 time like 2024-06-21T15:00:00+02:00, got '{}': {}", s, e))
+}
+
+fn detail_parser(s: &str) -> Result<f64, String> {
+    let value: f64 = s.parse().map_err(|_| format!("`{}` is not a valid number", s))?;
+    if !(0.0..=2.0).contains(&value) {
+        Err(format!("`{}` is out of range. It should be between 0 and 2", s))
+    } else {
+        Ok(value)
+    }
+}
+
+fn lon_lat_parser(s: &str) -> Result<(f64, f64), String> {
+    let (lon, lat) = range_parser(s).map_err(|_| format!("expected LON,LAT, got '{}'", s))?;
+    if !(-180.0..=180.0).contains(&lon) || !(-90.0..=90.0).contains(&lat) {
+        return Err(format!("'{}' is not a valid longitude and latitude", s));
+    }
+    Ok((lon, lat))
+}
+
+fn range_parser(s: &str) -> Result<(f64, f64), String> {
+    let (min, max) = s.split_once(',').ok_or_else(|| format!("expected MIN,MAX, got '{}'", s))?;
+    let parse = |v: &str| v.trim().parse::<f64>().map_err(|e| format!("invalid number '{}': {}", v, e));
+    Ok((parse(min)?, parse(max)?))
+}
+
+#[derive(Args, Debug, Clone, Serialize, Deserialize)]
+pub struct CalcArgs {
+    /// An input raster as NAME=PATH, the expression refers to its bands as NAME (band 1) or NAME2, NAME3...
+    #[arg(short, long = "input", value_parser = calc_input_parser, required = true)]
+    pub inputs: Vec<(String, String)>,
+
+    /// The per-pixel expression, e.g. "(nir - red) / (nir + red)"
+    #[arg(short, long)]
+    pub expr: String,
+
+    /// The nodata value of the result, pixels where any input band is nodata become nodata
+    #[arg(long, default_value_t = DEFAULT_NODATA, allow_hyphen_values = true)]
+    pub nodata: f64,
+
+    /// The value range mapped to 0-255 when the output is an image (png, webp, jpeg), as MIN,MAX, default is the data range
+    #[arg(long, value_parser = range_parser, allow_hyphen_values = true)]
+    pub range: Option<(f64, f64)>,
+}
+
+#[derive(Args, Debug, Clone, Serialize, Deserialize)]
+pub struct TilesArgs {
+    /// The zoom levels to generate, as MIN-MAX or a single level, e.g. 8-14
+    #[arg(short, long, value_parser = clap::value_parser!(ZoomRange))]
+    pub zoom: ZoomRange,
+
+    /// The tile row order, xyz counts rows from the north, tms from the south
+    #[arg(value_enum, long, default_value_t = TileScheme::Xyz)]
+    pub scheme: TileScheme,
+
+    /// The tile image format: png, webp or jpeg
+    #[arg(value_enum, long, default_value_t = Format::PNG)]
+    pub tile_format: Format,
+
+    /// The width and height of tiles in pixels
+    #[arg(long, default_value_t = 256)]
+    pub tile_size: usize,
+
+    /// The name stored in MBTiles or GeoPackage metadata, default is the basemap file name
+    #[arg(long)]
+    pub name: Option<String>,
+
+    /// The attribution stored in MBTiles or GeoPackage metadata
+    #[arg(long)]
+    pub attribution: Option<String>,
+
+    /// The description stored in MBTiles or GeoPackage metadata
+    #[arg(long)]
+    pub description: Option<String>,
+}
+
+#[derive(Args, Debug, Clone, Serialize, Deserialize)]
+pub struct TerrainArgs {
+    /// The derivative to compute
+    #[arg(value_enum)]
+    pub derivative: Derivative,
+
+    /// The DEM, band 1 is read, pixel sizes in degrees are converted to meters
+    #[arg(long)]
+    pub dem: String,
+
+    /// The unit of slope
+    #[arg(value_enum, long, default_value_t = SlopeUnit::Degrees)]
+    pub unit: SlopeUnit,
+
+    /// The elevation multiplier, for elevation units other than the pixel size unit or vertical exaggeration
+    #[arg(long, default_value_t = 1.0)]
+    pub z_factor: f64,
+
+    /// The horizon search distance of sky-view and ambient-occlusion in pixels
+    #[arg(long, default_value_t = 10, value_parser = clap::value_parser!(u32).range(1..))]
+    pub radius: u32,
+
+    /// The number of horizon search directions of sky-view and ambient-occlusion
+    #[arg(long, default_value_t = 16, value_parser = clap::value_parser!(u32).range(1..))]
+    pub directions: u32,
+
+    /// The sun azimuth of hillshade and shadow in degrees, clockwise from north
+    #[arg(long, default_value_t = 315.0)]
+    pub sun_azimuth: f64,
+
+    /// The sun altitude of hillshade and shadow in degrees above the horizon
+    #[arg(long, default_value_t = 45.0, allow_hyphen_values = true)]
+    pub sun_altitude: f64,
+
+    /// Compute the sun position at this RFC 3339 time instead, e.g. 2024-06-21T15:00:00+02:00
+    #[arg(long, value_parser = time_parser, conflicts_with_all = ["sun_azimuth", "sun_altitude"])]
+    pub time: Option<DateTime<FixedOffset>>,
+
+    /// The LON,LAT used with --time, default is the centre of the DEM
+    #[arg(long, value_parser = lon_lat_parser, requires = "time", allow_hyphen_values = true)]
+    pub location: Option<(f64, f64)>,
+
+    /// The angular width of the shadow penumbra in degrees, 0 gives a binary shadow
+    #[arg(long, default_value_t = 0.0)]
+    pub penumbra: f64,
+
+    /// Darken hillshade by the shadows cast by the terrain
+    #[arg(long, default_value_t = false)]
+    pub cast_shadows: bool,
+
+    /// The order of the texture shading fractional Laplacian, 0-2, higher values bring out finer detail
+    #[arg(long, default_value_t = 0.5, value_parser = detail_parser)]
+    pub detail: f64,
+
+    /// How the colour range is computed from the data when --range is not given:
+    /// none, minmax, percentile[:LOW,HIGH] or stddev[:N], default is percentile:2,98 for texture
+    #[arg(long, conflicts_with = "range", value_parser = clap::value_parser!(Stretch))]
+    pub stretch: Option<Stretch>,
+
+    /// How to colour the derivative, default is shade for slope, TRI and roughness, aspect for aspect,
+    /// diverging for curvatures and TPI, and gray for sky-view, ambient-occlusion, hillshade, shadow and texture
+    #[arg(value_enum, long)]
+    pub style: Option<TerrainStyle>,
+
+    /// The value range mapped to the colours as MIN,MAX, default is the data range
+    #[arg(long, value_parser = range_parser, allow_hyphen_values = true)]
+    pub range: Option<(f64, f64)>,
+}
+
+#[derive(Args, Debug, Clone, Serialize, Deserialize)]
+pub struct ServeArgs {
+    /// The port to listen on
+    #[arg(short, long, default_value_t = 8080)]
+    pub port: u16,
+
+    /// The address to listen on, keep the default to serve this machine only
+    #[arg(long, default_value = "127.0.0.1")]
+    pub host: String,
+
+    /// The folder caching rendered tiles, tiles are rendered on every request when not set
+    #[arg(long)]
+    pub cache_dir: Option<String>,
+
+    /// The number of threads rendering tiles, default is the number of CPUs
+    #[arg(long)]
+    pub threads: Option<usize>,
+
+    /// The width and height of tiles in pixels
+    #[arg(long, default_value_t = 256)]
+    pub tile_size: usize,
+}
+
+#[derive(Args, Debug, Clone, Serialize, Deserialize)]
+pub struct ApiArgs {
+    /// The port to listen on
+    #[arg(short, long, default_value_t = 8081)]
+    pub port: u16,
+
+    /// The address to listen on, keep the default to serve this machine only
+    #[arg(long, default_value = "127.0.0.1")]
+    pub host: String,
+
+    /// The folder holding images that requests may refer to by relative path, only uploads are accepted when not set
+    #[arg(long)]
+    pub root: Option<String>,
+
+    /// The maximum request body size in MiB
+    #[arg(long, default_value_t = 64)]
+    pub max_body_mb: usize,
+
+    /// The maximum number of blends running at the same time, more requests get 503
+    #[arg(long, default_value_t = 4)]
+    pub max_concurrent: usize,
 }
 
+#[derive(Args, Debug, Clone, Serialize, Deserialize)]
+pub struct BatchArgs {
+    /// CSV or JSON manifest, each row holds base, overlay, and optionally mode and output
+    #[arg(long, conflicts_with_all = ["base_dir", "overlay_dir"], required_unless_present = "base_dir")]
+    pub manifest: Option<String>,
+
+    /// The folder of basemap images, paired with --overlay-dir by file stem
+    #[arg(long, requires = "overlay_dir")]
+    pub base_dir: Option<String>,
+
+    /// The folder of upper layer images, paired with --base-dir by file stem
+    #[arg(long, requires = "base_dir")]
+    pub overlay_dir: Option<String>,
+
+    /// The glob pattern used to select files in both folders, use **/ to search sub folders.
+    /// Only image files are used, world files and .aux.xml next to them are skipped
+    #[arg(long, default_value = "*")]
+    pub pattern: String,
+
+    /// The strategy used to pair files of --base-dir and --overlay-dir
+    #[arg(value_enum, long, default_value_t = PairBy::Stem)]
+    pub pair_by: PairBy,
+
+    /// The regex matched against relative paths of basemap images, its capture groups form the pairing key
+    #[arg(long, required_if_eq("pair_by", "regex"))]
+    pub base_regex: Option<String>,
+
+    /// The regex matched against relative paths of upper layer images, default is --base-regex
+    #[arg(long)]
+    pub overlay_regex: Option<String>,
+
+    /// The minimum overlap, as a fraction of the basemap footprint, to pair two georeferenced images
+    #[arg(long, default_value_t = 0.5)]
+    pub min_overlap: f64,
+}
 
 #[derive(Parser, Debug, Clone, Serialize, Deserialize)]
 #[command(author, version, about, long_about = None)]
 pub struct ArgParse{
-    /// The path to the image, the basemap of blend image 
+    #[command(subcommand)]
+    pub command: Option<Command>,
+
+    /// The path to the image, the basemap of blend image, - reads it from stdin
+    #[arg(default_value = "", hide_default_value = true)]
     pub image: String,
 
-    /// The path to the image, The upper layer image of the blend
+    /// The path to the image, The upper layer image of the blend, - reads it from stdin
+    #[arg(default_value = "", hide_default_value = true)]
     pub image2: String,
 
-    /// The blend image save path
+    /// Read the basemap through GDAL mapping source bands to R,G,B or R,G,B,A, e.g. 8,4,3 for a Sentinel-2 false colour composite
+    #[arg(long, value_parser = clap::value_parser!(BandMapping))]
+    pub bands: Option<BandMapping>,
+
+    /// The per-band stretch of --bands: none, minmax, percentile[:LOW,HIGH] or stddev[:N], default is percentile:2,98
+    #[arg(long, requires = "bands", value_parser = clap::value_parser!(Stretch))]
+    pub stretch: Option<Stretch>,
+
+    /// Read the upper layer image through GDAL mapping source bands to R,G,B or R,G,B,A
+    #[arg(long, value_parser = clap::value_parser!(BandMapping))]
+    pub bands2: Option<BandMapping>,
+
+    /// The per-band stretch of --bands2
+    #[arg(long, requires = "bands2", value_parser = clap::value_parser!(Stretch))]
+    pub stretch2: Option<Stretch>,
+
+    /// The blend image save path: a folder, a file whose extension selects the format, or - for stdout
     #[arg(short, long)]
     pub output: Option<String>,
 
-    /// The blend mode, default is overlay
-    #[arg(value_enum, short = 'm', long, default_value_t = BlendMode::Overlay)]
-    pub blend_mode: BlendMode,
+    /// The output file name template, placeholders: {stem}, {stem2}, {mode}, {index}, {format}.
+    /// Default is {stem}.{format}, or {stem}_{mode}.{format} in batch mode
+    #[arg(long)]
+    pub output_template: Option<String>,
+
+    /// Overwrite existing output files, this is the default
+    #[arg(long, default_value_t = false, conflicts_with_all = ["skip_existing", "fail_if_exists"])]
+    pub overwrite: bool,
+
+    /// Skip blending when the output file already exists
+    #[arg(long, default_value_t = false, conflicts_with = "fail_if_exists")]
+    pub skip_existing: bool,
+
+    /// Fail when the output file already exists
+    #[arg(long, default_value_t = false)]
+    pub fail_if_exists: bool,
+
+    /// The blend mode, default is overlay. Built-in modes: overlay, multiply, burn, softlight, hardlight,
+    /// difference, lighten, darken, dodge, plus, addition, exclusion, subtract, screen, and the Porter-Duff
+    /// operators over, atop, xor, in, out, dest-over, dest-in, dest-out, dest-atop, clear, source, destination.
+    /// Use expr:<expression> for a per-pixel formula, e.g. "expr:base * (0.4 + 0.6 * top)"
+    #[arg(short = 'm', long, value_parser = blend_mode_parser)]
+    pub blend_mode: Option<String>,
+
+    /// A JSON recipe holding the blend mode, swap_layers and adjustments, -m and --swap-layers take precedence
+    #[arg(long)]
+    pub recipe: Option<String>,
+
+    /// Swap the basemap and the upper layer, so image becomes the source and image2 the destination
+    #[arg(long, default_value_t = false)]
+    pub swap_layers: bool,
+
+    /// The input whose georeferencing, metadata and band descriptions are copied to the output
+    #[arg(value_enum, long, default_value_t = GeorefSource::Base)]
+    pub georef_from: GeorefSource,
+
+    /// What to do when both inputs are georeferenced but their CRS or geotransform differ
+    #[arg(value_enum, long, default_value_t = GridMismatch::Warn)]
+    pub grid_mismatch: GridMismatch,
 
+    /// The output format, used when the output file extension is not a known format
     #[arg(value_enum, long, default_value_t = Format::PNG)]
     pub format: Format,
 
+    /// Save tiff outputs as Cloud Optimized GeoTIFF, tiled with internal overviews
+    #[arg(long, default_value_t = false)]
+    pub cog: bool,
+
+    /// The compression of tiff output, default is the compression of the georeferenced input or lzw, deflate for COG
+    #[arg(value_enum, long)]
+    pub compress: Option<Compression>,
+
+    /// The compression level of deflate (1-12) or zstd (1-22), needs --compress unless --cog is set
+    #[arg(long)]
+    pub level: Option<u8>,
+
+    /// The quality of jpeg or webp compression (1-100), needs --compress
+    #[arg(long)]
+    pub quality: Option<u8>,
+
+    /// The predictor of lzw, deflate or zstd compression
+    #[arg(value_enum, long)]
+    pub predictor: Option<Predictor>,
+
+    /// Write a tiled tiff instead of strips
+    #[arg(long, default_value_t = false)]
+    pub tiled: bool,
+
+    /// The tile size of tiff output, a multiple of 16, implies --tiled, default is 512 for COG
+    #[arg(long)]
+    pub block_size: Option<u32>,
+
+    /// The photometric interpretation of tiff output
+    #[arg(value_enum, long)]
+    pub photometric: Option<Photometric>,
+
+    /// How the fourth band of tiff output is interpreted as alpha
+    #[arg(value_enum, long)]
+    pub alpha: Option<Alpha>,
+
+    /// Whether to write a BigTIFF, default is if-safer for COG
+    #[arg(value_enum, long)]
+    pub bigtiff: Option<BigTiff>,
+
+    /// The resampling method used to build the internal overviews of COG, default is average
+    #[arg(value_enum, long)]
+    pub overview_resampling: Option<Resampling>,
+
+    /// A GDAL creation option as KEY=VALUE passed to the GTiff or COG driver, overrides the options above, can be repeated
+    #[arg(long = "co", value_parser = parse_creation_option)]
+    pub creation_options: Vec<(String, String)>,
+
     /// The gamma value, default is 1.0, range is [0.1, 10.0]
     #[arg(short, long, value_parser = gamma_value_parser, default_value_t = 1.0)]
     pub gamma: f32,
@@ -170,42 +565,140 @@ pub struct ArgParse{
     /// The colorize strength, default is 100, range is [-100, 100]
     #[arg(long, value_parser = colorize_strength_parse,  default_value_t = 100)]
     pub colorize_strength: u8,
+
+    /// An adjustment applied after the ones above, repeat to build an ordered chain. Format is
+    /// NAME[:KEY=VALUE;...], e.g. levels:black=10;white=240, curves:points=[[0,0],[128,150],[255,255]],
+    /// saturation:saturation=30, colorize:color=[200,120,40];strength=60
+    #[arg(long, value_parser = adjust_step_parser)]
+    pub adjust: Vec<AdjustStep>,
 }
 
 
 impl ArgParseProcess for ArgParse{
 
-    type Error = anyhow::Error;
-    fn output_folder(&self) -> Result<String> {
+    type Error = BlendError;
+    fn output_target(&self) -> Result<OutputTarget> {
         let output = match self.output {
 
             Some(ref output) => {
                 if output.is_empty(){
-                    return Err(anyhow!("Output folder is empty"));
+                    return Err(BlendError::InvalidParameter("Output path is empty".to_string()));
                 }
 
-                output.to_string()
+                OutputTarget::parse(output)
             },
             None => {
                 let current_dir =  env::current_dir()?;
-                current_dir.to_str().unwrap().to_string()
+                OutputTarget::Folder(current_dir.to_str().unwrap().to_string())
             }
         };
     
         Ok(output)
     }
 
+    fn output_template(&self) -> &str {
+        match (&self.output_template, &self.command) {
+            (Some(template), _) => template,
+            (None, Some(Command::Batch(_))) => DEFAULT_BATCH_TEMPLATE,
+            (None, _) => DEFAULT_TEMPLATE,
+        }
+    }
+
+    fn exists_policy(&self) -> ExistsPolicy {
+        if self.skip_existing {
+            ExistsPolicy::Skip
+        } else if self.fail_if_exists {
+            ExistsPolicy::Fail
+        } else {
+            ExistsPolicy::Overwrite
+        }
+    }
+
+    /// 命令行中不是默认值的增强参数覆盖配方中的, --adjust 的各步接在配方的调整链之后
+    fn adjustments(&self, recipe: &Recipe) -> Result<Adjustments> {
+        let defaults = Adjustments::default();
+        let mut adjustments = recipe.adjustments.clone().unwrap_or_default();
+        if self.brightness != defaults.brightness {
+            adjustments.brightness = self.brightness;
+        }
+        if self.contrast != defaults.contrast {
+            adjustments.contrast = self.contrast;
+        }
+        if self.gamma != defaults.gamma {
+            adjustments.gamma = self.gamma;
+        }
+        if self.saturation != defaults.saturation {
+            adjustments.saturation = self.saturation;
+        }
+        if self.colorize_strength != defaults.colorize_strength {
+            adjustments.colorize_strength = self.colorize_strength;
+        }
+        adjustments.colorize |= self.colorize;
+        if let Some(color) = self.parse_color()? {
+            adjustments.colorize_color = Some([color[0], color[1], color[2]]);
+        }
+        adjustments.adjust.extend(self.adjust.iter().cloned());
+        Ok(adjustments)
+    }
+
+    fn recipe(&self) -> Result<Recipe> {
+        match &self.recipe {
+            Some(recipe) => Ok(Recipe::load(recipe)?),
+            None => Ok(Recipe::default()),
+        }
+    }
+
+    /// 命令行中给出的选项覆盖配方中的
+    fn tiff_options(&self, recipe: &Recipe) -> Result<TiffOptions> {
+        let mut options = recipe.tiff.clone().unwrap_or_default();
+        options.cog |= self.cog;
+        options.tiled |= self.tiled;
+        options.compress = self.compress.or(options.compress);
+        options.level = self.level.or(options.level);
+        options.quality = self.quality.or(options.quality);
+        options.predictor = self.predictor.or(options.predictor);
+        options.block_size = self.block_size.or(options.block_size);
+        options.photometric = self.photometric.or(options.photometric);
+        options.alpha = self.alpha.or(options.alpha);
+        options.bigtiff = self.bigtiff.or(options.bigtiff);
+        options.overview_resampling = self.overview_resampling.unwrap_or(options.overview_resampling);
+        options.co.extend(self.creation_options.iter().cloned());
+        options.validate()?;
+        Ok(options)
+    }
+
+    /// 指定了 --bands / --bands2 时按波段映射读取
+    fn layers(&self, image: &str, image2: &str) -> (LayerSource, LayerSource) {
+        let layer = |path: &str, bands: &Option<BandMapping>, stretch: &Option<Stretch>| match bands {
+            Some(mapping) => {
+                let stretch = stretch.unwrap_or(mapping.stretch);
+                LayerSource::Bands(path.to_string(), mapping.clone().with_stretch(stretch))
+            }
+            None => LayerSource::from(path),
+        };
+        (layer(image, &self.bands, &self.stretch), layer(image2, &self.bands2, &self.stretch2))
+    }
+
+    fn output_spec(&self, recipe: &Recipe) -> Result<OutputSpec> {
+        Ok(OutputSpec::new(self.output_target()?)
+            .with_format(self.format.clone())
+            .with_template(self.output_template())
+            .with_exists_policy(self.exists_policy())
+            .with_tiff(self.tiff_options(recipe)?))
+    }
+
     fn parse_color(&self) -> Result<Option<Vec<u8>>> {
 
         if let Some(colorize_color) = &self.colorize_color{
             if colorize_color.starts_with('#'){
 
                 if colorize_color.len() != 7{
-                    return Err(anyhow!("colorize color must be #RRGGBB"));
+                    return Err(BlendError::InvalidParameter("colorize color must be #RRGGBB".to_string()));
                 } 
                 // 解析十六进制颜色
                 let parse_hex = |s: &str| -> Result<u8> {
-                    u8::from_str_radix(s, 16).map_err(|_| anyhow!("Invalid hex value"))
+                    u8::from_str_radix(s, 16)
+                        .map_err(|_| BlendError::InvalidParameter(format!("Invalid hex value: {}", s)))
                 };
         
                 let r = parse_hex(&colorize_color[1..3])?;
@@ -217,14 +710,14 @@ impl ArgParseProcess for ArgParse{
                 // 解析逗号分隔的RGB值
                 let str_parts: Vec<&str> = colorize_color.split(",").map(str::trim).collect();
                 if str_parts.len() != 3 {
-                    return Err(anyhow!("Invalid color format: {}. Expected 'R,G,B' or '#RRGGBB'", colorize_color));
+                    return Err(BlendError::InvalidParameter(format!("Invalid color format: {}. Expected 'R,G,B' or '#RRGGBB'", colorize_color)));
                 }
         
                 let mut rgb = Vec::with_capacity(3);
                 for part in str_parts {
                     match part.parse::<u8>() {
                         Ok(value) => rgb.push(value),
-                        Err(_) => return Err(anyhow!("Invalid color component: {}", part)),
+                        Err(_) => return Err(BlendError::InvalidParameter(format!("Invalid color component: {}", part))),
                     }
                 }
         
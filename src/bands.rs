@@ -0,0 +1,184 @@
+use std::str::FromStr;
+
+use gdal::Dataset;
+use serde::{Deserialize, Serialize};
+
+use crate::blend::BlendImage;
+use crate::error::{BlendError, Result};
+
+/// 波段拉伸到 0-255 的方式, 每个波段分别统计, 不计 nodata
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Stretch {
+    /// 不拉伸, 原值截断到 0-255
+    None,
+    /// 最小值到最大值
+    MinMax,
+    /// 累计百分比裁剪, 如 (2, 98)
+    Percentile(f64, f64),
+    /// 均值加减 n 倍标准差
+    StdDev(f64),
+}
+
+impl Default for Stretch {
+    fn default() -> Self {
+        Self::Percentile(2.0, 98.0)
+    }
+}
+
+impl FromStr for Stretch {
+    type Err = String;
+
+    /// none, minmax, percentile[:low,high], stddev[:n]
+    fn from_str(s: &str) -> Result<Self, String> {
+        let (name, params) = match s.split_once(':') {
+            Some((name, params)) => (name, Some(params)),
+            None => (s, None),
+        };
+        let numbers = |params: &str| {
+            params
+                .split(',')
+                .map(|v| v.trim().parse::<f64>().map_err(|e| format!("invalid number '{}': {}", v, e)))
+                .collect::<Result<Vec<_>, _>>()
+        };
+        match (name.to_lowercase().as_str(), params) {
+            ("none", None) => Ok(Self::None),
+            ("minmax", None) => Ok(Self::MinMax),
+            ("percentile", None) => Ok(Self::default()),
+            ("percentile", Some(params)) => match numbers(params)?[..] {
+                [low, high] if 0.0 <= low && low < high && high <= 100.0 => Ok(Self::Percentile(low, high)),
+                _ => Err(format!("percentile needs LOW,HIGH within 0-100, got '{}'", params)),
+            },
+            ("stddev", None) => Ok(Self::StdDev(2.0)),
+            ("stddev", Some(params)) => match numbers(params)?[..] {
+                [n] if n > 0.0 => Ok(Self::StdDev(n)),
+                _ => Err(format!("stddev needs a positive number, got '{}'", params)),
+            },
+            _ => Err(format!("unknown stretch '{}', expected none, minmax, percentile[:LOW,HIGH] or stddev[:N]", s)),
+        }
+    }
+}
+
+impl Stretch {
+    /// 由有效值统计拉伸的范围
+    pub(crate) fn range(&self, values: &[f64]) -> (f64, f64) {
+        if values.is_empty() {
+            return (0.0, 255.0);
+        }
+        match self {
+            Self::None => (0.0, 255.0),
+            Self::MinMax => values
+                .iter()
+                .fold((f64::MAX, f64::MIN), |(min, max), &v| (min.min(v), max.max(v))),
+            Self::Percentile(low, high) => {
+                let mut sorted = values.to_vec();
+                sorted.sort_by(f64::total_cmp);
+                let at = |p: f64| sorted[((p / 100.0) * (sorted.len() - 1) as f64).round() as usize];
+                (at(*low), at(*high))
+            }
+            Self::StdDev(n) => {
+                let count = values.len() as f64;
+                let mean = values.iter().sum::<f64>() / count;
+                let std_dev = (values.iter().map(|v| (v - mean).powi(2)).sum::<f64>() / count).sqrt();
+                (mean - n * std_dev, mean + n * std_dev)
+            }
+        }
+    }
+}
+
+/// 多波段影像到 RGBA 的映射, 如 Sentinel-2 的假彩色 8,4,3
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct BandMapping {
+    /// R G B 对应的波段号, 从 1 开始
+    pub rgb: [isize; 3],
+    /// 透明度波段, 原值截断到 0-255, 不拉伸
+    pub alpha: Option<isize>,
+    #[serde(default)]
+    pub stretch: Stretch,
+}
+
+impl FromStr for BandMapping {
+    type Err = String;
+
+    /// R,G,B 或 R,G,B,A
+    fn from_str(s: &str) -> Result<Self, String> {
+        let bands = s
+            .split(',')
+            .map(|v| match v.trim().parse::<isize>() {
+                Ok(band) if band >= 1 => Ok(band),
+                _ => Err(format!("invalid band number '{}', bands start at 1", v)),
+            })
+            .collect::<Result<Vec<_>, _>>()?;
+        match bands[..] {
+            [r, g, b] => Ok(Self { rgb: [r, g, b], alpha: None, stretch: Stretch::default() }),
+            [r, g, b, a] => Ok(Self { rgb: [r, g, b], alpha: Some(a), stretch: Stretch::default() }),
+            _ => Err(format!("expected R,G,B or R,G,B,A band numbers, got '{}'", s)),
+        }
+    }
+}
+
+impl BandMapping {
+    pub fn with_stretch(mut self, stretch: Stretch) -> Self {
+        self.stretch = stretch;
+        self
+    }
+
+    /// 按映射读取影像, 任一波段为 nodata 的像素透明
+    pub fn read(&self, path: &str) -> Result<BlendImage> {
+        let dataset = Dataset::open(path)?;
+        let (width, height) = dataset.raster_size();
+        let count = dataset.raster_count();
+        for band in self.rgb.iter().chain(&self.alpha) {
+            if *band > count {
+                return Err(BlendError::InvalidParameter(format!("{} has {} bands, band {} does not exist", path, count, band)));
+            }
+        }
+
+        let read_band = |index: isize| -> Result<(Vec<f64>, Option<f64>)> {
+            let band = dataset.rasterband(index)?;
+            let data = band.read_as::<f64>((0, 0), (width, height), (width, height), None)?.data;
+            Ok((data, band.no_data_value()))
+        };
+        let is_nodata = |value: f64, nodata: Option<f64>| value.is_nan() || Some(value) == nodata;
+
+        let mut raw_pixels = vec![255u8; width * height * 4];
+        for (channel, index) in self.rgb.iter().enumerate() {
+            let (data, nodata) = read_band(*index)?;
+            let valid: Vec<f64> = data.iter().copied().filter(|v| !is_nodata(*v, nodata)).collect();
+            let (min, max) = self.stretch.range(&valid);
+            for (pixel, &value) in raw_pixels.chunks_exact_mut(4).zip(&data) {
+                if is_nodata(value, nodata) {
+                    pixel[3] = 0;
+                    continue;
+                }
+                let unit = if max > min { (value - min) / (max - min) } else { 0.0 };
+                pixel[channel] = (unit.clamp(0.0, 1.0) * 255.0).round() as u8;
+            }
+        }
+        if let Some(index) = self.alpha {
+            let (data, _) = read_band(index)?;
+            for (pixel, &value) in raw_pixels.chunks_exact_mut(4).zip(&data) {
+                pixel[3] = pixel[3].min(value.clamp(0.0, 255.0) as u8);
+            }
+        }
+
+        BlendImage::from_raw(width as u32, height as u32, raw_pixels)
+    }
+
+    /// 按缩小到不超过 max_size 的影像统计 R G B 波段的拉伸范围, 用于分块读取时各块使用相同的拉伸
+    pub fn sample_ranges(&self, dataset: &Dataset, max_size: usize) -> Result<Vec<(f64, f64)>> {
+        let (width, height) = dataset.raster_size();
+        let scale = (max_size as f64 / width.max(height).max(1) as f64).min(1.0);
+        let size = (((width as f64 * scale) as usize).max(1), ((height as f64 * scale) as usize).max(1));
+        self.rgb
+            .iter()
+            .map(|index| {
+                let band = dataset.rasterband(*index)?;
+                let nodata = band.no_data_value();
+                let data = band.read_as::<f64>((0, 0), (width, height), size, None)?.data;
+                let valid: Vec<f64> = data.into_iter().filter(|v| !v.is_nan() && Some(*v) != nodata).collect();
+                Ok(self.stretch.range(&valid))
+            })
+            .collect()
+    }
+}
@@ -0,0 +1,299 @@
+use std::{collections::HashMap, fs::File, path::Path};
+
+use gdal::{spatial_ref::SpatialRef, Dataset};
+use regex::Regex;
+
+use crate::argparse::{BatchArgs, PairBy};
+use crate::blend::BlendImagePair;
+use crate::error::{BlendError, Result};
+
+/// 读取批量混合的 manifest, 支持 csv(带表头) 和 json 数组
+///
+/// 每一行包含 base, overlay, 以及可选的 mode, output.
+/// 相对路径以 manifest 所在文件夹为基准.
+pub fn load_manifest(manifest: &str) -> Result<Vec<BlendImagePair>> {
+    let manifest_path = Path::new(manifest);
+    let extension = manifest_path
+        .extension()
+        .and_then(|ext| ext.to_str())
+        .map(|ext| ext.to_lowercase());
+
+    let mut pairs: Vec<BlendImagePair> = match extension.as_deref() {
+        Some("json") => serde_json::from_reader(File::open(manifest_path)?).map_err(|e| invalid_manifest(manifest, e))?,
+        Some("csv") => {
+            let mut reader = csv::ReaderBuilder::new()
+                .trim(csv::Trim::All)
+                .from_reader(File::open(manifest_path)?);
+            reader
+                .deserialize()
+                .collect::<Result<Vec<BlendImagePair>, csv::Error>>()
+                .map_err(|e| invalid_manifest(manifest, e))?
+        }
+        _ => {
+            return Err(BlendError::InvalidParameter(format!(
+                "Unsupported manifest {}, expected .csv or .json",
+                manifest
+            )))
+        }
+    };
+
+    let manifest_dir = manifest_path.parent().unwrap_or(Path::new(""));
+    for pair in pairs.iter_mut() {
+        pair.image = resolve_path(manifest_dir, &pair.image);
+        pair.image2 = resolve_path(manifest_dir, &pair.image2);
+        pair.output = pair.output.as_ref().map(|output| resolve_path(manifest_dir, output));
+    }
+
+    Ok(pairs)
+}
+
+/// 按 --pair-by 指定的策略配对两个文件夹中匹配 pattern 的图像
+pub fn pair_directories(base_dir: &str, overlay_dir: &str, batch: &BatchArgs) -> Result<Vec<BlendImagePair>> {
+    let bases = list_files(base_dir, &batch.pattern)?;
+    let overlays = list_files(overlay_dir, &batch.pattern)?;
+
+    let matched = match batch.pair_by {
+        PairBy::Stem => pair_by_key(bases, &overlays, |file| file_stem(&file.path), |file| file_stem(&file.path))?,
+        PairBy::Path => pair_by_key(bases, &overlays, |file| Some(file.key_path()), |file| Some(file.key_path()))?,
+        PairBy::Regex => {
+            let base_regex = batch
+                .base_regex
+                .as_deref()
+                .ok_or_else(|| BlendError::InvalidParameter("--pair-by regex needs --base-regex".to_string()))?;
+            let base_regex = parse_regex(base_regex)?;
+            let overlay_regex = match &batch.overlay_regex {
+                Some(overlay_regex) => parse_regex(overlay_regex)?,
+                None => base_regex.clone(),
+            };
+            pair_by_key(
+                bases,
+                &overlays,
+                |file| regex_key(&base_regex, &file.relative),
+                |file| regex_key(&overlay_regex, &file.relative),
+            )?
+        }
+        PairBy::Overlap => pair_by_overlap(bases, &overlays, batch.min_overlap),
+    };
+
+    let mut pairs = Vec::new();
+    for (base, overlay) in matched {
+        match overlay {
+            Some(overlay) => pairs.push(BlendImagePair {
+                relative_dir: base.relative_dir(),
+                image: base.path,
+                image2: overlay,
+                blend_mode: None,
+                output: None,
+            }),
+            None => eprintln!("warning: no image in {} matches {}", overlay_dir, base.path),
+        }
+    }
+
+    if pairs.is_empty() {
+        return Err(BlendError::InvalidParameter(format!(
+            "No image pairs found in {} and {} with pattern {}",
+            base_dir, overlay_dir, batch.pattern
+        )));
+    }
+    Ok(pairs)
+}
+
+/// 文件夹中的一个图像, relative 为相对于该文件夹的路径, 以 / 分隔
+struct ImageFile {
+    path: String,
+    relative: String,
+}
+
+impl ImageFile {
+    /// 不含扩展名的相对路径, 用于按路径配对
+    fn key_path(&self) -> String {
+        match self.relative.rsplit_once('.') {
+            Some((name, ext)) if !ext.contains('/') => name.to_string(),
+            _ => self.relative.clone(),
+        }
+    }
+
+    fn relative_dir(&self) -> Option<String> {
+        self.relative
+            .rsplit_once('/')
+            .map(|(dir, _)| dir.to_string())
+    }
+}
+
+/// 上层图像的 key 重复时无法确定配对, 返回错误
+fn pair_by_key<F, G>(bases: Vec<ImageFile>, overlays: &[ImageFile], base_key: F, overlay_key: G) -> Result<Vec<(ImageFile, Option<String>)>>
+where
+    F: Fn(&ImageFile) -> Option<String>,
+    G: Fn(&ImageFile) -> Option<String>,
+{
+    let mut keys: HashMap<String, &str> = HashMap::new();
+    for file in overlays {
+        let Some(key) = overlay_key(file) else { continue };
+        if let Some(other) = keys.insert(key.clone(), file.path.as_str()) {
+            return Err(BlendError::InvalidParameter(format!(
+                "{} and {} have the same pairing key {:?}, use --pair-by path or a narrower --pattern",
+                other, file.path, key
+            )));
+        }
+    }
+
+    Ok(bases
+        .into_iter()
+        .map(|base| {
+            let overlay = base_key(&base)
+                .and_then(|key| keys.get(&key))
+                .map(|overlay| overlay.to_string());
+            (base, overlay)
+        })
+        .collect())
+}
+
+fn parse_regex(regex: &str) -> Result<Regex> {
+    Regex::new(regex).map_err(|e| BlendError::InvalidParameter(format!("Invalid regex {}: {}", regex, e)))
+}
+
+/// 以所有捕获组拼接作为配对的 key, 不匹配时返回 None
+fn regex_key(regex: &Regex, relative: &str) -> Option<String> {
+    let captures = regex.captures(relative)?;
+    let groups: Vec<&str> = captures
+        .iter()
+        .skip(1)
+        .map(|group| group.map(|m| m.as_str()).unwrap_or(""))
+        .collect();
+    Some(groups.join("/"))
+}
+
+/// 影像的地理范围, 由 geotransform 和栅格大小计算
+struct Footprint {
+    min_x: f64,
+    min_y: f64,
+    max_x: f64,
+    max_y: f64,
+    srs: Option<SpatialRef>,
+}
+
+impl Footprint {
+    fn open(path: &str) -> Result<Footprint> {
+        let dataset = Dataset::open(path)?;
+        let transform = dataset.geo_transform()?;
+        let (width, height) = dataset.raster_size();
+        let (width, height) = (width as f64, height as f64);
+
+        let corners = [(0.0, 0.0), (width, 0.0), (0.0, height), (width, height)]
+            .map(|(px, py)| {
+                (
+                    transform[0] + px * transform[1] + py * transform[2],
+                    transform[3] + px * transform[4] + py * transform[5],
+                )
+            });
+
+        Ok(Footprint {
+            min_x: corners.iter().map(|c| c.0).fold(f64::INFINITY, f64::min),
+            min_y: corners.iter().map(|c| c.1).fold(f64::INFINITY, f64::min),
+            max_x: corners.iter().map(|c| c.0).fold(f64::NEG_INFINITY, f64::max),
+            max_y: corners.iter().map(|c| c.1).fold(f64::NEG_INFINITY, f64::max),
+            srs: dataset.spatial_ref().ok(),
+        })
+    }
+
+    fn area(&self) -> f64 {
+        (self.max_x - self.min_x) * (self.max_y - self.min_y)
+    }
+
+    /// 与 other 重叠部分占自身面积的比例, 坐标系不同时为 0
+    fn overlap(&self, other: &Footprint) -> f64 {
+        if let (Some(srs), Some(other_srs)) = (&self.srs, &other.srs) {
+            if srs != other_srs {
+                return 0.0;
+            }
+        }
+        let width = self.max_x.min(other.max_x) - self.min_x.max(other.min_x);
+        let height = self.max_y.min(other.max_y) - self.min_y.max(other.min_y);
+        if width <= 0.0 || height <= 0.0 || self.area() <= 0.0 {
+            return 0.0;
+        }
+        width * height / self.area()
+    }
+}
+
+/// 每个底图与重叠比例最大且不小于 min_overlap 的上层图像配对
+fn pair_by_overlap(bases: Vec<ImageFile>, overlays: &[ImageFile], min_overlap: f64) -> Vec<(ImageFile, Option<String>)> {
+    let overlays: Vec<(&str, Footprint)> = overlays
+        .iter()
+        .filter_map(|file| match Footprint::open(&file.path) {
+            Ok(footprint) => Some((file.path.as_str(), footprint)),
+            Err(e) => {
+                eprintln!("warning: skip {}, no georeferencing: {}", file.path, e);
+                None
+            }
+        })
+        .collect();
+
+    bases
+        .into_iter()
+        .map(|base| {
+            let footprint = match Footprint::open(&base.path) {
+                Ok(footprint) => footprint,
+                Err(e) => {
+                    eprintln!("warning: skip {}, no georeferencing: {}", base.path, e);
+                    return (base, None);
+                }
+            };
+            let overlay = overlays
+                .iter()
+                .map(|(path, other)| (*path, footprint.overlap(other)))
+                .filter(|(_, overlap)| *overlap >= min_overlap && *overlap > 0.0)
+                .max_by(|a, b| a.1.total_cmp(&b.1))
+                .map(|(path, _)| path.to_string());
+            (base, overlay)
+        })
+        .collect()
+}
+
+/// 只保留扩展名为图像格式的文件, 跳过同一文件夹中的 .pgw, .aux.xml 等附属文件
+fn list_files(dir: &str, pattern: &str) -> Result<Vec<ImageFile>> {
+    let pattern = Path::new(dir).join(pattern);
+    let pattern = pattern
+        .to_str()
+        .ok_or_else(|| BlendError::InvalidParameter(format!("Invalid pattern: {:?}", pattern)))?;
+
+    let mut files = Vec::new();
+    let entries = glob::glob(pattern).map_err(|e| BlendError::InvalidParameter(format!("Invalid pattern {}: {}", pattern, e)))?;
+    for entry in entries {
+        let path = entry.map_err(|e| BlendError::Io(e.into()))?;
+        if path.is_file() && image::ImageFormat::from_path(&path).is_ok() {
+            let relative = path
+                .strip_prefix(dir)
+                .unwrap_or(&path)
+                .components()
+                .map(|c| c.as_os_str().to_string_lossy())
+                .collect::<Vec<_>>()
+                .join("/");
+            files.push(ImageFile {
+                path: path.to_string_lossy().into_owned(),
+                relative,
+            });
+        }
+    }
+    files.sort_by(|a, b| a.path.cmp(&b.path));
+    Ok(files)
+}
+
+fn invalid_manifest(manifest: &str, e: impl std::fmt::Display) -> BlendError {
+    BlendError::InvalidParameter(format!("Invalid manifest {}: {}", manifest, e))
+}
+
+fn file_stem(path: &str) -> Option<String> {
+    Path::new(path)
+        .file_stem()
+        .and_then(|stem| stem.to_str())
+        .map(|stem| stem.to_string())
+}
+
+fn resolve_path(base_dir: &Path, path: &str) -> String {
+    if Path::new(path).is_absolute() {
+        path.to_string()
+    } else {
+        base_dir.join(path).to_string_lossy().into_owned()
+    }
+}
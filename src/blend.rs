@@ -1,16 +1,17 @@
+use std::io::{Cursor, Read};
 use std::path::Path;
-use ndarray::Array1;
-use anyhow::{anyhow, Result};
+use std::sync::atomic::{AtomicUsize, Ordering};
 use gdal::errors::GdalError;
-use gdal::raster::{Buffer, GdalDataType, RasterCreationOption};
+use gdal::raster::{Buffer, ColorInterpretation, GdalDataType};
 use gdal::{Dataset, Metadata};
-use image::{DynamicImage, GenericImageView, ImageBuffer, Pixel};
-use palette::{blend::{Blend, Compose}, LinSrgba};
+use image::{DynamicImage, ImageBuffer, Rgba};
 use serde::{Deserialize, Serialize};
-use crate::{adjuster::{BrightnessGammaContrastAdjuster, HueSaturationAdjuster}, argparse::ArgParseProcess};
-use crate::{argparse::ArgParse, core::OUTPUT_FOLDER};
 use rayon::prelude::*;
 use crate::argparse::Format;
+use crate::blend_op::blend_op;
+use crate::error::{BlendError, Result};
+use crate::geotiff::{self, TiffOptions};
+use crate::pipeline::{Adjustments, BlendJob, JobOutcome};
 
 pub struct ImageIterator {
     width: u32,
@@ -66,38 +67,142 @@ impl BlendImage{
         self.height
     }
 
+    /// RGBA 像素, 每个像素 4 个字节
+    pub fn raw_pixels(&self) -> &[u8]{
+        &self.raw_pixels
+    }
+
+    pub fn into_rgba_image(self) -> Result<ImageBuffer<Rgba<u8>, Vec<u8>>>{
+        let (width, height) = (self.width, self.height);
+        ImageBuffer::from_vec(width, height, self.raw_pixels)
+            .ok_or_else(|| BlendError::InvalidParameter(format!("The pixel buffer does not match a {}x{} image", width, height)))
+    }
+}
+
+impl From<DynamicImage> for BlendImage{
+    fn from(img: DynamicImage) -> Self{
+        Self::from(img.to_rgba8())
+    }
+}
+
+impl From<ImageBuffer<Rgba<u8>, Vec<u8>>> for BlendImage{
+    fn from(img: ImageBuffer<Rgba<u8>, Vec<u8>>) -> Self{
+        let (width, height) = img.dimensions();
+        BlendImage{
+            raw_pixels: img.into_raw(),
+            width,
+            height,
+        }
+    }
 }
 
 
 impl BlendImage{
 
+    /// 打开图像, `-` 表示从标准输入读取
     pub fn open_image(img_path: &str) -> Result<BlendImage>{
+        if img_path == "-" {
+            return Self::from_reader(std::io::stdin().lock());
+        }
         let img = image::open(img_path)?;
-        let (width, height) = img.dimensions();
+        Ok(Self::from(img))
+    }
+
+    /// 从编码后的字节解码, 格式由文件头推断
+    pub fn from_bytes(bytes: &[u8]) -> Result<BlendImage>{
+        let img = image::load_from_memory(bytes)?;
+        Ok(Self::from(img))
+    }
+
+    pub fn from_reader<R: Read>(mut reader: R) -> Result<BlendImage>{
+        let mut bytes = Vec::new();
+        reader.read_to_end(&mut bytes)?;
+        Self::from_bytes(&bytes)
+    }
+
+    /// 从 RGBA 像素构建, raw_pixels 的长度必须为 width * height * 4
+    pub fn from_raw(width: u32, height: u32, raw_pixels: Vec<u8>) -> Result<BlendImage>{
+        if raw_pixels.len() != width as usize * height as usize * 4 {
+            return Err(BlendError::InvalidParameter(format!(
+                "{} bytes do not match a {}x{} RGBA image",
+                raw_pixels.len(), width, height
+            )));
+        }
         Ok(BlendImage{
-            raw_pixels: img.to_rgba8().to_vec(),
+            raw_pixels,
             width,
             height,
         })
     }
 
-    pub fn save_image(img: BlendImage, output_path: &str) -> Result<()> {
+    pub fn save_image(img: BlendImage, output_path: &str, format: &Format) -> Result<()> {
+        let dynimage = Self::into_dynamic_image(img, format)?;
+        dynimage.save_with_format(output_path, format.image_format())
+            .map_err(Self::encode_error)?;
+        Ok(())
+    }
+
+    /// 编码为 png/webp/jpeg 字节流
+    pub fn encode_image(img: BlendImage, format: &Format) -> Result<Vec<u8>> {
+        let dynimage = Self::into_dynamic_image(img, format)?;
+        let mut bytes = Cursor::new(Vec::new());
+        dynimage.write_to(&mut bytes, format.image_format())
+            .map_err(Self::encode_error)?;
+        Ok(bytes.into_inner())
+    }
 
-        let raw_pixels = img.raw_pixels;
-        let width = img.width;
-        let height = img.height;
+    /// 编码为 GeoTIFF 字节流, 先写入 GDAL 内存文件再读出
+    pub fn encode_tiff(img: BlendImage, georef_image: Option<&str>) -> Result<Vec<u8>> {
+        Self::encode_tiff_with(img, georef_image, &TiffOptions::default())
+    }
 
-        let img_buffer = ImageBuffer::from_vec(width, height, raw_pixels).unwrap();
-        let dynimage = DynamicImage::ImageRgba8(img_buffer);
+    /// 按创建选项编码为 GeoTIFF 或 COG 字节流
+    pub fn encode_tiff_with(img: BlendImage, georef_image: Option<&str>, options: &TiffOptions) -> Result<Vec<u8>> {
+        options.encode_with(|path, options| Self::write_tiff(img, path, georef_image, options))
+    }
 
-        dynimage.save(output_path)?;
-        Ok(())
+    /// 不重复的 GDAL 内存文件路径
+    pub(crate) fn mem_path() -> String {
+        static MEM_FILE_INDEX: AtomicUsize = AtomicUsize::new(0);
+        format!(
+            "/vsimem/blend_{}_{}.tif",
+            std::process::id(),
+            MEM_FILE_INDEX.fetch_add(1, Ordering::Relaxed)
+        )
+    }
+
+    /// 保存和编码时 image 的非 I/O 错误都是编码错误
+    fn encode_error(err: image::ImageError) -> BlendError {
+        match BlendError::from(err) {
+            BlendError::Decode(err) => BlendError::Encode(err),
+            err => err,
+        }
+    }
+
+    fn into_dynamic_image(img: BlendImage, format: &Format) -> Result<DynamicImage> {
+        let dynimage = DynamicImage::ImageRgba8(img.into_rgba_image()?);
+
+        // jpeg 不支持透明通道
+        if *format == Format::JPEG {
+            Ok(DynamicImage::ImageRgb8(dynimage.to_rgb8()))
+        } else {
+            Ok(dynimage)
+        }
     }
 
-    pub fn save_tiff(image: BlendImage, output_path: &str, options: &ArgParse) -> Result<()>{
-        let tiff_image = &options.image;
+    /// 保存为 GeoTIFF, 地理参考信息取自 georef_image
+    pub fn save_tiff(image: BlendImage, output_path: &str, georef_image: Option<&str>) -> Result<()>{
+        Self::save_tiff_with(image, output_path, georef_image, &TiffOptions::default())
+    }
+
+    /// 按创建选项保存为 GeoTIFF 或 COG
+    pub fn save_tiff_with(image: BlendImage, output_path: &str, georef_image: Option<&str>, options: &TiffOptions) -> Result<()>{
+        options.save_with(output_path, |path, options| Self::write_tiff(image, path, georef_image, options))
+    }
+
+    fn write_tiff(image: BlendImage, output_path: &str, georef_image: Option<&str>, options: &TiffOptions) -> Result<()>{
         let (width, height) = (image.get_width(), image.get_height());
-        let image_buffer: ImageBuffer<image::Rgba<u8>, Vec<u8>> = ImageBuffer::from_vec(width, height, image.raw_pixels).unwrap();
+        let image_buffer = image.into_rgba_image()?;
 
         let mut bands: [Vec<u8>; 4] = [
             Vec::with_capacity((width * height) as usize),
@@ -112,61 +217,93 @@ impl BlendImage{
             bands[3].push(pixel[3]);
         }
 
-        let dataset = Dataset::open(tiff_image).unwrap();
-        let compress = if let Some(compress) = dataset
-            .metadata_item("COMPRESSION", "IMAGE_STRUCTURE"){
-                compress
-            } else {
-                "LZW".to_string()
-            };
-
-        let bands_num = dataset.raster_count();
-        let data_type = dataset.rasterband(1).unwrap().band_type();
-        let projection = dataset.projection();
-        let geo_transform = dataset.geo_transform().unwrap();
+        // 没有地理参考来源时(如从标准输入或内存读取的底图), 保存为 RGBA 四个波段的 tiff
+        let dataset = georef_image.map(Dataset::open).transpose()?;
+        if let Some(dataset) = &dataset {
+            let (source_width, source_height) = dataset.raster_size();
+            if (source_width as u32, source_height as u32) != (width, height) {
+                return Err(BlendError::SizeMismatch {
+                    expected: (source_width as u32, source_height as u32),
+                    actual: (width, height),
+                });
+            }
+        }
+        let source_compress = dataset
+            .as_ref()
+            .and_then(|dataset| dataset.metadata_item("COMPRESSION", "IMAGE_STRUCTURE"));
+        let creation_options = options.gtiff_options(source_compress);
+
+        // 波段类型和缩放只在来源同为四个波段时沿用, 多光谱等其他来源输出 8 位 RGBA
+        let rgba_source = dataset.as_ref().filter(|dataset| dataset.raster_count() == 4);
+        let data_type = match rgba_source {
+            Some(dataset) => dataset.rasterband(1)?.band_type(),
+            None => GdalDataType::UInt8,
+        };
 
-        let mut output_dataset = Self::create_tiff(output_path, (width as isize, height as isize), bands_num, &data_type, &compress)
-            .expect(&format!("Failed to create output TIFF file: {output_path}"));
+        let mut output_dataset = Self::create_tiff(output_path, (width as isize, height as isize), 4, &data_type, &creation_options)?;
 
         // 设置输出图像的地理参考信息
-        output_dataset.set_geo_transform(&geo_transform).unwrap();
-        output_dataset.set_projection(&projection).unwrap();
+        if let Some(dataset) = &dataset {
+            let projection = dataset.projection();
+            let geo_transform = dataset.geo_transform()
+                .map_err(|e| BlendError::Georef(format!("{} has no geotransform: {}", georef_image.unwrap_or_default(), e)))?;
+            output_dataset.set_geo_transform(&geo_transform)?;
+            output_dataset.set_projection(&projection)?;
+            Self::copy_metadata(dataset, &mut output_dataset)?;
+        }
 
         for (i, band_data) in bands.iter().enumerate() {
-            let input_band = dataset.rasterband(i as isize + 1).unwrap();
+            let mut output_band = output_dataset.rasterband(i as isize + 1)?;
 
-            let mut output_band = output_dataset.rasterband(i as isize + 1).unwrap();
-            
-            if let Some(scale) = input_band.scale(){
-                output_band.set_scale(scale).unwrap();
-            }
-            if let Some(offset) = input_band.offset(){
-                output_band.set_offset(offset).unwrap();
+            if let Some(dataset) = rgba_source {
+                let input_band = dataset.rasterband(i as isize + 1)?;
+                if let Some(scale) = input_band.scale(){
+                    output_band.set_scale(scale)?;
+                }
+                if let Some(offset) = input_band.offset(){
+                    output_band.set_offset(offset)?;
+                }
+                if input_band.color_interpretation() != ColorInterpretation::Undefined {
+                    output_band.set_color_interpretation(input_band.color_interpretation())?;
+                }
+                if let Ok(description) = input_band.description() {
+                    if !description.is_empty() {
+                        output_band.set_description(&description)?;
+                    }
+                }
             }
 
             // write_tiff(&data_type, &mut output_band,band_data.to_vec(), (0,0), (width, height));
             let buffer = Buffer::<u8>::new((width as usize, height as usize), band_data.to_vec());
-            output_band.write((0, 0), (width as usize, height as usize), &buffer).unwrap();
+            output_band.write((0, 0), (width as usize, height as usize), &buffer)?;
         }
 
-        output_dataset.close().unwrap();
-        dataset.close().unwrap();
+        output_dataset.close()?;
+        if let Some(dataset) = dataset {
+            dataset.close()?;
+        }
 
     Ok(())
     }
 
-    fn create_tiff(output_tiff: &str, size: (isize, isize), bands_num: isize, data_type: &GdalDataType, compress: &str) -> Result<Dataset, GdalError>{
+    /// 复制数据集级别的元数据, 跳过压缩等由输出决定的 IMAGE_STRUCTURE 和子数据集、xml 域
+    fn copy_metadata(source: &Dataset, output: &mut Dataset) -> Result<()> {
+        for entry in source.metadata() {
+            let skip = matches!(entry.domain.as_str(), "IMAGE_STRUCTURE" | "SUBDATASETS" | "DERIVED_SUBDATASETS")
+                || entry.domain.starts_with("xml:");
+            if !skip {
+                output.set_metadata_item(&entry.key, &entry.value, &entry.domain)?;
+            }
+        }
+        Ok(())
+    }
+
+    pub(crate) fn create_tiff(output_tiff: &str, size: (isize, isize), bands_num: isize, data_type: &GdalDataType, creation_options: &[(String, String)]) -> Result<Dataset, GdalError>{
         let (clip_width, clip_height) = size;
         // 创建输出图像的驱动程序
-        let driver = gdal::DriverManager::get_driver_by_name("GTiff")
-            .expect(&format!("Failed to create driver GTiff"));
+        let driver = gdal::DriverManager::get_driver_by_name("GTiff")?;
         
-        let options = [
-                RasterCreationOption {
-                    key: "COMPRESS",
-                    value: compress
-                }
-            ];
+        let options = geotiff::creation_options(creation_options);
         // 创建输出图像的数据集
         let output_path = Path::new(output_tiff);
         match data_type {
@@ -191,229 +328,83 @@ impl BlendImage{
             GdalDataType::Float64 => {
                 driver.create_with_band_type_with_options::<f64, &Path>(output_path, clip_width as isize, clip_height as isize, bands_num, &options)
             },
-            _ => Err(GdalError::BadArgument(format!("Failed to create output TIFF file: {output_tiff}")))
+            _ => Err(GdalError::BadArgument(format!("Unsupported data type {data_type} for output TIFF file: {output_tiff}")))
         }
     }
 
 }
 
+/// 批量混合中的一组图像, 对应 manifest 中的一行
+#[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct BlendImagePair {
+    #[serde(rename = "base", alias = "image")]
     pub image: String,
+    #[serde(rename = "overlay", alias = "image2")]
     pub image2: String,
-    pub blend_mode: String,
+    /// 为空时使用命令行的 blend mode
+    #[serde(rename = "mode", alias = "blend_mode", default)]
+    pub blend_mode: Option<String>,
+    /// 输出文件路径, 为空时保存到输出文件夹
+    #[serde(default)]
+    pub output: Option<String>,
+    /// 按文件夹配对时底图所在的子文件夹, 输出时保持相同的目录结构
+    #[serde(skip)]
+    pub relative_dir: Option<String>,
 }
 
 pub struct BlendManager;
 
 impl BlendManager{
 
-    fn dyn_image_from_raw(blend_image: &BlendImage) -> DynamicImage{
-        let _len_vec = blend_image.raw_pixels.len() as u128;
-        let raw_pixels = &blend_image.raw_pixels;
-        let img_buffer = ImageBuffer::from_vec(
-            blend_image.get_width(),
-            blend_image.get_height(),
-            raw_pixels.to_vec())
-            .unwrap();
-
-        DynamicImage::ImageRgba8(img_buffer)
+    /// 并行执行多个混合任务, 单个任务失败不影响其他任务, 结果与 jobs 一一对应
+    pub fn blend_manager_pair(jobs: &[BlendJob]) -> Vec<Result<JobOutcome>>{
+        jobs.par_iter().map(|job| job.execute()).collect()
     }
 
-    pub fn blend_manger(options: &ArgParse) -> Result<()>{
-        let mut image = BlendImage::open_image(&options.image)?;
-        let image2 = BlendImage::open_image(&options.image2)?;
-        let blend_mode = options.blend_mode.blend_name();
-
-        Self::enchance(&mut image, options)?;
-        Self::blend(&mut image, &image2, &blend_mode)?;
-        Self::image_save(image, &blend_mode, options)?;
-        Ok(())
-    }
-
-    pub fn blend_manager_pair(image_pairs: Vec<BlendImagePair>, options: &ArgParse) -> Result<()>{
-        image_pairs.par_iter().enumerate().for_each(|(index,pair)| {
-
-            let mut image = BlendImage::open_image(&pair.image).unwrap();
-            let image2 = BlendImage::open_image(&pair.image2).unwrap();
-
-            Self::enchance(&mut image, options).unwrap();
-
-            Self::blend(&mut image, &image2, &pair.blend_mode).unwrap();
-            let output_filename = format!("{}_{}", pair.blend_mode, index + 1);
-            Self::image_save(image, &output_filename, options).unwrap();
-        });
-        Ok(())
+    /// 交换底图与上层图像, 即交换 Porter-Duff 中 source 与 destination 的角色
+    pub fn swap_layers(image: BlendImage, image2: BlendImage, swap: bool) -> (BlendImage, BlendImage){
+        if swap {
+            (image2, image)
+        } else {
+            (image, image2)
+        }
     }
-    pub fn enchance(blend_image: &mut BlendImage, options: &ArgParse) -> Result<()>{
-        let dyn_image = BlendManager::dyn_image_from_raw(blend_image);
-        let mut image = dyn_image.to_rgba8();
-        let bgc_adjuster = BrightnessGammaContrastAdjuster::new(
-            options.brightness,
-            options.contrast,
-            options.gamma);
-        let colorize_color = options.parse_color()?;
-        let hs_adjuster = HueSaturationAdjuster::new(
-            options.saturation,
-            options.colorize,
-            &colorize_color,
-            options.colorize_strength
-        );
-
-        // image.pixels_mut().par_bridge().for_each(|pixel|{
-        //     bgc_adjuster.adjust_pixel(pixel);
-        //     hs_adjuster.adjust_pixel(pixel);
-        // });
-        for (_, _, pixel) in image.enumerate_pixels_mut(){
-            bgc_adjuster.adjust_pixel(pixel);
-            hs_adjuster.adjust_pixel(pixel);
 
+    pub fn enchance(blend_image: &mut BlendImage, adjustments: &Adjustments) -> Result<()>{
+        let chain = adjustments.adjuster_chain()?;
+        if chain.is_empty() || blend_image.width == 0 {
+            return Ok(());
         }
 
-        let  blended_image = DynamicImage::ImageRgba8(image);
-        blend_image.raw_pixels = blended_image.into_bytes();
-
+        let row_len = blend_image.width as usize * 4;
+        blend_image.raw_pixels
+            .par_chunks_mut(row_len)
+            .for_each(|row| chain.adjust_row(row));
         Ok(())
     }
+
     pub fn blend(blend_image: &mut BlendImage, blend_image2: &BlendImage, blend_mode: &str) -> Result<()>{
-        let dyn_image = BlendManager::dyn_image_from_raw(blend_image);
-        let dyn_image2 = BlendManager::dyn_image_from_raw(blend_image2);
+        let op = blend_op(blend_mode)?;
 
-        let (width, height) = dyn_image.dimensions();
-        let (width2, height2) = dyn_image2.dimensions();
+        let (width, height) = (blend_image.get_width(), blend_image.get_height());
+        let (width2, height2) = (blend_image2.get_width(), blend_image2.get_height());
         if width != width2 || height != height2{
-            return Err(anyhow!("the size of blend images must be the same"));
+            return Err(BlendError::SizeMismatch {
+                expected: (width, height),
+                actual: (width2, height2),
+            });
         }
-
-        let mut image = dyn_image.to_rgba8();
-        let image2 = dyn_image2.to_rgba8();
-
-        for (x, y) in ImageIterator::new(width, height) {
-            let pixel = image.get_pixel(x, y);
-            let pixel2 = image2.get_pixel(x, y);
-            let px_data = pixel.channels();
-            let px_data2 = pixel2.channels();
-            let components:(f32,f32, f32, f32);
-            if blend_mode.to_lowercase().as_str() == "softlight" {
-                let color = Array1::from_shape_fn(3, |i| px_data[i] as f32 / 255.0);
-                let color_alpha = px_data[3] as f32 / 255.0;
-
-                let color2 = Array1::from_shape_fn(3, |i| px_data2[i] as f32 / 255.0);
-                let color2_alpha = px_data2[3] as f32 / 255.0;
-
-                components = Self::softlight_op(color2, color, color2_alpha, color_alpha);
-            }else {
-                let color = LinSrgba::new(
-                            px_data[0] as f32 / 255.0,
-                            px_data[1] as f32 / 255.0,
-                            px_data[2] as f32 / 255.0,
-                            px_data[3] as f32 / 255.0,
-                        ).into_linear();
-
-
-                let color2  = LinSrgba::new(
-                    px_data2[0] as f32 / 255.0,
-                    px_data2[1] as f32 / 255.0,
-                    px_data2[2] as f32 / 255.0,
-                    px_data2[3] as f32 / 255.0,
-                ).into_linear();
-
-
-                let blended = match blend_mode.to_lowercase().as_str() {
-                    "overlay" => color.overlay(color2),
-                    "over" => color2.over(color),
-                    "atop" => color2.atop(color),
-                    "xor" => color2.xor(color),
-                    "addition" | "plus" => color2.plus(color),
-                    "multiply" => color2.multiply(color),
-                    "burn" => color.burn(color2),
-                    "difference" => color2.difference(color),
-                    "soft_light" | "soft light" | "softlight" => color2.soft_light(color),
-                    "screen" => color2.screen(color),
-                    "hard_light" | "hard light" | "hardlight" => color.hard_light(color2),
-                    "dodge" => color.dodge(color2),
-                    "subtract" | "exclusion" => color2.exclusion(color),
-                    "lighten" => color2.lighten(color),
-                    "darken" => color2.darken(color),
-                    _ => color2.overlay(color),
-                };
-
-                components = blended.into_components();
-            }
-
-            image.put_pixel(
-                x,y,image::Rgba([
-                    (components.0 * 255.0) as u8,
-                    (components.1 * 255.0) as u8,
-                    (components.2 * 255.0) as u8,
-                    px_data[3], // 以dem alpha值为准
-                ])
-            );
+        if width == 0 || height == 0 {
+            return Ok(());
         }
 
-        let  blended_image = DynamicImage::ImageRgba8(image);
-        blend_image.raw_pixels = blended_image.into_bytes();
-        Ok(())
-    }
-
-    pub fn image_save(image: BlendImage, blend_mode:&str, options: &ArgParse) -> Result<()>{
-        let output = OUTPUT_FOLDER.read().unwrap();
-        let output = if let Some(output) = output.as_ref(){
-            output
-        }else{
-            return Err(anyhow!("No output folder specified"));
-        };
-        let format = options.format.format_name();
-        // let filename = format!("blend_{}.{}", blend_mode, format);
-        let image_path = Path::new(&options.image);
-        let image_filename = image_path.file_name().map(|s| s.to_str().unwrap()).unwrap();
-        let image_filename = image_filename.rsplit_once('.').map(|(name, _)| name).unwrap_or(image_filename);
-        let filename = format!("{}.{}", image_filename, format);
-        let save_path = Path::new(&output).join(filename);
-        let save_path = save_path.to_str().unwrap();
-
-        if options.format == Format::TIFF {
-            BlendImage::save_tiff(image, save_path, options)?;
-        }else {
-
-            BlendImage::save_image(image, save_path)?;
-        }
+        // 逐行并行混合
+        let row_len = width as usize * 4;
+        blend_image.raw_pixels
+            .par_chunks_mut(row_len)
+            .zip(blend_image2.raw_pixels.par_chunks(row_len))
+            .enumerate()
+            .for_each(|(y, (row, row2))| op.blend_row(y as u32, row, row2));
         Ok(())
     }
-
-    fn softlight_op(dst: Array1<f32>, src: Array1<f32>, da: f32, sa: f32) -> (f32, f32, f32, f32) {
-        let src2 = &src * 2.0;
-        let dst_np = if da != 0.0 {
-            (&dst * 1.0) / da
-        } else {
-            Array1::zeros(dst.len())
-        };
-
-        // 计算中间值的逐元素操作
-        let temp = &src * (1.0 - da) + &dst * (1.0 - sa);
-
-        let result: Vec<f32> = src2
-            .iter()
-            .zip(dst.iter())
-            .zip(dst_np.iter())
-            .zip(temp.iter())
-            .map(|(((&src2, &dst), &dst_np), &temp)| {
-                if src2 < sa {
-                    (dst * (sa + (src2 - sa) * (1.0 - dst_np)) + temp) / 1.0
-                } else if 4.0 * dst <= da {
-                    (dst * sa + da * (src2 - sa)
-                        * (((16.0 * dst_np - 12.0) * dst_np + 3.0) * dst_np / 1.0) + temp) / 1.0
-                } else {
-                    (dst * sa + da * (src2 - sa)
-                        * ((dst_np.sqrt()) - dst_np) + temp) / 1.0
-                }
-            })
-            .map(Self::clamp) // 确保每个通道值在0到1范围内
-            .collect();
-
-        (result[0], result[1], result[2], da)
-    }
-
-    fn clamp(value: f32) -> f32 {
-        value.max(0.0).min(1.0)
-    }
 }
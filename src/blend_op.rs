@@ -0,0 +1,222 @@
+use std::{collections::HashMap, sync::{Arc, RwLock}};
+
+use lazy_static::lazy_static;
+use ndarray::Array1;
+use palette::{blend::{Blend, Compose}, LinSrgba};
+
+use crate::error::{BlendError, Result};
+use crate::expr::{ExprOp, EXPR_PREFIX, MAX_LENGTH};
+
+/// 混合结果透明度的来源
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum AlphaMode {
+    /// 保留底图的透明度, 普通混合模式以底图(如 dem)为准
+    Base,
+    /// 使用合成结果的透明度, Porter-Duff 合成会改变透明度
+    Composite,
+}
+
+/// 混合模式
+///
+/// 像素为 [0, 1] 范围内非预乘的 RGBA, dst 为底图, src 为上层图像.
+/// 实现 blend_pixel 即可, 需要像素坐标或整行处理时可以重写 blend_row.
+pub trait BlendOp: Send + Sync {
+    /// 混合模式的名称, 即 -m 和 manifest 中 mode 的取值
+    fn name(&self) -> &str;
+
+    fn alpha_mode(&self) -> AlphaMode {
+        AlphaMode::Base
+    }
+
+    fn blend_pixel(&self, dst: [f32; 4], src: [f32; 4]) -> [f32; 4];
+
+    /// 混合第 y 行, dst 与 src 为该行的 RGBA 字节, 结果写回 dst
+    fn blend_row(&self, _y: u32, dst: &mut [u8], src: &[u8]) {
+        let alpha_mode = self.alpha_mode();
+        for (dst_px, src_px) in dst.chunks_exact_mut(4).zip(src.chunks_exact(4)) {
+            let blended = self.blend_pixel(to_unit(dst_px), to_unit(src_px));
+            let alpha = match alpha_mode {
+                AlphaMode::Base => dst_px[3],
+                AlphaMode::Composite => (blended[3] * 255.0) as u8,
+            };
+            dst_px[0] = (blended[0] * 255.0) as u8;
+            dst_px[1] = (blended[1] * 255.0) as u8;
+            dst_px[2] = (blended[2] * 255.0) as u8;
+            dst_px[3] = alpha;
+        }
+    }
+}
+
+fn to_unit(px: &[u8]) -> [f32; 4] {
+    [
+        px[0] as f32 / 255.0,
+        px[1] as f32 / 255.0,
+        px[2] as f32 / 255.0,
+        px[3] as f32 / 255.0,
+    ]
+}
+
+lazy_static!(
+    static ref BLEND_OPS: RwLock<HashMap<String, Arc<dyn BlendOp>>> = RwLock::new(builtin_ops());
+);
+
+/// 注册自定义混合模式, 注册后命令行、manifest 和 recipe 都可以使用, 名称不区分大小写且不能与已有的重复
+pub fn register_blend_op<T: BlendOp + 'static>(op: T) -> Result<()> {
+    let name = op.name().to_lowercase();
+    let mut ops = BLEND_OPS.write().unwrap_or_else(|e| e.into_inner());
+    if ops.contains_key(&name) {
+        return Err(BlendError::InvalidParameter(format!("blend mode {} is already registered", name)));
+    }
+    ops.insert(name, Arc::new(op));
+    Ok(())
+}
+
+/// 按名称查找混合模式, 不区分大小写; 以 expr: 开头时解析为表达式混合模式
+pub fn blend_op(name: &str) -> Result<Arc<dyn BlendOp>> {
+    let max_length = EXPR_PREFIX.len() + MAX_LENGTH;
+    if name.len() > max_length {
+        return Err(BlendError::InvalidParameter(format!("blend mode is longer than {} bytes", max_length)));
+    }
+    if name.len() >= EXPR_PREFIX.len() && name[..EXPR_PREFIX.len()].eq_ignore_ascii_case(EXPR_PREFIX) {
+        return Ok(Arc::new(ExprOp::parse(&name[EXPR_PREFIX.len()..])?));
+    }
+
+    let ops = BLEND_OPS.read().unwrap_or_else(|e| e.into_inner());
+    ops.get(&name.to_lowercase())
+        .cloned()
+        .ok_or_else(|| BlendError::InvalidParameter(format!("unknown blend mode: {}", name)))
+}
+
+/// 所有已注册的混合模式名称, 按字母排序
+pub fn blend_op_names() -> Vec<String> {
+    let ops = BLEND_OPS.read().unwrap_or_else(|e| e.into_inner());
+    let mut names: Vec<String> = ops.keys().cloned().collect();
+    names.sort();
+    names
+}
+
+/// 基于 palette 的混合模式
+struct PaletteOp {
+    name: &'static str,
+    alpha_mode: AlphaMode,
+    op: fn(LinSrgba, LinSrgba) -> LinSrgba,
+}
+
+impl PaletteOp {
+    fn blend(name: &'static str, op: fn(LinSrgba, LinSrgba) -> LinSrgba) -> Arc<dyn BlendOp> {
+        Arc::new(Self { name, alpha_mode: AlphaMode::Base, op })
+    }
+
+    fn compose(name: &'static str, op: fn(LinSrgba, LinSrgba) -> LinSrgba) -> Arc<dyn BlendOp> {
+        Arc::new(Self { name, alpha_mode: AlphaMode::Composite, op })
+    }
+}
+
+impl BlendOp for PaletteOp {
+    fn name(&self) -> &str {
+        self.name
+    }
+
+    fn alpha_mode(&self) -> AlphaMode {
+        self.alpha_mode
+    }
+
+    fn blend_pixel(&self, dst: [f32; 4], src: [f32; 4]) -> [f32; 4] {
+        let dst = LinSrgba::new(dst[0], dst[1], dst[2], dst[3]);
+        let src = LinSrgba::new(src[0], src[1], src[2], src[3]);
+        let (r, g, b, a) = (self.op)(dst, src).into_components();
+        [r, g, b, a]
+    }
+}
+
+/// 柔光, 使用自己的实现而不是 palette 的 soft_light
+struct SoftlightOp;
+
+impl SoftlightOp {
+    fn softlight_op(dst: Array1<f32>, src: Array1<f32>, da: f32, sa: f32) -> (f32, f32, f32, f32) {
+        let src2 = &src * 2.0;
+        let dst_np = if da != 0.0 {
+            (&dst * 1.0) / da
+        } else {
+            Array1::zeros(dst.len())
+        };
+
+        // 计算中间值的逐元素操作
+        let temp = &src * (1.0 - da) + &dst * (1.0 - sa);
+
+        let result: Vec<f32> = src2
+            .iter()
+            .zip(dst.iter())
+            .zip(dst_np.iter())
+            .zip(temp.iter())
+            .map(|(((&src2, &dst), &dst_np), &temp)| {
+                if src2 < sa {
+                    (dst * (sa + (src2 - sa) * (1.0 - dst_np)) + temp) / 1.0
+                } else if 4.0 * dst <= da {
+                    (dst * sa + da * (src2 - sa)
+                        * (((16.0 * dst_np - 12.0) * dst_np + 3.0) * dst_np / 1.0) + temp) / 1.0
+                } else {
+                    (dst * sa + da * (src2 - sa)
+                        * ((dst_np.sqrt()) - dst_np) + temp) / 1.0
+                }
+            })
+            .map(Self::clamp) // 确保每个通道值在0到1范围内
+            .collect();
+
+        (result[0], result[1], result[2], da)
+    }
+
+    fn clamp(value: f32) -> f32 {
+        value.max(0.0).min(1.0)
+    }
+}
+
+impl BlendOp for SoftlightOp {
+    fn name(&self) -> &str {
+        "softlight"
+    }
+
+    fn blend_pixel(&self, dst: [f32; 4], src: [f32; 4]) -> [f32; 4] {
+        // softlight_op 以上层图像作为 dst
+        let color = Array1::from_shape_fn(3, |i| dst[i]);
+        let color2 = Array1::from_shape_fn(3, |i| src[i]);
+        let (r, g, b, a) = Self::softlight_op(color2, color, src[3], dst[3]);
+        [r, g, b, a]
+    }
+}
+
+fn builtin_ops() -> HashMap<String, Arc<dyn BlendOp>> {
+    let ops: Vec<Arc<dyn BlendOp>> = vec![
+        PaletteOp::blend("overlay", |dst, src| dst.overlay(src)),
+        PaletteOp::blend("multiply", |dst, src| src.multiply(dst)),
+        PaletteOp::blend("burn", |dst, src| dst.burn(src)),
+        Arc::new(SoftlightOp),
+        PaletteOp::blend("hardlight", |dst, src| dst.hard_light(src)),
+        PaletteOp::blend("difference", |dst, src| src.difference(dst)),
+        PaletteOp::blend("lighten", |dst, src| src.lighten(dst)),
+        PaletteOp::blend("darken", |dst, src| src.darken(dst)),
+        PaletteOp::blend("dodge", |dst, src| dst.dodge(src)),
+        PaletteOp::blend("plus", |dst, src| src.plus(dst)),
+        PaletteOp::blend("addition", |dst, src| src.plus(dst)),
+        PaletteOp::blend("exclusion", |dst, src| src.exclusion(dst)),
+        PaletteOp::blend("subtract", |dst, src| src.exclusion(dst)),
+        PaletteOp::blend("screen", |dst, src| src.screen(dst)),
+        // Porter-Duff 合成, src 为上层图像, dst 为底图
+        PaletteOp::compose("over", |dst, src| src.over(dst)),
+        PaletteOp::compose("atop", |dst, src| src.atop(dst)),
+        PaletteOp::compose("xor", |dst, src| src.xor(dst)),
+        PaletteOp::compose("in", |dst, src| src.inside(dst)),
+        PaletteOp::compose("out", |dst, src| src.outside(dst)),
+        PaletteOp::compose("dest-over", |dst, src| dst.over(src)),
+        PaletteOp::compose("dest-in", |dst, src| dst.inside(src)),
+        PaletteOp::compose("dest-out", |dst, src| dst.outside(src)),
+        PaletteOp::compose("dest-atop", |dst, src| dst.atop(src)),
+        PaletteOp::compose("clear", |_, _| LinSrgba::new(0.0, 0.0, 0.0, 0.0)),
+        PaletteOp::compose("source", |_, src| src),
+        PaletteOp::compose("destination", |dst, _| dst),
+    ];
+
+    ops.into_iter()
+        .map(|op| (op.name().to_string(), op))
+        .collect()
+}
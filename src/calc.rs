@@ -0,0 +1,223 @@
+use std::cell::RefCell;
+
+use gdal::raster::{Buffer, GdalDataType};
+use gdal::{Dataset, GeoTransform};
+use rayon::prelude::*;
+
+use crate::blend::BlendImage;
+use crate::error::{BlendError, Result};
+use crate::expr::Expression;
+use crate::geotiff::TiffOptions;
+use crate::pipeline::LayerSource;
+
+/// 栅格计算结果默认的 nodata 值
+pub const DEFAULT_NODATA: f64 = -9999.0;
+
+/// 栅格计算器, 读取多个数据集的任意波段逐像素计算表达式
+///
+/// 表达式中输入名(如 `a`)为该输入的第 1 个波段, `a3` 为第 3 个波段, 名称不区分大小写.
+/// 任一参与计算的波段为 nodata 或结果不是有限数时, 结果为 nodata.
+#[derive(Debug, Clone)]
+pub struct RasterCalc {
+    expression: String,
+    inputs: Vec<(String, String)>,
+    nodata: f64,
+}
+
+impl RasterCalc {
+    pub fn new(expression: &str) -> Self {
+        Self {
+            expression: expression.to_string(),
+            inputs: Vec::new(),
+            nodata: DEFAULT_NODATA,
+        }
+    }
+
+    /// 添加输入数据集, name 只能由字母组成
+    pub fn input(mut self, name: &str, path: &str) -> Self {
+        self.inputs.push((name.to_lowercase(), path.to_string()));
+        self
+    }
+
+    /// 结果的 nodata 值
+    pub fn nodata(mut self, nodata: f64) -> Self {
+        self.nodata = nodata;
+        self
+    }
+
+    fn check_inputs(&self) -> Result<()> {
+        if self.inputs.is_empty() {
+            return Err(BlendError::InvalidParameter("raster calculator needs at least one input".to_string()));
+        }
+        for (index, (name, _)) in self.inputs.iter().enumerate() {
+            if name.is_empty() || !name.chars().all(|c| c.is_ascii_alphabetic()) {
+                return Err(BlendError::InvalidParameter(format!("input name must be letters only, got '{}'", name)));
+            }
+            if self.inputs[..index].iter().any(|(other, _)| other == name) {
+                return Err(BlendError::InvalidParameter(format!("duplicate input name '{}'", name)));
+            }
+        }
+        Ok(())
+    }
+
+    pub fn evaluate(&self) -> Result<CalcResult> {
+        self.check_inputs()?;
+        let datasets = self.inputs
+            .iter()
+            .map(|(_, path)| Dataset::open(path))
+            .collect::<Result<Vec<_>, _>>()?;
+
+        let (width, height) = datasets[0].raster_size();
+        for dataset in &datasets[1..] {
+            let size = dataset.raster_size();
+            if size != (width, height) {
+                return Err(BlendError::SizeMismatch {
+                    expected: (width as u32, height as u32),
+                    actual: (size.0 as u32, size.1 as u32),
+                });
+            }
+        }
+
+        // 表达式中用到的 (输入序号, 波段号), 下标即 evaluate_slots 中的位置
+        let used: RefCell<Vec<(usize, isize)>> = RefCell::new(Vec::new());
+        let expression = Expression::parse_with(&self.expression, |name| {
+            let split = name.find(|c: char| c.is_ascii_digit()).unwrap_or(name.len());
+            let (input, band) = name.split_at(split);
+            let dataset = self.inputs.iter().position(|(name, _)| name == input)?;
+            let band: isize = if band.is_empty() { 1 } else { band.parse().ok()? };
+            if band < 1 || band > datasets[dataset].raster_count() {
+                return None;
+            }
+            let mut used = used.borrow_mut();
+            let slot = used.iter().position(|b| *b == (dataset, band)).unwrap_or_else(|| {
+                used.push((dataset, band));
+                used.len() - 1
+            });
+            Some(slot)
+        })?;
+
+        let bands = used
+            .into_inner()
+            .into_iter()
+            .map(|(dataset, band)| {
+                let band = datasets[dataset].rasterband(band)?;
+                let data = band.read_as::<f64>((0, 0), (width, height), (width, height), None)?.data;
+                Ok((data, band.no_data_value()))
+            })
+            .collect::<Result<Vec<_>>>()?;
+
+        let mut data = vec![self.nodata; width * height];
+        if width > 0 {
+            data.par_chunks_mut(width).enumerate().for_each(|(y, row)| {
+                let mut slots = vec![0.0; bands.len()];
+                for (x, value) in row.iter_mut().enumerate() {
+                    let i = y * width + x;
+                    let valid = slots.iter_mut().zip(&bands).all(|(slot, (band, nodata))| {
+                        *slot = band[i];
+                        !band[i].is_nan() && Some(band[i]) != *nodata
+                    });
+                    if valid {
+                        let result = expression.evaluate_slots(&slots);
+                        if result.is_finite() {
+                            *value = result;
+                        }
+                    }
+                }
+            });
+        }
+
+        Ok(CalcResult {
+            width,
+            height,
+            data,
+            nodata: self.nodata,
+            geo_transform: datasets[0].geo_transform().ok(),
+            projection: datasets[0].projection(),
+        })
+    }
+}
+
+/// 栅格计算的结果, 单波段, 地理参考信息取自第一个输入
+#[derive(Debug, Clone)]
+pub struct CalcResult {
+    pub width: usize,
+    pub height: usize,
+    pub data: Vec<f64>,
+    pub nodata: f64,
+    pub geo_transform: Option<GeoTransform>,
+    pub projection: String,
+}
+
+impl CalcResult {
+    fn is_nodata(&self, value: f64) -> bool {
+        value == self.nodata || value.is_nan()
+    }
+
+    /// 有效值的最小值和最大值, 全部为 nodata 时为 None
+    pub fn range(&self) -> Option<(f64, f64)> {
+        self.data
+            .iter()
+            .filter(|value| !self.is_nodata(**value))
+            .fold(None, |range, &value| match range {
+                None => Some((value, value)),
+                Some((min, max)) => Some((min.min(value), max.max(value))),
+            })
+    }
+
+    /// 保存为 Float32 单波段 GeoTIFF, 未设置压缩方式时为 LZW
+    pub fn save_tiff(&self, output_path: &str, options: &TiffOptions) -> Result<()> {
+        options.save_with(output_path, |path, options| self.write_tiff(path, options))
+    }
+
+    fn write_tiff(&self, output_path: &str, options: &TiffOptions) -> Result<()> {
+        let mut dataset = BlendImage::create_tiff(
+            output_path,
+            (self.width as isize, self.height as isize),
+            1,
+            &GdalDataType::Float32,
+            &options.gtiff_options(None),
+        )?;
+        if let Some(geo_transform) = &self.geo_transform {
+            dataset.set_geo_transform(geo_transform)?;
+            dataset.set_projection(&self.projection)?;
+        }
+
+        let mut band = dataset.rasterband(1)?;
+        band.set_no_data_value(Some(self.nodata))?;
+        let data: Vec<f32> = self.data.iter().map(|value| *value as f32).collect();
+        let buffer = Buffer::new((self.width, self.height), data);
+        band.write((0, 0), (self.width, self.height), &buffer)?;
+
+        dataset.close()?;
+        Ok(())
+    }
+
+    /// 转为用于混合的灰度图层, range 内的值线性映射到 0-255, nodata 为透明;
+    /// range 为 None 时使用数据的最小值和最大值
+    pub fn to_blend_image(&self, range: Option<(f64, f64)>) -> Result<BlendImage> {
+        let (min, max) = range.or_else(|| self.range()).unwrap_or((0.0, 1.0));
+        if max < min {
+            return Err(BlendError::InvalidParameter(format!("invalid value range {}..{}", min, max)));
+        }
+
+        let mut raw_pixels = Vec::with_capacity(self.data.len() * 4);
+        for &value in &self.data {
+            if self.is_nodata(value) {
+                raw_pixels.extend_from_slice(&[0, 0, 0, 0]);
+                continue;
+            }
+            let unit = if max > min { (value - min) / (max - min) } else { 0.0 };
+            let gray = (unit.clamp(0.0, 1.0) * 255.0).round() as u8;
+            raw_pixels.extend_from_slice(&[gray, gray, gray, 255]);
+        }
+        BlendImage::from_raw(self.width as u32, self.height as u32, raw_pixels)
+    }
+}
+
+impl TryFrom<CalcResult> for LayerSource {
+    type Error = BlendError;
+
+    fn try_from(result: CalcResult) -> Result<Self> {
+        Ok(Self::Image(result.to_blend_image(None)?))
+    }
+}
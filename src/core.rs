@@ -1,25 +1,356 @@
-use std::sync::RwLock;
+use std::path::Path;
 
 use anyhow::{anyhow, Result};
-use crate::{argparse::{ArgParse, ArgParseProcess}, utils::makedirs};
-use lazy_static::lazy_static;
-
-lazy_static!(
-    pub static ref OUTPUT_FOLDER:RwLock<Option<String>> = RwLock::new(None);
-);
+use chrono::Utc;
+use crate::{argparse::{ApiArgs, ArgParse, ArgParseProcess, BatchArgs, CalcArgs, Command, Format, ServeArgs, TerrainArgs, TilesArgs}, utils::makedirs};
+use crate::batch::{load_manifest, pair_directories};
+use crate::blend::{BlendImage, BlendImagePair, BlendManager};
+use crate::calc::RasterCalc;
+use crate::error::BlendError;
+use crate::output::{check_exists, check_folder_exists, render_template, OutputTarget, TemplateContext};
+use crate::pipeline::{BlendJob, BlendJobBuilder, JobOutcome, LayerSource};
+use crate::recipe::Recipe;
+use crate::serve::{BlendParams, TileServer, WindowSource};
+use crate::service::{BlendService, ServiceConfig};
+use crate::sun::Sun;
+use crate::terrain::{Dem, Terrain};
+use crate::tiles::TilePyramid;
+use crate::tilestore::{TileInfo, TileOutput};
+use crate::worldfile::Georeference;
 
 
 pub fn options_post_processing(options: &ArgParse) -> Result<()> {
 
+    let batch = matches!(options.command, Some(Command::Batch(_)));
+    if !batch && (options.image.is_empty() || options.image2.is_empty()) {
+        return Err(BlendError::InvalidParameter("No input file specified".to_string()).into());
+    }
+    if options.image == "-" && options.image2 == "-" {
+        return Err(BlendError::InvalidParameter("Only one input image can be read from stdin".to_string()).into());
+    }
+    if (options.image == "-" && options.bands.is_some()) || (options.image2 == "-" && options.bands2.is_some()) {
+        return Err(BlendError::InvalidParameter("--bands needs a file readable by GDAL, not stdin".to_string()).into());
+    }
+    // 提前检查输出文件名模板, 避免处理完图像后才报错
+    render_template(options.output_template(), &TemplateContext {
+        image: "image.png",
+        image2: "image2.png",
+        mode: "overlay",
+        index: 1,
+        format: "png",
+    })?;
+
+    match options.output_target()? {
+        OutputTarget::Folder(output_folder) => {
+            eprintln!("Output folder: {:?}", output_folder);
+            makedirs(&output_folder)?;
+        }
+        target if batch => {
+            return Err(BlendError::InvalidParameter(format!("Batch mode needs an output folder, got {:?}", target)).into());
+        }
+        OutputTarget::File(output_file) => {
+            eprintln!("Output file: {:?}", output_file);
+            if let Some(parent) = Path::new(&output_file).parent().and_then(|p| p.to_str()) {
+                if !parent.is_empty() {
+                    makedirs(parent)?;
+                }
+            }
+        }
+        OutputTarget::Stdout => {}
+    }
+
+    Ok(())
+}
+
+/// 命令行入口: 单张混合, 批量混合和切片先检查输入输出参数, 其他子命令各自检查
+pub fn run(options: &ArgParse) -> Result<()> {
+    let recipe = options.recipe()?;
+    match &options.command {
+        Some(Command::Calc(calc)) => run_calc(calc, options, &recipe),
+        Some(Command::Terrain(terrain)) => run_terrain(terrain, options, &recipe),
+        Some(Command::Serve(serve)) => run_serve(serve, options, &recipe),
+        Some(Command::Api(api)) => run_api(api),
+        Some(Command::Batch(batch)) => {
+            options_post_processing(options)?;
+            run_batch(batch, options, &recipe)
+        }
+        Some(Command::Tiles(tiles)) => {
+            options_post_processing(options)?;
+            run_tiles(tiles, options, &recipe)
+        }
+        None => {
+            options_post_processing(options)?;
+            run_single(options, &recipe)
+        }
+    }
+}
+
+/// 命令行参数优先于 recipe, 先应用 recipe 再用命令行参数覆盖
+fn job_builder(options: &ArgParse, recipe: &Recipe, image: &str, image2: &str) -> Result<BlendJobBuilder> {
+    let (base, top) = options.layers(image, image2);
+    let mut builder = BlendJob::builder(base, top)
+        .recipe(recipe)
+        .adjustments(options.adjustments(recipe)?)
+        .georef_from(options.georef_from)
+        .grid_mismatch(options.grid_mismatch);
+    if let Some(blend_mode) = &options.blend_mode {
+        builder = builder.blend_mode(blend_mode);
+    }
+    if options.swap_layers {
+        builder = builder.swap_layers(true);
+    }
+    Ok(builder)
+}
+
+fn run_single(options: &ArgParse, recipe: &Recipe) -> Result<()> {
+    let job = job_builder(options, recipe, &options.image, &options.image2)?
+        .output(options.output_spec(recipe)?)
+        .build()?;
+
+    if let JobOutcome::Skipped(save_path) = job.execute()? {
+        eprintln!("skip existing output: {}", save_path);
+    }
+    Ok(())
+}
+
+fn run_batch(batch: &BatchArgs, options: &ArgParse, recipe: &Recipe) -> Result<()> {
+    let image_pairs = match (&batch.manifest, &batch.base_dir, &batch.overlay_dir) {
+        (Some(manifest), _, _) => load_manifest(manifest)?,
+        (None, Some(base_dir), Some(overlay_dir)) => pair_directories(base_dir, overlay_dir, batch)?,
+        _ => return Err(BlendError::InvalidParameter("batch needs --manifest or both --base-dir and --overlay-dir".to_string()).into()),
+    };
+    println!("batch: {} image pairs", image_pairs.len());
+
+    // 任务构建失败(如未知混合模式)与执行失败一样计入汇总, 不影响其他行
+    let mut results: Vec<Option<Result<JobOutcome>>> = Vec::with_capacity(image_pairs.len());
+    let mut jobs = Vec::new();
+    for (index, pair) in image_pairs.iter().enumerate() {
+        match batch_job(index + 1, pair, options, recipe) {
+            Ok(job) => {
+                jobs.push(job);
+                results.push(None);
+            }
+            Err(e) => results.push(Some(Err(e))),
+        }
+    }
+    let mut outcomes = BlendManager::blend_manager_pair(&jobs)
+        .into_iter()
+        .map(|outcome| outcome.map_err(anyhow::Error::from));
+    let results: Vec<Result<JobOutcome>> = results
+        .into_iter()
+        .map(|result| result.unwrap_or_else(|| outcomes.next().expect("one outcome per job")))
+        .collect();
+
+    let failed: Vec<(usize, &anyhow::Error)> = results
+        .iter()
+        .enumerate()
+        .filter_map(|(index, result)| result.as_ref().err().map(|err| (index, err)))
+        .collect();
+    let skipped = results.iter().filter(|result| matches!(result, Ok(JobOutcome::Skipped(_)))).count();
+
+    println!(
+        "batch finished: {} succeeded, {} skipped, {} failed",
+        results.len() - failed.len() - skipped,
+        skipped,
+        failed.len()
+    );
+    for (index, err) in &failed {
+        let pair = &image_pairs[*index];
+        eprintln!("  [{}] {} + {}: {}", index + 1, pair.image, pair.image2, err);
+    }
+
+    if !failed.is_empty() {
+        return Err(anyhow!("{} of {} batch items failed", failed.len(), results.len()));
+    }
+    Ok(())
+}
+
+/// 由 manifest 或文件夹配对的一行生成混合任务, 行内的 mode 和 output 优先于命令行参数
+fn batch_job(index: usize, pair: &BlendImagePair, options: &ArgParse, recipe: &Recipe) -> Result<BlendJob> {
+    let mut output = options.output_spec(recipe)?.with_relative_dir(pair.relative_dir.clone());
+    if let Some(save_path) = &pair.output {
+        output.target = OutputTarget::File(save_path.to_string());
+    }
+
+    let mut builder = job_builder(options, recipe, &pair.image, &pair.image2)?
+        .output(output)
+        .index(index);
+    if let Some(blend_mode) = &pair.blend_mode {
+        builder = builder.blend_mode(blend_mode);
+    }
+    Ok(builder.build()?)
+}
+
+/// calc 和 terrain 的输出文件, 格式由扩展名决定, 并创建所在的文件夹
+fn raster_output<'a>(options: &'a ArgParse, command: &str) -> Result<(&'a str, Format)> {
+    let output = options.output.as_deref().filter(|output| !output.is_empty())
+        .ok_or_else(|| BlendError::InvalidParameter(format!("{} needs an output file, use -o", command)))?;
+    let format = Format::from_path(output)
+        .ok_or_else(|| BlendError::UnsupportedFormat(format!("cannot infer output format from {}", output)))?;
+    if let Some(parent) = Path::new(output).parent().and_then(|p| p.to_str()) {
+        if !parent.is_empty() {
+            makedirs(parent)?;
+        }
+    }
+    Ok((output, format))
+}
+
+/// 栅格计算, 输出 tif 时保存为 GeoTIFF, 其他格式保存为可用于混合的灰度图层
+fn run_calc(calc: &CalcArgs, options: &ArgParse, recipe: &Recipe) -> Result<()> {
+    let (output, format) = raster_output(options, "calc")?;
+
+    let raster_calc = calc.inputs
+        .iter()
+        .fold(RasterCalc::new(&calc.expr).nodata(calc.nodata), |raster_calc, (name, path)| raster_calc.input(name, path));
+    let result = raster_calc.evaluate()?;
+
+    match format {
+        Format::TIFF => result.save_tiff(output, &options.tiff_options(recipe)?)?,
+        format => {
+            BlendImage::save_image(result.to_blend_image(calc.range)?, output, &format)?;
+            if let Some(geo_transform) = result.geo_transform {
+                Georeference::new(geo_transform, result.projection).write_sidecars(output, &format)?;
+            }
+        }
+    }
+    Ok(())
+}
+
+/// 地形因子, 输出 tif 且没有指定 --style 时保存原始值, 否则着色为可用于混合的 RGBA 图层
+fn run_terrain(args: &TerrainArgs, options: &ArgParse, recipe: &Recipe) -> Result<()> {
+    let (output, format) = raster_output(options, "terrain")?;
+    let dem = Dem::open(&args.dem)?;
+    let sun = match args.time {
+        Some(time) => {
+            let (lon, lat) = match args.location {
+                Some(location) => location,
+                None => dem.center_lon_lat()?,
+            };
+            let sun = Sun::at(time.with_timezone(&Utc), lon, lat);
+            eprintln!("sun: azimuth {:.1}, altitude {:.1}", sun.azimuth, sun.altitude);
+            sun
+        }
+        None => Sun::new(args.sun_azimuth, args.sun_altitude),
+    };
+    let terrain = Terrain::new(args.derivative)
+        .with_slope_unit(args.unit)
+        .with_z_factor(args.z_factor)
+        .with_radius(args.radius as usize)
+        .with_directions(args.directions as usize)
+        .with_sun(sun)
+        .with_penumbra(args.penumbra)
+        .with_cast_shadows(args.cast_shadows)
+        .with_detail(args.detail);
+    let terrain = match args.stretch {
+        Some(stretch) => terrain.with_stretch(stretch),
+        None => terrain,
+    };
+    let result = terrain.compute(&dem);
+
+    match (format, args.style) {
+        (Format::TIFF, None) => result.save_tiff(output, &options.tiff_options(recipe)?)?,
+        (Format::TIFF, style) => {
+            let image = terrain.style(&dem, &result, style, args.range)?;
+            BlendImage::save_tiff_with(image, output, Some(&args.dem), &options.tiff_options(recipe)?)?;
+        }
+        (format, style) => {
+            BlendImage::save_image(terrain.style(&dem, &result, style, args.range)?, output, &format)?;
+            if let Some(geo_transform) = result.geo_transform {
+                Georeference::new(geo_transform, result.projection).write_sidecars(output, &format)?;
+            }
+        }
+    }
+    Ok(())
+}
+
+/// 混合后切片, -o 为 .mbtiles 或 .gpkg 时写入单个文件, 否则写入文件夹下的 z/x/y
+fn run_tiles(tiles: &TilesArgs, options: &ArgParse, recipe: &Recipe) -> Result<()> {
+    let output = match options.output_target()? {
+        OutputTarget::Folder(folder) => {
+            if !check_folder_exists(&folder, options.exists_policy())? {
+                eprintln!("skip existing output: {}", folder);
+                return Ok(());
+            }
+            TileOutput::Directory(folder)
+        }
+        OutputTarget::File(file) => match TileOutput::parse(&file) {
+            TileOutput::Directory(_) => {
+                return Err(BlendError::UnsupportedFormat(format!("tiles are written to a folder, .mbtiles or .gpkg, got {}", file)).into())
+            }
+            output => {
+                if !check_exists(&file, options.exists_policy())? {
+                    eprintln!("skip existing output: {}", file);
+                    return Ok(());
+                }
+                output
+            }
+        },
+        OutputTarget::Stdout => return Err(BlendError::InvalidParameter("tiles cannot be written to stdout".to_string()).into()),
+    };
+    let job = job_builder(options, recipe, &options.image, &options.image2)?.build()?;
+    let georef_image = job.georef_path()
+        .ok_or_else(|| BlendError::Georef("tiles needs a georeferenced basemap file, not stdin".to_string()))?
+        .to_string();
+
+    let pyramid = TilePyramid::new(tiles.zoom)
+        .with_scheme(tiles.scheme)
+        .with_format(tiles.tile_format.clone())
+        .with_tile_size(tiles.tile_size);
+    let name = tiles.name.clone().unwrap_or_else(|| {
+        Path::new(&georef_image).file_stem().map(|stem| stem.to_string_lossy().into_owned()).unwrap_or_default()
+    });
+    let info = TileInfo::new(&name)
+        .with_attribution(tiles.attribution.clone())
+        .with_description(tiles.description.clone());
+    let stats = pyramid.write(job.run()?, &georef_image, &output, &info)?;
+    println!("tiles: {} written, {} transparent skipped", stats.written, stats.empty);
+    Ok(())
+}
+
+/// 瓦片服务, 命令行和 recipe 中的混合参数作为查询参数的默认值
+fn run_serve(serve: &ServeArgs, options: &ArgParse, recipe: &Recipe) -> Result<()> {
     if options.image.is_empty() || options.image2.is_empty() {
-        return Err(anyhow!("No input file specified"));
+        return Err(BlendError::InvalidParameter("No input file specified".to_string()).into());
+    }
+    if options.image == "-" || options.image2 == "-" {
+        return Err(BlendError::InvalidParameter("serve reads windows of GeoTIFF files, not stdin".to_string()).into());
+    }
+    if serve.tile_size == 0 || serve.tile_size > 4096 {
+        return Err(BlendError::InvalidParameter(format!("tile size must be 1-4096, got {}", serve.tile_size)).into());
     }
-    let output_folder = options.output_folder()?;
-    println!("Output folder: {:?}", output_folder);
 
-    makedirs(&output_folder)?;
-    *OUTPUT_FOLDER.write().unwrap() = Some(output_folder);
+    // 先构建一次任务, 检查混合模式和调整参数
+    let job = job_builder(options, recipe, &options.image, &options.image2)?.build()?;
+    let params = BlendParams {
+        blend_mode: job.blend_mode().to_string(),
+        swap_layers: job.swap_layers(),
+        adjustments: job.adjustments().clone(),
+    };
+    let (base, top) = options.layers(&options.image, &options.image2);
+    let source = |layer: LayerSource| match layer {
+        LayerSource::Bands(path, mapping) => WindowSource::open(&path, Some(mapping)),
+        layer => WindowSource::open(layer.name(), None),
+    };
+    let server = TileServer::new(source(base)?, source(top)?, params)
+    .with_tile_size(serve.tile_size)
+    .with_cache_dir(serve.cache_dir.clone());
 
+    let threads = serve.threads.unwrap_or_else(|| std::thread::available_parallelism().map(|n| n.get()).unwrap_or(4));
+    server.serve(&format!("{}:{}", serve.host, serve.port), threads)?;
     Ok(())
 }
 
+/// HTTP 混合服务, 混合参数全部来自请求
+fn run_api(api: &ApiArgs) -> Result<()> {
+    let config = ServiceConfig {
+        max_body_size: api.max_body_mb * 1024 * 1024,
+        max_concurrent: api.max_concurrent.max(1),
+        root: api.root.as_ref().map(|root| Path::new(root).to_path_buf()),
+    };
+    let service = BlendService::bind(&format!("{}:{}", api.host, api.port), config)?;
+    if let Some(address) = service.local_addr() {
+        eprintln!("blend service on http://{}/blend", address);
+    }
+    service.run();
+    Ok(())
+}
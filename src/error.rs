@@ -0,0 +1,84 @@
+use gdal::errors::GdalError;
+use image::ImageError;
+use thiserror::Error;
+
+pub type Result<T, E = BlendError> = std::result::Result<T, E>;
+
+/// 库的错误类型, 命令行按错误类型返回不同的退出码
+#[derive(Debug, Error)]
+pub enum BlendError {
+    /// 读写文件或标准输入输出失败
+    #[error("I/O error: {0}")]
+    Io(#[from] std::io::Error),
+
+    /// 图像数据损坏或无法识别
+    #[error("failed to decode image: {0}")]
+    Decode(#[source] ImageError),
+
+    /// 图像无法按指定格式编码
+    #[error("failed to encode image: {0}")]
+    Encode(#[source] ImageError),
+
+    /// 不支持的图像格式或数据类型
+    #[error("unsupported format: {0}")]
+    UnsupportedFormat(String),
+
+    /// 两个图层或图像与地理参考来源的大小不一致
+    #[error("image size mismatch: expected {}x{}, got {}x{}", expected.0, expected.1, actual.0, actual.1)]
+    SizeMismatch {
+        expected: (u32, u32),
+        actual: (u32, u32),
+    },
+
+    /// 混合模式、颜色、输出文件名模板等参数无效
+    #[error("invalid parameter: {0}")]
+    InvalidParameter(String),
+
+    /// GDAL 读写栅格失败
+    #[error("GDAL error: {0}")]
+    Gdal(#[from] GdalError),
+
+    /// 缺少或无法使用地理参考信息
+    #[error("georeferencing error: {0}")]
+    Georef(String),
+
+    /// 读写 MBTiles 或 GeoPackage 失败
+    #[error("SQLite error: {0}")]
+    Sqlite(#[from] rusqlite::Error),
+}
+
+impl BlendError {
+    /// 命令行退出码, 1 为其他错误, 2 为 clap 的参数错误
+    pub fn exit_code(&self) -> i32 {
+        match self {
+            Self::Io(_) => 3,
+            Self::Decode(_) => 4,
+            Self::Encode(_) => 5,
+            Self::UnsupportedFormat(_) => 6,
+            Self::SizeMismatch { .. } => 7,
+            Self::InvalidParameter(_) => 8,
+            Self::Gdal(_) => 9,
+            Self::Georef(_) => 10,
+            Self::Sqlite(_) => 11,
+        }
+    }
+}
+
+impl From<ImageError> for BlendError {
+    fn from(err: ImageError) -> Self {
+        match err {
+            ImageError::IoError(err) => Self::Io(err),
+            ImageError::Unsupported(err) => Self::UnsupportedFormat(err.to_string()),
+            ImageError::Encoding(_) => Self::Encode(err),
+            _ => Self::Decode(err),
+        }
+    }
+}
+
+/// 在 anyhow 错误链中查找 BlendError 得到退出码, 找不到时为 1
+pub fn exit_code(err: &anyhow::Error) -> i32 {
+    err.chain()
+        .find_map(|cause| cause.downcast_ref::<BlendError>())
+        .map(BlendError::exit_code)
+        .unwrap_or(1)
+}
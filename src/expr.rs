@@ -0,0 +1,668 @@
+use std::fmt;
+
+use crate::blend_op::{AlphaMode, BlendOp};
+use crate::error::BlendError;
+
+/// 表达式混合模式的前缀, 如 `-m "expr:base * (0.4 + 0.6 * top)"`
+pub const EXPR_PREFIX: &str = "expr:";
+
+/// 表达式的解析错误, position 为出错字符的位置(从 0 开始)
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ParseError {
+    pub source: String,
+    pub position: usize,
+    pub message: String,
+}
+
+impl fmt::Display for ParseError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{} at column {}\n  {}\n  {}^", self.message, self.position + 1, self.source, " ".repeat(self.position))
+    }
+}
+
+impl std::error::Error for ParseError {}
+
+impl From<ParseError> for BlendError {
+    fn from(err: ParseError) -> Self {
+        BlendError::InvalidParameter(format!("invalid expression: {}", err))
+    }
+}
+
+/// 表达式求值时的像素变量, 取值范围 [0, 1]
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct PixelVars {
+    pub base: [f32; 4],
+    pub top: [f32; 4],
+    /// 当前计算的通道, 0 1 2 分别为 r g b
+    pub channel: usize,
+    pub x: u32,
+    pub y: u32,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum Layer {
+    Base,
+    Top,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum Var {
+    /// channel 为 None 时取当前计算的通道
+    Layer(Layer, Option<usize>),
+    X,
+    Y,
+    /// 由 Expression::parse_with 的变量表解析出的变量, 值为 evaluate_slots 参数中的下标
+    Slot(usize),
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum UnaryOp {
+    Neg,
+    Not,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum BinaryOp {
+    Add,
+    Sub,
+    Mul,
+    Div,
+    Rem,
+    Pow,
+    Lt,
+    Le,
+    Gt,
+    Ge,
+    Eq,
+    Ne,
+    And,
+    Or,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum Func {
+    Min,
+    Max,
+    Clamp,
+    Abs,
+    Sqrt,
+    Pow,
+    Exp,
+    Log,
+    Floor,
+    Ceil,
+    Round,
+    Sin,
+    Cos,
+    Mix,
+    If,
+}
+
+impl Func {
+    fn parse(name: &str) -> Option<(Func, usize)> {
+        let func = match name {
+            "min" => (Self::Min, 2),
+            "max" => (Self::Max, 2),
+            "clamp" => (Self::Clamp, 3),
+            "abs" => (Self::Abs, 1),
+            "sqrt" => (Self::Sqrt, 1),
+            "pow" => (Self::Pow, 2),
+            "exp" => (Self::Exp, 1),
+            "log" => (Self::Log, 1),
+            "floor" => (Self::Floor, 1),
+            "ceil" => (Self::Ceil, 1),
+            "round" => (Self::Round, 1),
+            "sin" => (Self::Sin, 1),
+            "cos" => (Self::Cos, 1),
+            "mix" | "lerp" => (Self::Mix, 3),
+            "if" => (Self::If, 3),
+            _ => return None,
+        };
+        Some(func)
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+enum Node {
+    Number(f64),
+    Var(Var),
+    Unary(UnaryOp, Box<Node>),
+    Binary(BinaryOp, Box<Node>, Box<Node>),
+    Call(Func, Vec<Node>),
+    Cond(Box<Node>, Box<Node>, Box<Node>),
+}
+
+fn truth(value: f64) -> bool {
+    value != 0.0 && !value.is_nan()
+}
+
+fn boolean(value: bool) -> f64 {
+    if value { 1.0 } else { 0.0 }
+}
+
+impl Node {
+    fn eval<F: Fn(Var) -> f64>(&self, vars: &F) -> f64 {
+        match self {
+            Node::Number(value) => *value,
+            Node::Var(var) => vars(*var),
+            Node::Unary(op, node) => {
+                let value = node.eval(vars);
+                match op {
+                    UnaryOp::Neg => -value,
+                    UnaryOp::Not => boolean(!truth(value)),
+                }
+            }
+            Node::Binary(BinaryOp::And, left, right) => boolean(truth(left.eval(vars)) && truth(right.eval(vars))),
+            Node::Binary(BinaryOp::Or, left, right) => boolean(truth(left.eval(vars)) || truth(right.eval(vars))),
+            Node::Binary(op, left, right) => {
+                let (l, r) = (left.eval(vars), right.eval(vars));
+                match op {
+                    BinaryOp::Add => l + r,
+                    BinaryOp::Sub => l - r,
+                    BinaryOp::Mul => l * r,
+                    BinaryOp::Div => l / r,
+                    BinaryOp::Rem => l % r,
+                    BinaryOp::Pow => l.powf(r),
+                    BinaryOp::Lt => boolean(l < r),
+                    BinaryOp::Le => boolean(l <= r),
+                    BinaryOp::Gt => boolean(l > r),
+                    BinaryOp::Ge => boolean(l >= r),
+                    BinaryOp::Eq => boolean(l == r),
+                    BinaryOp::Ne => boolean(l != r),
+                    BinaryOp::And | BinaryOp::Or => unreachable!("handled above"),
+                }
+            }
+            Node::Cond(cond, then, otherwise) => {
+                if truth(cond.eval(vars)) { then.eval(vars) } else { otherwise.eval(vars) }
+            }
+            Node::Call(Func::If, args) => {
+                if truth(args[0].eval(vars)) { args[1].eval(vars) } else { args[2].eval(vars) }
+            }
+            Node::Call(func, args) => {
+                let arg = |i: usize| args[i].eval(vars);
+                match func {
+                    Func::Min => arg(0).min(arg(1)),
+                    Func::Max => arg(0).max(arg(1)),
+                    Func::Clamp => arg(0).max(arg(1)).min(arg(2)),
+                    Func::Abs => arg(0).abs(),
+                    Func::Sqrt => arg(0).sqrt(),
+                    Func::Pow => arg(0).powf(arg(1)),
+                    Func::Exp => arg(0).exp(),
+                    Func::Log => arg(0).ln(),
+                    Func::Floor => arg(0).floor(),
+                    Func::Ceil => arg(0).ceil(),
+                    Func::Round => arg(0).round(),
+                    Func::Sin => arg(0).sin(),
+                    Func::Cos => arg(0).cos(),
+                    Func::Mix => {
+                        let t = arg(2);
+                        arg(0) * (1.0 - t) + arg(1) * t
+                    }
+                    Func::If => unreachable!("handled above"),
+                }
+            }
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+enum Token {
+    Number(f64),
+    Ident(String),
+    Op(&'static str),
+    End,
+}
+
+const OPERATORS: [&str; 21] = [
+    "<=", ">=", "==", "!=", "&&", "||",
+    "+", "-", "*", "/", "%", "^", "(", ")", ",", "?", ":", "<", ">", "!", ".",
+];
+
+fn tokenize(source: &str) -> Result<Vec<(Token, usize)>, ParseError> {
+    let chars: Vec<char> = source.chars().collect();
+    let error = |position: usize, message: String| ParseError {
+        source: source.to_string(),
+        position,
+        message,
+    };
+
+    let mut tokens = Vec::new();
+    let mut i = 0;
+    while i < chars.len() {
+        let c = chars[i];
+        let next_is_digit = chars.get(i + 1).is_some_and(|c| c.is_ascii_digit());
+        if c.is_whitespace() {
+            i += 1;
+        } else if c.is_ascii_digit() || (c == '.' && next_is_digit) {
+            let start = i;
+            while i < chars.len() && (chars[i].is_ascii_digit() || chars[i] == '.') {
+                i += 1;
+            }
+            // 科学计数法, 如 1e-3
+            if i < chars.len() && (chars[i] == 'e' || chars[i] == 'E') {
+                let mut j = i + 1;
+                if j < chars.len() && (chars[j] == '+' || chars[j] == '-') {
+                    j += 1;
+                }
+                if j < chars.len() && chars[j].is_ascii_digit() {
+                    i = j;
+                    while i < chars.len() && chars[i].is_ascii_digit() {
+                        i += 1;
+                    }
+                }
+            }
+            let text: String = chars[start..i].iter().collect();
+            let value = text
+                .parse::<f64>()
+                .map_err(|_| error(start, format!("invalid number '{}'", text)))?;
+            tokens.push((Token::Number(value), start));
+        } else if c.is_ascii_alphabetic() || c == '_' {
+            let start = i;
+            while i < chars.len() && (chars[i].is_ascii_alphanumeric() || chars[i] == '_') {
+                i += 1;
+            }
+            let text: String = chars[start..i].iter().collect();
+            tokens.push((Token::Ident(text.to_lowercase()), start));
+        } else {
+            let rest: String = chars[i..chars.len().min(i + 2)].iter().collect();
+            let op = OPERATORS
+                .iter()
+                .find(|op| rest.starts_with(**op))
+                .ok_or_else(|| error(i, format!("unexpected character '{}'", c)))?;
+            tokens.push((Token::Op(op), i));
+            i += op.chars().count();
+        }
+    }
+    tokens.push((Token::End, chars.len()));
+    Ok(tokens)
+}
+
+/// 表达式的最大长度(字节), 混合模式和 calc 的表达式都可能来自 HTTP 请求
+pub const MAX_LENGTH: usize = 4096;
+
+/// 语法树的最大深度, 括号、三元表达式、一元运算符和每个二元运算符各算一层, 避免解析和求值时递归过深导致栈溢出
+const MAX_DEPTH: usize = 256;
+
+/// 变量名到变量下标的映射
+type Resolve<'a> = &'a dyn Fn(&str) -> Option<usize>;
+
+struct Parser<'a> {
+    source: &'a str,
+    tokens: Vec<(Token, usize)>,
+    index: usize,
+    /// 当前的嵌套层数
+    depth: usize,
+    /// 变量表, 为 None 时使用混合模式的 base/top/x/y 变量
+    resolve: Option<Resolve<'a>>,
+}
+
+impl<'a> Parser<'a> {
+    fn peek(&self) -> &Token {
+        &self.tokens[self.index].0
+    }
+
+    fn position(&self) -> usize {
+        self.tokens[self.index].1
+    }
+
+    fn advance(&mut self) -> Token {
+        let token = self.tokens[self.index].0.clone();
+        if token != Token::End {
+            self.index += 1;
+        }
+        token
+    }
+
+    fn error<T>(&self, position: usize, message: impl Into<String>) -> Result<T, ParseError> {
+        Err(ParseError {
+            source: self.source.to_string(),
+            position,
+            message: message.into(),
+        })
+    }
+
+    fn eat(&mut self, op: &str) -> bool {
+        if matches!(self.peek(), Token::Op(o) if *o == op) {
+            self.advance();
+            true
+        } else {
+            false
+        }
+    }
+
+    fn expect(&mut self, op: &str) -> Result<(), ParseError> {
+        if self.eat(op) {
+            Ok(())
+        } else {
+            let found = describe(self.peek());
+            self.error(self.position(), format!("expected '{}' but found {}", op, found))
+        }
+    }
+
+    /// 在下一层嵌套中解析, 超过 MAX_DEPTH 层时返回错误
+    fn nested(&mut self, parse: impl FnOnce(&mut Self) -> Result<Node, ParseError>) -> Result<Node, ParseError> {
+        self.deeper()?;
+        let node = parse(self);
+        self.depth -= 1;
+        node
+    }
+
+    fn deeper(&mut self) -> Result<(), ParseError> {
+        if self.depth >= MAX_DEPTH {
+            return self.error(self.position(), format!("expression is nested more than {} levels deep", MAX_DEPTH));
+        }
+        self.depth += 1;
+        Ok(())
+    }
+
+    /// cond ? a : b, 优先级最低, 右结合
+    fn conditional(&mut self) -> Result<Node, ParseError> {
+        self.nested(|parser| {
+            let cond = parser.binary(0)?;
+            if parser.eat("?") {
+                let then = parser.conditional()?;
+                parser.expect(":")?;
+                let otherwise = parser.conditional()?;
+                return Ok(Node::Cond(Box::new(cond), Box::new(then), Box::new(otherwise)));
+            }
+            Ok(cond)
+        })
+    }
+
+    /// 二元运算符的优先级, 数值越大结合越紧
+    fn binary_op(token: &Token) -> Option<(BinaryOp, u8)> {
+        let op = match token {
+            Token::Op("||") => (BinaryOp::Or, 1),
+            Token::Op("&&") => (BinaryOp::And, 2),
+            Token::Op("==") => (BinaryOp::Eq, 3),
+            Token::Op("!=") => (BinaryOp::Ne, 3),
+            Token::Op("<") => (BinaryOp::Lt, 4),
+            Token::Op("<=") => (BinaryOp::Le, 4),
+            Token::Op(">") => (BinaryOp::Gt, 4),
+            Token::Op(">=") => (BinaryOp::Ge, 4),
+            Token::Op("+") => (BinaryOp::Add, 5),
+            Token::Op("-") => (BinaryOp::Sub, 5),
+            Token::Op("*") => (BinaryOp::Mul, 6),
+            Token::Op("/") => (BinaryOp::Div, 6),
+            Token::Op("%") => (BinaryOp::Rem, 6),
+            _ => return None,
+        };
+        Some(op)
+    }
+
+    /// 左结合的运算符每折叠一次, 语法树加深一层
+    fn binary(&mut self, min_precedence: u8) -> Result<Node, ParseError> {
+        let depth = self.depth;
+        let mut left = self.unary()?;
+        while let Some((op, precedence)) = Self::binary_op(self.peek()) {
+            if precedence <= min_precedence {
+                break;
+            }
+            self.deeper()?;
+            self.advance();
+            let right = self.binary(precedence)?;
+            left = Node::Binary(op, Box::new(left), Box::new(right));
+        }
+        self.depth = depth;
+        Ok(left)
+    }
+
+    fn unary(&mut self) -> Result<Node, ParseError> {
+        self.nested(|parser| {
+            if parser.eat("-") {
+                return Ok(Node::Unary(UnaryOp::Neg, Box::new(parser.unary()?)));
+            }
+            if parser.eat("+") {
+                return parser.unary();
+            }
+            if parser.eat("!") {
+                return Ok(Node::Unary(UnaryOp::Not, Box::new(parser.unary()?)));
+            }
+            parser.power()
+        })
+    }
+
+    /// a ^ b 右结合, 比一元负号结合更紧, -a^2 为 -(a^2)
+    fn power(&mut self) -> Result<Node, ParseError> {
+        let base = self.primary()?;
+        if self.eat("^") {
+            let exponent = self.unary()?;
+            return Ok(Node::Binary(BinaryOp::Pow, Box::new(base), Box::new(exponent)));
+        }
+        Ok(base)
+    }
+
+    fn primary(&mut self) -> Result<Node, ParseError> {
+        let position = self.position();
+        match self.advance() {
+            Token::Number(value) => Ok(Node::Number(value)),
+            Token::Op("(") => {
+                let node = self.conditional()?;
+                self.expect(")")?;
+                Ok(node)
+            }
+            Token::Ident(name) => {
+                if *self.peek() == Token::Op("(") {
+                    return self.call(&name, position);
+                }
+                self.variable(&name, position)
+            }
+            token => self.error(position, format!("expected a number, variable or '(' but found {}", describe(&token))),
+        }
+    }
+
+    fn call(&mut self, name: &str, position: usize) -> Result<Node, ParseError> {
+        let (func, arity) = match Func::parse(name) {
+            Some(func) => func,
+            None => return self.error(position, format!("unknown function '{}'", name)),
+        };
+        self.expect("(")?;
+        let mut args = Vec::new();
+        if !self.eat(")") {
+            loop {
+                args.push(self.conditional()?);
+                if self.eat(")") {
+                    break;
+                }
+                self.expect(",")?;
+            }
+        }
+        if args.len() != arity {
+            return self.error(position, format!("function '{}' takes {} arguments, got {}", name, arity, args.len()));
+        }
+        Ok(Node::Call(func, args))
+    }
+
+    fn constant(name: &str) -> Option<f64> {
+        match name {
+            "pi" => Some(std::f64::consts::PI),
+            "e" => Some(std::f64::consts::E),
+            "true" => Some(1.0),
+            "false" => Some(0.0),
+            _ => None,
+        }
+    }
+
+    fn variable(&mut self, name: &str, position: usize) -> Result<Node, ParseError> {
+        if let Some(resolve) = self.resolve {
+            // 变量表优先于常量
+            return match (resolve(name), Self::constant(name)) {
+                (Some(slot), _) => Ok(Node::Var(Var::Slot(slot))),
+                (None, Some(value)) => Ok(Node::Number(value)),
+                (None, None) => self.error(position, format!("unknown variable '{}'", name)),
+            };
+        }
+
+        let layer = match name {
+            "a" | "base" => Layer::Base,
+            "b" | "top" => Layer::Top,
+            "x" => return Ok(Node::Var(Var::X)),
+            "y" => return Ok(Node::Var(Var::Y)),
+            _ => match Self::constant(name) {
+                Some(value) => return Ok(Node::Number(value)),
+                None => return self.error(position, format!("unknown variable '{}'", name)),
+            },
+        };
+
+        if !self.eat(".") {
+            return Ok(Node::Var(Var::Layer(layer, None)));
+        }
+        let channel_position = self.position();
+        let channel = match self.advance() {
+            Token::Ident(channel) => match channel.as_str() {
+                "r" | "red" => 0,
+                "g" | "green" => 1,
+                "b" | "blue" => 2,
+                "a" | "alpha" => 3,
+                _ => return self.error(channel_position, format!("unknown channel '{}', expected r, g, b or a", channel)),
+            },
+            token => return self.error(channel_position, format!("expected a channel but found {}", describe(&token))),
+        };
+        Ok(Node::Var(Var::Layer(layer, Some(channel))))
+    }
+}
+
+fn describe(token: &Token) -> String {
+    match token {
+        Token::Number(value) => format!("number {}", value),
+        Token::Ident(name) => format!("'{}'", name),
+        Token::Op(op) => format!("'{}'", op),
+        Token::End => "end of expression".to_string(),
+    }
+}
+
+/// 逐像素计算的表达式
+///
+/// 变量 `a`/`base` 和 `b`/`top` 为底图和上层图像当前通道的值, `a.r`, `top.alpha` 等取指定通道,
+/// 取值范围均为 [0, 1]; `x`, `y` 为像素坐标. 比较和逻辑运算的结果为 1 或 0, 支持 `cond ? a : b`.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Expression {
+    source: String,
+    root: Node,
+}
+
+impl Expression {
+    pub fn parse(source: &str) -> Result<Expression, ParseError> {
+        Self::parse_inner(source, None)
+    }
+
+    /// 使用自定义变量表解析表达式, resolve 将(小写的)变量名映射为 evaluate_slots 参数中的下标,
+    /// 返回 None 的变量名视为未知变量. 此时不能使用 base/top/x/y 等混合模式的变量.
+    pub fn parse_with<F: Fn(&str) -> Option<usize>>(source: &str, resolve: F) -> Result<Expression, ParseError> {
+        Self::parse_inner(source, Some(&resolve))
+    }
+
+    fn parse_inner(source: &str, resolve: Option<Resolve>) -> Result<Expression, ParseError> {
+        if source.len() > MAX_LENGTH {
+            // 错误信息中只保留开头部分
+            let end = (0..=64).rev().find(|&end| source.is_char_boundary(end)).unwrap_or(0);
+            return Err(ParseError {
+                source: format!("{}...", &source[..end]),
+                position: 0,
+                message: format!("expression is longer than {} bytes", MAX_LENGTH),
+            });
+        }
+        let tokens = tokenize(source)?;
+        let mut parser = Parser {
+            source,
+            tokens,
+            index: 0,
+            depth: 0,
+            resolve,
+        };
+        let root = parser.conditional()?;
+        if *parser.peek() != Token::End {
+            let found = describe(parser.peek());
+            return parser.error(parser.position(), format!("unexpected {}", found));
+        }
+        Ok(Expression {
+            source: source.to_string(),
+            root,
+        })
+    }
+
+    pub fn source(&self) -> &str {
+        &self.source
+    }
+
+    pub fn evaluate(&self, vars: &PixelVars) -> f32 {
+        let lookup = |var: Var| match var {
+            Var::Layer(layer, channel) => {
+                let pixel = match layer {
+                    Layer::Base => &vars.base,
+                    Layer::Top => &vars.top,
+                };
+                pixel[channel.unwrap_or(vars.channel)] as f64
+            }
+            Var::X => vars.x as f64,
+            Var::Y => vars.y as f64,
+            Var::Slot(_) => f64::NAN,
+        };
+        self.root.eval(&lookup) as f32
+    }
+
+    /// 对 parse_with 解析的表达式求值, slots 按变量表的下标给出变量的值
+    pub fn evaluate_slots(&self, slots: &[f64]) -> f64 {
+        let lookup = |var: Var| match var {
+            Var::Slot(slot) => slots[slot],
+            _ => f64::NAN,
+        };
+        self.root.eval(&lookup)
+    }
+}
+
+/// 表达式混合模式, 对 r g b 分别求值, 结果截断到 [0, 1], 透明度保留底图的
+pub struct ExprOp {
+    name: String,
+    expression: Expression,
+}
+
+impl ExprOp {
+    pub fn parse(source: &str) -> Result<ExprOp, ParseError> {
+        Ok(ExprOp {
+            name: format!("{}{}", EXPR_PREFIX, source),
+            expression: Expression::parse(source)?,
+        })
+    }
+
+    fn blend_at(&self, dst: [f32; 4], src: [f32; 4], x: u32, y: u32) -> [f32; 4] {
+        let mut vars = PixelVars {
+            base: dst,
+            top: src,
+            channel: 0,
+            x,
+            y,
+        };
+        let mut result = dst;
+        for (channel, value) in result.iter_mut().take(3).enumerate() {
+            vars.channel = channel;
+            let v = self.expression.evaluate(&vars);
+            *value = if v.is_nan() { 0.0 } else { v.clamp(0.0, 1.0) };
+        }
+        result
+    }
+}
+
+impl BlendOp for ExprOp {
+    fn name(&self) -> &str {
+        &self.name
+    }
+
+    fn alpha_mode(&self) -> AlphaMode {
+        AlphaMode::Base
+    }
+
+    fn blend_pixel(&self, dst: [f32; 4], src: [f32; 4]) -> [f32; 4] {
+        self.blend_at(dst, src, 0, 0)
+    }
+
+    fn blend_row(&self, y: u32, dst: &mut [u8], src: &[u8]) {
+        for (x, (dst_px, src_px)) in dst.chunks_exact_mut(4).zip(src.chunks_exact(4)).enumerate() {
+            let unit = |px: &[u8]| [0, 1, 2, 3].map(|i| px[i] as f32 / 255.0);
+            let blended = self.blend_at(unit(dst_px), unit(src_px), x as u32, y);
+            dst_px[0] = (blended[0] * 255.0) as u8;
+            dst_px[1] = (blended[1] * 255.0) as u8;
+            dst_px[2] = (blended[2] * 255.0) as u8;
+        }
+    }
+}
@@ -0,0 +1,97 @@
+use clap::ValueEnum;
+use gdal::spatial_ref::SpatialRef;
+use gdal::{Dataset, GeoTransform};
+use serde::{Deserialize, Serialize};
+
+use crate::error::{BlendError, Result};
+
+/// 输出的地理参考信息和元数据取自哪个输入
+#[derive(Debug, Clone, Copy, Default, ValueEnum, Serialize, Deserialize, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum GeorefSource {
+    /// 底图, 即第一个输入
+    #[default]
+    Base,
+    /// 上层图像, 即第二个输入
+    Overlay,
+}
+
+/// 两个输入的坐标系或仿射变换不一致时的处理方式
+#[derive(Debug, Clone, Copy, Default, ValueEnum, Serialize, Deserialize, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum GridMismatch {
+    /// 输出警告后继续混合
+    #[default]
+    Warn,
+    /// 返回 Georef 错误
+    Fail,
+    /// 不检查
+    Ignore,
+}
+
+/// 输入的栅格网格: 仿射变换和坐标系
+struct Grid {
+    geo_transform: GeoTransform,
+    projection: String,
+}
+
+impl Grid {
+    /// GDAL 无法打开或没有仿射变换时为 None, 如普通的 png
+    fn open(path: &str) -> Option<Self> {
+        let dataset = Dataset::open(path).ok()?;
+        Some(Self {
+            geo_transform: dataset.geo_transform().ok()?,
+            projection: dataset.projection(),
+        })
+    }
+
+    /// 不一致之处, 一致时为 None
+    fn difference(&self, other: &Grid) -> Option<String> {
+        if !same_crs(&self.projection, &other.projection) {
+            return Some("different CRS".to_string());
+        }
+
+        // 像素大小和旋转按相对误差比较, 原点允许 1% 像素的偏差
+        let pixel_size = self.geo_transform[1].abs().max(self.geo_transform[5].abs());
+        let (a, b) = (&self.geo_transform, &other.geo_transform);
+        if [1, 2, 4, 5].iter().any(|&i| (a[i] - b[i]).abs() > pixel_size * 1e-6) {
+            return Some(format!("pixel size {} x {} vs {} x {}", a[1], a[5], b[1], b[5]));
+        }
+        if [0, 3].iter().any(|&i| (a[i] - b[i]).abs() > pixel_size * 0.01) {
+            return Some(format!("origin ({}, {}) vs ({}, {})", a[0], a[3], b[0], b[3]));
+        }
+        None
+    }
+}
+
+fn same_crs(wkt: &str, other: &str) -> bool {
+    if wkt == other {
+        return true;
+    }
+    match (SpatialRef::from_wkt(wkt), SpatialRef::from_wkt(other)) {
+        (Ok(srs), Ok(other)) => srs == other,
+        _ => false,
+    }
+}
+
+/// 检查两个输入是否位于同一网格, 只有两个输入都有地理参考信息时才比较
+pub fn check_grid(base: &str, overlay: &str, mismatch: GridMismatch) -> Result<()> {
+    if mismatch == GridMismatch::Ignore {
+        return Ok(());
+    }
+    let (Some(base_grid), Some(overlay_grid)) = (Grid::open(base), Grid::open(overlay)) else {
+        return Ok(());
+    };
+    let Some(difference) = base_grid.difference(&overlay_grid) else {
+        return Ok(());
+    };
+
+    let message = format!("{} and {} are not on the same grid: {}", base, overlay, difference);
+    match mismatch {
+        GridMismatch::Fail => Err(BlendError::Georef(format!("{}, use --grid-mismatch warn to blend anyway", message))),
+        _ => {
+            eprintln!("warning: {}", message);
+            Ok(())
+        }
+    }
+}
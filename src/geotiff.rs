@@ -0,0 +1,385 @@
+use std::collections::BTreeMap;
+
+use clap::ValueEnum;
+use gdal::raster::RasterCreationOption;
+use gdal::{Dataset, DriverManager, Metadata};
+use serde::{Deserialize, Serialize};
+
+use crate::blend::BlendImage;
+use crate::error::{BlendError, Result};
+
+/// GeoTIFF 的压缩方式
+#[derive(Debug, Clone, Copy, ValueEnum, Serialize, Deserialize, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum Compression {
+    None,
+    Lzw,
+    Deflate,
+    Zstd,
+    /// 有损, 只适用于 8 位数据
+    Jpeg,
+    /// 有损, 只适用于 8 位的 RGB/RGBA
+    Webp,
+}
+
+impl Compression {
+    pub fn gdal_name(&self) -> &'static str {
+        match self {
+            Self::None => "NONE",
+            Self::Lzw => "LZW",
+            Self::Deflate => "DEFLATE",
+            Self::Zstd => "ZSTD",
+            Self::Jpeg => "JPEG",
+            Self::Webp => "WEBP",
+        }
+    }
+
+    fn is_lossy(&self) -> bool {
+        matches!(self, Self::Jpeg | Self::Webp)
+    }
+}
+
+/// 无损压缩的预测器
+#[derive(Debug, Clone, Copy, ValueEnum, Serialize, Deserialize, PartialEq, Eq)]
+#[serde(rename_all = "kebab-case")]
+pub enum Predictor {
+    None,
+    /// 水平差分, 适合整数数据
+    Horizontal,
+    /// 适合浮点数据
+    FloatingPoint,
+}
+
+/// 是否写为 BigTIFF
+#[derive(Debug, Clone, Copy, ValueEnum, Serialize, Deserialize, PartialEq, Eq)]
+#[serde(rename_all = "kebab-case")]
+pub enum BigTiff {
+    Yes,
+    No,
+    IfNeeded,
+    /// 压缩后仍可能超过 4GB 时使用 BigTIFF
+    IfSafer,
+}
+
+impl BigTiff {
+    pub fn gdal_name(&self) -> &'static str {
+        match self {
+            Self::Yes => "YES",
+            Self::No => "NO",
+            Self::IfNeeded => "IF_NEEDED",
+            Self::IfSafer => "IF_SAFER",
+        }
+    }
+}
+
+/// 像素的颜色空间
+#[derive(Debug, Clone, Copy, ValueEnum, Serialize, Deserialize, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum Photometric {
+    #[value(name = "minisblack")]
+    MinIsBlack,
+    Rgb,
+    /// 只用于 JPEG 压缩, 压缩率更高
+    #[value(name = "ycbcr")]
+    YCbCr,
+}
+
+impl Photometric {
+    pub fn gdal_name(&self) -> &'static str {
+        match self {
+            Self::MinIsBlack => "MINISBLACK",
+            Self::Rgb => "RGB",
+            Self::YCbCr => "YCBCR",
+        }
+    }
+}
+
+/// 第四个波段作为透明度的方式
+#[derive(Debug, Clone, Copy, ValueEnum, Serialize, Deserialize, PartialEq, Eq)]
+#[serde(rename_all = "kebab-case")]
+pub enum Alpha {
+    Yes,
+    NonPremultiplied,
+    Premultiplied,
+    Unspecified,
+}
+
+impl Alpha {
+    pub fn gdal_name(&self) -> &'static str {
+        match self {
+            Self::Yes => "YES",
+            Self::NonPremultiplied => "NON-PREMULTIPLIED",
+            Self::Premultiplied => "PREMULTIPLIED",
+            Self::Unspecified => "UNSPECIFIED",
+        }
+    }
+}
+
+/// 生成金字塔(overview)的重采样方法
+#[derive(Debug, Clone, Copy, ValueEnum, Serialize, Deserialize, PartialEq, Eq)]
+#[serde(rename_all = "kebab-case")]
+pub enum Resampling {
+    Nearest,
+    Bilinear,
+    Cubic,
+    CubicSpline,
+    Lanczos,
+    Average,
+    Mode,
+}
+
+impl Resampling {
+    pub fn gdal_name(&self) -> &'static str {
+        match self {
+            Self::Nearest => "NEAREST",
+            Self::Bilinear => "BILINEAR",
+            Self::Cubic => "CUBIC",
+            Self::CubicSpline => "CUBICSPLINE",
+            Self::Lanczos => "LANCZOS",
+            Self::Average => "AVERAGE",
+            Self::Mode => "MODE",
+        }
+    }
+}
+
+/// GeoTIFF 的创建选项, 用于 GTiff 和 COG 驱动, 未设置的选项使用驱动的默认值
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct TiffOptions {
+    /// 保存为 Cloud Optimized GeoTIFF, 按瓦片组织并内置金字塔
+    pub cog: bool,
+    /// 未设置时 GTiff 沿用地理参考来源的压缩方式或 LZW, COG 为 DEFLATE
+    pub compress: Option<Compression>,
+    /// DEFLATE (1-12) 或 ZSTD (1-22) 的压缩级别
+    pub level: Option<u8>,
+    /// JPEG 或 WEBP 的质量 (1-100)
+    pub quality: Option<u8>,
+    pub predictor: Option<Predictor>,
+    /// GTiff 按瓦片组织, 设置 block_size 时自动启用, COG 总是按瓦片组织
+    pub tiled: bool,
+    /// 瓦片大小, 为 16 的倍数, COG 默认 512
+    pub block_size: Option<u32>,
+    pub photometric: Option<Photometric>,
+    pub alpha: Option<Alpha>,
+    /// 未设置时 COG 为 IF_SAFER
+    pub bigtiff: Option<BigTiff>,
+    /// COG 金字塔的重采样方法
+    pub overview_resampling: Resampling,
+    /// 直接传给 GDAL 驱动的创建选项, 优先于以上设置
+    pub co: BTreeMap<String, String>,
+}
+
+impl Default for TiffOptions {
+    fn default() -> Self {
+        Self {
+            cog: false,
+            compress: None,
+            level: None,
+            quality: None,
+            predictor: None,
+            tiled: false,
+            block_size: None,
+            photometric: None,
+            alpha: None,
+            bigtiff: None,
+            overview_resampling: Resampling::Average,
+            co: BTreeMap::new(),
+        }
+    }
+}
+
+impl TiffOptions {
+    /// 检查选项的组合, 避免 GDAL 忽略无效选项后静默输出
+    pub fn validate(&self) -> Result<()> {
+        let invalid = |message: String| Err(BlendError::InvalidParameter(message));
+        if let Some(block_size) = self.block_size {
+            if !(16..=4096).contains(&block_size) || block_size % 16 != 0 {
+                return invalid(format!("block size must be a multiple of 16 between 16 and 4096, got {}", block_size));
+            }
+        }
+        // 未设置压缩方式时 GTiff 沿用地理参考来源的压缩方式, 此时无法确定压缩级别和质量是否有效
+        let compress = match (self.compress, self.cog) {
+            (Some(compress), _) => Some(compress),
+            (None, true) => Some(Compression::Deflate),
+            (None, false) => None,
+        };
+        if compress.is_none() && (self.level.is_some() || self.quality.is_some()) {
+            return invalid("compression level and quality need an explicit compress".to_string());
+        }
+        if let (Some(level), Some(compress)) = (self.level, compress) {
+            let max_level = match compress {
+                Compression::Deflate => 12,
+                Compression::Zstd => 22,
+                _ => return invalid(format!("compression level only applies to DEFLATE and ZSTD, not {}", compress.gdal_name())),
+            };
+            if !(1..=max_level).contains(&level) {
+                return invalid(format!("{} level must be within 1-{}, got {}", compress.gdal_name(), max_level, level));
+            }
+        }
+        if let Some(quality) = self.quality {
+            if compress.is_some_and(|compress| !compress.is_lossy()) {
+                return invalid(format!("quality only applies to JPEG and WEBP, not {}", compress.unwrap().gdal_name()));
+            }
+            if !(1..=100).contains(&quality) {
+                return invalid(format!("quality must be within 1-100, got {}", quality));
+            }
+        }
+        if self.predictor.is_some() && compress.is_some_and(|compress| compress.is_lossy() || compress == Compression::None) {
+            return invalid(format!("predictor needs LZW, DEFLATE or ZSTD compression, not {}", compress.unwrap().gdal_name()));
+        }
+        if self.photometric == Some(Photometric::YCbCr) && compress != Some(Compression::Jpeg) {
+            return invalid("YCbCr photometric needs JPEG compression".to_string());
+        }
+        if self.cog && (self.photometric.is_some() || self.alpha.is_some()) {
+            return invalid("photometric and alpha are not supported by COG output".to_string());
+        }
+        Ok(())
+    }
+
+    /// GTiff 驱动的创建选项, source_compress 为地理参考来源的压缩方式
+    pub fn gtiff_options(&self, source_compress: Option<String>) -> Vec<(String, String)> {
+        let compress = match self.compress {
+            Some(compress) => compress.gdal_name().to_string(),
+            None => source_compress.unwrap_or("LZW".to_string()),
+        };
+        let mut options = vec![("COMPRESS".to_string(), compress.clone())];
+        if let Some(level) = self.level {
+            let key = if compress.eq_ignore_ascii_case("ZSTD") { "ZSTD_LEVEL" } else { "ZLEVEL" };
+            options.push((key.to_string(), level.to_string()));
+        }
+        if let Some(quality) = self.quality {
+            let key = if compress.eq_ignore_ascii_case("WEBP") { "WEBP_LEVEL" } else { "JPEG_QUALITY" };
+            options.push((key.to_string(), quality.to_string()));
+        }
+        if let Some(predictor) = self.predictor {
+            let value = match predictor {
+                Predictor::None => "1",
+                Predictor::Horizontal => "2",
+                Predictor::FloatingPoint => "3",
+            };
+            options.push(("PREDICTOR".to_string(), value.to_string()));
+        }
+        if self.tiled || self.block_size.is_some() {
+            options.push(("TILED".to_string(), "YES".to_string()));
+        }
+        if let Some(block_size) = self.block_size {
+            options.push(("BLOCKXSIZE".to_string(), block_size.to_string()));
+            options.push(("BLOCKYSIZE".to_string(), block_size.to_string()));
+        }
+        if let Some(photometric) = self.photometric {
+            options.push(("PHOTOMETRIC".to_string(), photometric.gdal_name().to_string()));
+        }
+        if let Some(alpha) = self.alpha {
+            options.push(("ALPHA".to_string(), alpha.gdal_name().to_string()));
+        }
+        if let Some(bigtiff) = self.bigtiff {
+            options.push(("BIGTIFF".to_string(), bigtiff.gdal_name().to_string()));
+        }
+        self.with_co(options)
+    }
+
+    /// COG 驱动的创建选项
+    pub fn cog_options(&self) -> Vec<(String, String)> {
+        let mut options = vec![
+            ("BLOCKSIZE".to_string(), self.block_size.unwrap_or(512).to_string()),
+            ("COMPRESS".to_string(), self.compress.unwrap_or(Compression::Deflate).gdal_name().to_string()),
+            ("BIGTIFF".to_string(), self.bigtiff.unwrap_or(BigTiff::IfSafer).gdal_name().to_string()),
+            ("OVERVIEWS".to_string(), "AUTO".to_string()),
+            ("RESAMPLING".to_string(), self.overview_resampling.gdal_name().to_string()),
+        ];
+        if let Some(level) = self.level {
+            options.push(("LEVEL".to_string(), level.to_string()));
+        }
+        if let Some(quality) = self.quality {
+            options.push(("QUALITY".to_string(), quality.to_string()));
+        }
+        if let Some(predictor) = self.predictor {
+            let value = match predictor {
+                Predictor::None => "NO",
+                Predictor::Horizontal => "STANDARD",
+                Predictor::FloatingPoint => "FLOATING_POINT",
+            };
+            options.push(("PREDICTOR".to_string(), value.to_string()));
+        }
+        self.with_co(options)
+    }
+
+    /// co 中的选项替换同名的选项, 键不区分大小写
+    fn with_co(&self, mut options: Vec<(String, String)>) -> Vec<(String, String)> {
+        for (key, value) in &self.co {
+            options.retain(|(k, _)| !k.eq_ignore_ascii_case(key));
+            options.push((key.to_uppercase(), value.clone()));
+        }
+        options
+    }
+
+    /// 按选项保存, write 以 GTiff 驱动写入给定路径;
+    /// 保存为 COG 时先不压缩地写入 GDAL 内存文件, 再由 COG 驱动复制并生成金字塔
+    pub fn save_with<F>(&self, output_path: &str, write: F) -> Result<()>
+    where
+        F: FnOnce(&str, &TiffOptions) -> Result<()>,
+    {
+        self.validate()?;
+        if !self.cog {
+            return write(output_path, self);
+        }
+
+        let mem_path = BlendImage::mem_path();
+        let intermediate = TiffOptions {
+            compress: Some(Compression::None),
+            ..TiffOptions::default()
+        };
+        let result = write(&mem_path, &intermediate).and_then(|_| self.translate_cog(&mem_path, output_path));
+        let _ = gdal::vsi::unlink_mem_file(&mem_path);
+        result?;
+        validate_cog(output_path)
+    }
+
+    /// 按选项编码为字节流
+    pub fn encode_with<F>(&self, write: F) -> Result<Vec<u8>>
+    where
+        F: FnOnce(&str, &TiffOptions) -> Result<()>,
+    {
+        let mem_path = BlendImage::mem_path();
+        self.save_with(&mem_path, write)?;
+        let bytes = gdal::vsi::get_vsi_mem_file_bytes_owned(&mem_path);
+        let _ = gdal::vsi::unlink_mem_file(&mem_path);
+        Ok(bytes?)
+    }
+
+    fn translate_cog(&self, source_path: &str, output_path: &str) -> Result<()> {
+        let driver = DriverManager::get_driver_by_name("COG")
+            .map_err(|_| BlendError::UnsupportedFormat("COG output needs GDAL 3.1 or later".to_string()))?;
+        let options = self.cog_options();
+        let source = Dataset::open(source_path)?;
+        let output = source.create_copy(&driver, output_path, &creation_options(&options))?;
+        output.close()?;
+        source.close()?;
+        Ok(())
+    }
+}
+
+/// 转为 GDAL 的创建选项
+pub(crate) fn creation_options(options: &[(String, String)]) -> Vec<RasterCreationOption<'_>> {
+    options
+        .iter()
+        .map(|(key, value)| RasterCreationOption { key, value })
+        .collect()
+}
+
+/// 解析 --co 的 KEY=VALUE
+pub fn parse_creation_option(s: &str) -> Result<(String, String), String> {
+    match s.split_once('=') {
+        Some((key, value)) if !key.trim().is_empty() => Ok((key.trim().to_uppercase(), value.trim().to_string())),
+        _ => Err(format!("expected KEY=VALUE, got '{}'", s)),
+    }
+}
+
+/// 检查文件是否为 COG 布局(瓦片、金字塔和数据的排列顺序), 由 GDAL 的 LAYOUT 元数据判断
+pub fn validate_cog(path: &str) -> Result<()> {
+    let dataset = Dataset::open(path)?;
+    match dataset.metadata_item("LAYOUT", "IMAGE_STRUCTURE") {
+        Some(layout) if layout.eq_ignore_ascii_case("COG") => Ok(()),
+        _ => Err(BlendError::UnsupportedFormat(format!("{} is not a cloud optimized GeoTIFF", path))),
+    }
+}
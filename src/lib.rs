@@ -2,5 +2,23 @@ pub mod blend;
 pub mod argparse;
 pub mod core;
 pub mod utils;
-pub mod blend_image;
-pub mod adjuster;
\ No newline at end of file
+pub mod adjuster;
+pub mod batch;
+pub mod output;
+pub mod pipeline;
+pub mod error;
+pub mod blend_op;
+pub mod recipe;
+pub mod expr;
+pub mod calc;
+pub mod bands;
+pub mod geotiff;
+pub mod tiles;
+pub mod tilestore;
+pub mod serve;
+pub mod service;
+pub mod worldfile;
+pub mod georef;
+pub mod terrain;
+pub mod sun;
+pub mod texture;
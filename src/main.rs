@@ -1,20 +1,11 @@
-mod blend;
-mod argparse;
-mod core;
-mod utils;
-mod blend_image;
-mod adjuster;
 use clap::Parser;
-use core::options_post_processing;
-use anyhow::Result;
-use argparse::ArgParse;
-use blend::BlendManager;
-fn main() -> Result<()>{
+use blend_images::argparse::ArgParse;
+use blend_images::core::run;
+use blend_images::error::exit_code;
+fn main() {
     let args = ArgParse::parse();
-    options_post_processing(&args)?;
-
-    println!("args: {:?}", args);
-    BlendManager::blend_manger(&args)?;
-    println!("done");
-    Ok(())
+    if let Err(e) = run(&args) {
+        eprintln!("Error: {}", e);
+        std::process::exit(exit_code(&e));
+    }
 }
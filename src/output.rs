@@ -0,0 +1,269 @@
+use std::io::Write;
+use std::path::Path;
+
+use crate::argparse::Format;
+use crate::blend::BlendImage;
+use crate::error::{BlendError, Result};
+use crate::expr::EXPR_PREFIX;
+use crate::geotiff::TiffOptions;
+use crate::utils::makedirs;
+use crate::worldfile::Georeference;
+
+/// 单张混合时默认的输出文件名, 与底图同名
+pub const DEFAULT_TEMPLATE: &str = "{stem}.{format}";
+
+/// 批量混合时默认的输出文件名, 避免同一底图的多种混合模式互相覆盖
+pub const DEFAULT_BATCH_TEMPLATE: &str = "{stem}_{mode}.{format}";
+
+/// -o 指定的输出位置
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum OutputTarget {
+    /// 输出文件夹, 文件名由输出文件名模板生成
+    Folder(String),
+    /// 输出文件, 扩展名决定编码格式
+    File(String),
+    /// 编码后写到标准输出
+    Stdout,
+}
+
+impl OutputTarget {
+    /// `-` 为标准输出, 已存在的文件夹、以 / 结尾或没有扩展名的路径为文件夹, 其余为文件
+    pub fn parse(output: &str) -> OutputTarget {
+        if output == "-" {
+            return OutputTarget::Stdout;
+        }
+        let path = Path::new(output);
+        if output.ends_with('/') || output.ends_with(std::path::MAIN_SEPARATOR) || path.is_dir() || path.extension().is_none() {
+            OutputTarget::Folder(output.to_string())
+        } else {
+            OutputTarget::File(output.to_string())
+        }
+    }
+}
+
+/// 输出设置: 输出位置、格式、文件名模板和覆盖策略
+#[derive(Debug, Clone)]
+pub struct OutputSpec {
+    pub target: OutputTarget,
+    /// 输出文件扩展名不是已知格式或输出到标准输出时使用的格式
+    pub format: Format,
+    pub template: String,
+    pub exists_policy: ExistsPolicy,
+    /// 输出文件夹下的子文件夹, 批量混合时保持输入的目录结构
+    pub relative_dir: Option<String>,
+    /// tiff 输出的创建选项
+    pub tiff: TiffOptions,
+}
+
+impl OutputSpec {
+    pub fn new(target: OutputTarget) -> Self {
+        Self {
+            target,
+            format: Format::PNG,
+            template: DEFAULT_TEMPLATE.to_string(),
+            exists_policy: ExistsPolicy::Overwrite,
+            relative_dir: None,
+            tiff: TiffOptions::default(),
+        }
+    }
+
+    pub fn with_format(mut self, format: Format) -> Self {
+        self.format = format;
+        self
+    }
+
+    pub fn with_template(mut self, template: &str) -> Self {
+        self.template = template.to_string();
+        self
+    }
+
+    pub fn with_exists_policy(mut self, exists_policy: ExistsPolicy) -> Self {
+        self.exists_policy = exists_policy;
+        self
+    }
+
+    pub fn with_relative_dir(mut self, relative_dir: Option<String>) -> Self {
+        self.relative_dir = relative_dir;
+        self
+    }
+
+    pub fn with_tiff(mut self, tiff: TiffOptions) -> Self {
+        self.tiff = tiff;
+        self
+    }
+
+    /// 生成保存路径, 输出到标准输出时返回 None
+    pub fn save_path(&self, image: &str, image2: &str, mode: &str, index: usize) -> Result<Option<String>> {
+        match &self.target {
+            OutputTarget::Folder(folder) => {
+                let format = self.format.format_name();
+                let filename = render_template(&self.template, &TemplateContext {
+                    image,
+                    image2,
+                    mode,
+                    index,
+                    format: &format,
+                })?;
+
+                let mut save_path = Path::new(folder).to_path_buf();
+                if let Some(relative_dir) = &self.relative_dir {
+                    save_path.push(relative_dir);
+                }
+                Ok(Some(save_path.join(filename).to_string_lossy().into_owned()))
+            }
+            OutputTarget::File(file) => Ok(Some(file.clone())),
+            OutputTarget::Stdout => Ok(None),
+        }
+    }
+
+    /// 保存到文件, 扩展名不是已知格式时使用 format, tiff 通过 GDAL 保存为 GeoTIFF 或 COG,
+    /// 其他格式的地理参考信息写入 world file 和 .aux.xml
+    pub fn save(&self, image: BlendImage, save_path: &str, georef_image: Option<&str>) -> Result<()> {
+        if let Some(parent) = Path::new(save_path).parent().and_then(|p| p.to_str()) {
+            if !parent.is_empty() {
+                makedirs(parent)?;
+            }
+        }
+
+        let format = Format::from_path(save_path).unwrap_or(self.format.clone());
+        match format {
+            Format::TIFF => BlendImage::save_tiff_with(image, save_path, georef_image, &self.tiff),
+            _ => {
+                let size = (image.get_width(), image.get_height());
+                let georeference = match georef_image {
+                    Some(georef_image) => Georeference::open(georef_image, size)?,
+                    None => None,
+                };
+                BlendImage::save_image(image, save_path, &format)?;
+                match georeference {
+                    Some(georeference) => georeference.write_sidecars(save_path, &format),
+                    None => Ok(()),
+                }
+            }
+        }
+    }
+
+    /// 按 format 编码
+    pub fn encode(&self, image: BlendImage, georef_image: Option<&str>) -> Result<Vec<u8>> {
+        match self.format {
+            Format::TIFF => BlendImage::encode_tiff_with(image, georef_image, &self.tiff),
+            _ => BlendImage::encode_image(image, &self.format),
+        }
+    }
+
+    /// 按 format 编码后写入 writer, 用于输出到标准输出
+    pub fn write<W: Write>(&self, image: BlendImage, writer: &mut W, georef_image: Option<&str>) -> Result<()> {
+        let bytes = self.encode(image, georef_image)?;
+        writer.write_all(&bytes)?;
+        writer.flush()?;
+        Ok(())
+    }
+}
+
+/// 输出文件已存在时的处理方式
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ExistsPolicy {
+    Overwrite,
+    Skip,
+    Fail,
+}
+
+/// 输出文件名模板中占位符的取值
+pub struct TemplateContext<'a> {
+    pub image: &'a str,
+    pub image2: &'a str,
+    pub mode: &'a str,
+    pub index: usize,
+    pub format: &'a str,
+}
+
+/// 渲染输出文件名模板
+///
+/// 支持的占位符: {stem}, {stem2}, {mode}, {index}, {format}.
+/// 表达式混合模式的 {mode} 为 expr, 渲染结果没有扩展名时追加 .{format}.
+pub fn render_template(template: &str, context: &TemplateContext) -> Result<String> {
+    let mut rendered = String::with_capacity(template.len());
+    let mut rest = template;
+
+    while let Some(start) = rest.find('{') {
+        rendered.push_str(&rest[..start]);
+        let end = rest[start..]
+            .find('}')
+            .ok_or_else(|| BlendError::InvalidParameter(format!("Unclosed placeholder in output template: {}", template)))?;
+        let name = &rest[start + 1..start + end];
+        let value = match name {
+            "stem" => file_stem(context.image),
+            "stem2" => file_stem(context.image2),
+            "mode" => mode_label(context.mode),
+            "index" => context.index.to_string(),
+            "format" => context.format.to_string(),
+            _ => return Err(BlendError::InvalidParameter(format!("Unknown placeholder {{{}}} in output template: {}", name, template))),
+        };
+        rendered.push_str(&value);
+        rest = &rest[start + end + 1..];
+    }
+    rendered.push_str(rest);
+
+    if rendered.is_empty() {
+        return Err(BlendError::InvalidParameter(format!("Output template renders an empty file name: {}", template)));
+    }
+    if Path::new(&rendered).extension().is_none() {
+        rendered = format!("{}.{}", rendered, context.format);
+    }
+    Ok(rendered)
+}
+
+/// 表达式中的 / : * 等字符不能用于文件名, 表达式统一为 expr, 自定义混合模式名中的这类字符替换为 _
+fn mode_label(mode: &str) -> String {
+    if mode.starts_with(EXPR_PREFIX) {
+        return "expr".to_string();
+    }
+    mode.chars()
+        .map(|c| if c.is_alphanumeric() || c == '-' || c == '_' || c == '.' { c } else { '_' })
+        .collect()
+}
+
+/// 按 policy 检查瓦片等整体写入的输出文件夹, 文件夹不为空时视为已存在, 返回 false 表示应跳过
+pub fn check_folder_exists(folder: &str, policy: ExistsPolicy) -> Result<bool> {
+    let empty = match std::fs::read_dir(folder) {
+        Ok(mut entries) => entries.next().is_none(),
+        Err(_) => true,
+    };
+    if empty {
+        return Ok(true);
+    }
+    match policy {
+        ExistsPolicy::Overwrite => Ok(true),
+        ExistsPolicy::Skip => Ok(false),
+        ExistsPolicy::Fail => Err(BlendError::Io(std::io::Error::new(
+            std::io::ErrorKind::AlreadyExists,
+            format!("Output folder is not empty: {}", folder),
+        ))),
+    }
+}
+
+/// 按 policy 检查输出文件, 返回 false 表示应跳过
+pub fn check_exists(save_path: &str, policy: ExistsPolicy) -> Result<bool> {
+    if !Path::new(save_path).exists() {
+        return Ok(true);
+    }
+    match policy {
+        ExistsPolicy::Overwrite => Ok(true),
+        ExistsPolicy::Skip => Ok(false),
+        ExistsPolicy::Fail => Err(BlendError::Io(std::io::Error::new(
+            std::io::ErrorKind::AlreadyExists,
+            format!("Output file already exists: {}", save_path),
+        ))),
+    }
+}
+
+fn file_stem(path: &str) -> String {
+    if path == "-" {
+        return "stdin".to_string();
+    }
+    Path::new(path)
+        .file_stem()
+        .and_then(|stem| stem.to_str())
+        .unwrap_or("")
+        .to_string()
+}
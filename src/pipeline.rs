@@ -0,0 +1,361 @@
+use std::io::Write;
+
+use image::DynamicImage;
+use serde::{Deserialize, Serialize};
+
+use crate::adjuster::{AdjustStep, AdjusterChain, BrightnessGammaContrastAdjuster, HueSaturationAdjuster};
+use crate::bands::BandMapping;
+use crate::blend::{BlendImage, BlendManager};
+use crate::blend_op::blend_op;
+use crate::error::{BlendError, Result};
+use crate::georef::{check_grid, GeorefSource, GridMismatch};
+use crate::output::{check_exists, OutputSpec};
+use crate::recipe::Recipe;
+
+/// 图层来源
+#[derive(Debug, Clone)]
+pub enum LayerSource {
+    /// 文件路径, `-` 表示标准输入
+    Path(String),
+    /// 编码后的图像字节, 格式由文件头推断
+    Bytes(Vec<u8>),
+    /// 已解码的图像
+    Image(BlendImage),
+    /// 多波段影像, 按波段映射和拉伸读取为 RGBA
+    Bands(String, BandMapping),
+}
+
+impl LayerSource {
+    pub fn load(&self) -> Result<BlendImage> {
+        match self {
+            Self::Path(path) => BlendImage::open_image(path),
+            Self::Bytes(bytes) => BlendImage::from_bytes(bytes),
+            Self::Image(image) => Ok(image.clone()),
+            Self::Bands(path, mapping) => mapping.read(path),
+        }
+    }
+
+    /// 可以提供地理参考信息的文件路径
+    pub fn georef_path(&self) -> Option<&str> {
+        match self {
+            Self::Path(path) if path != "-" => Some(path),
+            Self::Bands(path, _) => Some(path),
+            _ => None,
+        }
+    }
+
+    /// 用于输出文件名模板的名称, 内存中的图层统一为 memory
+    pub fn name(&self) -> &str {
+        match self {
+            Self::Path(path) | Self::Bands(path, _) => path,
+            _ => "memory",
+        }
+    }
+}
+
+impl From<&str> for LayerSource {
+    fn from(path: &str) -> Self {
+        Self::Path(path.to_string())
+    }
+}
+
+impl From<String> for LayerSource {
+    fn from(path: String) -> Self {
+        Self::Path(path)
+    }
+}
+
+impl From<Vec<u8>> for LayerSource {
+    fn from(bytes: Vec<u8>) -> Self {
+        Self::Bytes(bytes)
+    }
+}
+
+impl From<BlendImage> for LayerSource {
+    fn from(image: BlendImage) -> Self {
+        Self::Image(image)
+    }
+}
+
+impl From<DynamicImage> for LayerSource {
+    fn from(image: DynamicImage) -> Self {
+        Self::Image(BlendImage::from(image))
+    }
+}
+
+/// 对底图的增强参数, 默认不做任何调整
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct Adjustments {
+    pub brightness: f32,
+    pub contrast: f32,
+    pub gamma: f32,
+    pub saturation: f32,
+    pub colorize: bool,
+    pub colorize_color: Option<[u8; 3]>,
+    pub colorize_strength: u8,
+    /// 在上面的亮度对比度、饱和度和着色之后按顺序执行的调整
+    pub adjust: Vec<AdjustStep>,
+}
+
+impl Default for Adjustments {
+    fn default() -> Self {
+        Self {
+            brightness: 0.0,
+            contrast: 0.0,
+            gamma: 1.0,
+            saturation: 0.0,
+            colorize: false,
+            colorize_color: None,
+            colorize_strength: 100,
+            adjust: Vec::new(),
+        }
+    }
+}
+
+impl Adjustments {
+    /// 调整链: 亮度对比度伽马, 饱和度与着色, 然后是 adjust 中的各步, 跳过不改变像素的调整
+    pub fn adjuster_chain(&self) -> Result<AdjusterChain> {
+        let mut chain = AdjusterChain::new();
+        chain.push(BrightnessGammaContrastAdjuster::new(self.brightness, self.contrast, self.gamma));
+        let colorize_color = self.colorize_color.map(|color| color.to_vec());
+        chain.push(HueSaturationAdjuster::new(
+            self.saturation,
+            self.colorize,
+            &colorize_color,
+            self.colorize_strength,
+        ));
+        for step in &self.adjust {
+            chain.push_boxed(step.build()?);
+        }
+        Ok(chain)
+    }
+}
+
+/// 执行混合任务的结果
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum JobOutcome {
+    /// 已保存到文件
+    Saved(String),
+    /// 输出文件已存在, 按覆盖策略跳过
+    Skipped(String),
+    /// 已写到标准输出
+    Written,
+}
+
+/// 一次混合任务: 两个图层、混合模式、增强参数和输出设置
+///
+/// 不依赖命令行参数和全局状态, 可以在多个线程中同时执行.
+///
+/// ```no_run
+/// use blend_images::pipeline::{Adjustments, BlendJob};
+///
+/// let job = BlendJob::builder("dem.tif", "hillshade.tif")
+///     .blend_mode("multiply")
+///     .adjustments(Adjustments { saturation: 0.5, ..Default::default() })
+///     .build()?;
+/// let image = job.run()?;
+/// # Ok::<(), blend_images::error::BlendError>(())
+/// ```
+#[derive(Debug, Clone)]
+pub struct BlendJob {
+    base: LayerSource,
+    top: LayerSource,
+    blend_mode: String,
+    swap_layers: bool,
+    adjustments: Adjustments,
+    georef_from: GeorefSource,
+    grid_mismatch: GridMismatch,
+    output: Option<OutputSpec>,
+    index: usize,
+}
+
+impl BlendJob {
+    pub fn builder(base: impl Into<LayerSource>, top: impl Into<LayerSource>) -> BlendJobBuilder {
+        BlendJobBuilder::new(base, top)
+    }
+
+    pub fn base(&self) -> &LayerSource {
+        &self.base
+    }
+
+    pub fn top(&self) -> &LayerSource {
+        &self.top
+    }
+
+    pub fn blend_mode(&self) -> &str {
+        &self.blend_mode
+    }
+
+    pub fn swap_layers(&self) -> bool {
+        self.swap_layers
+    }
+
+    pub fn adjustments(&self) -> &Adjustments {
+        &self.adjustments
+    }
+
+    pub fn output(&self) -> Option<&OutputSpec> {
+        self.output.as_ref()
+    }
+
+    /// 提供输出的地理参考信息和元数据的文件路径, 按 georef_from 选择输入, 与是否交换图层无关
+    pub fn georef_path(&self) -> Option<&str> {
+        match self.georef_from {
+            GeorefSource::Base => self.base.georef_path(),
+            GeorefSource::Overlay => self.top.georef_path(),
+        }
+    }
+
+    /// 读取图层, 增强底图后混合, 返回混合结果
+    pub fn run(&self) -> Result<BlendImage> {
+        if let (Some(base), Some(top)) = (self.base.georef_path(), self.top.georef_path()) {
+            check_grid(base, top, self.grid_mismatch)?;
+        }
+        let mut image = self.base.load()?;
+        let image2 = self.top.load()?;
+
+        BlendManager::enchance(&mut image, &self.adjustments)?;
+        let (mut image, image2) = BlendManager::swap_layers(image, image2, self.swap_layers);
+        BlendManager::blend(&mut image, &image2, &self.blend_mode)?;
+        Ok(image)
+    }
+
+    /// 保存路径, 输出到标准输出时为 None
+    pub fn save_path(&self) -> Result<Option<String>> {
+        let output = self.output_spec()?;
+        output.save_path(self.base.name(), self.top.name(), &self.blend_mode, self.index)
+    }
+
+    /// 混合后按输出设置编码
+    pub fn encode(&self) -> Result<Vec<u8>> {
+        let output = self.output_spec()?;
+        let image = self.run()?;
+        output.encode(image, self.georef_path())
+    }
+
+    /// 混合并按输出设置保存到文件或写到标准输出
+    pub fn execute(&self) -> Result<JobOutcome> {
+        let output = self.output_spec()?;
+        let save_path = self.save_path()?;
+        if let Some(save_path) = &save_path {
+            if !check_exists(save_path, output.exists_policy)? {
+                return Ok(JobOutcome::Skipped(save_path.to_string()));
+            }
+        }
+
+        let image = self.run()?;
+        match save_path {
+            Some(save_path) => {
+                output.save(image, &save_path, self.georef_path())?;
+                Ok(JobOutcome::Saved(save_path))
+            }
+            None => {
+                let mut stdout = std::io::stdout().lock();
+                output.write(image, &mut stdout, self.georef_path())?;
+                stdout.flush()?;
+                Ok(JobOutcome::Written)
+            }
+        }
+    }
+
+    fn output_spec(&self) -> Result<&OutputSpec> {
+        self.output
+            .as_ref()
+            .ok_or_else(|| BlendError::InvalidParameter("The blend job has no output specified".to_string()))
+    }
+}
+
+pub struct BlendJobBuilder {
+    base: LayerSource,
+    top: LayerSource,
+    blend_mode: String,
+    swap_layers: bool,
+    adjustments: Adjustments,
+    georef_from: GeorefSource,
+    grid_mismatch: GridMismatch,
+    output: Option<OutputSpec>,
+    index: usize,
+}
+
+impl BlendJobBuilder {
+    pub fn new(base: impl Into<LayerSource>, top: impl Into<LayerSource>) -> Self {
+        Self {
+            base: base.into(),
+            top: top.into(),
+            blend_mode: "overlay".to_string(),
+            swap_layers: false,
+            adjustments: Adjustments::default(),
+            georef_from: GeorefSource::Base,
+            grid_mismatch: GridMismatch::Warn,
+            output: None,
+            index: 1,
+        }
+    }
+
+    pub fn blend_mode(mut self, blend_mode: &str) -> Self {
+        self.blend_mode = blend_mode.to_string();
+        self
+    }
+
+    pub fn swap_layers(mut self, swap_layers: bool) -> Self {
+        self.swap_layers = swap_layers;
+        self
+    }
+
+    pub fn adjustments(mut self, adjustments: Adjustments) -> Self {
+        self.adjustments = adjustments;
+        self
+    }
+
+    pub fn georef_from(mut self, georef_from: GeorefSource) -> Self {
+        self.georef_from = georef_from;
+        self
+    }
+
+    /// 两个输入都有地理参考信息时, 坐标系或仿射变换不一致的处理方式
+    pub fn grid_mismatch(mut self, grid_mismatch: GridMismatch) -> Self {
+        self.grid_mismatch = grid_mismatch;
+        self
+    }
+
+    /// 使用 recipe 中的混合模式、是否交换图层和增强参数, recipe 中没有设置的保持不变
+    pub fn recipe(mut self, recipe: &Recipe) -> Self {
+        if let Some(mode) = &recipe.mode {
+            self.blend_mode = mode.to_string();
+        }
+        self.swap_layers |= recipe.swap_layers;
+        if let Some(adjustments) = &recipe.adjustments {
+            self.adjustments = adjustments.clone();
+        }
+        self
+    }
+
+    pub fn output(mut self, output: OutputSpec) -> Self {
+        self.output = Some(output);
+        self
+    }
+
+    /// 输出文件名模板中的 {index}
+    pub fn index(mut self, index: usize) -> Self {
+        self.index = index;
+        self
+    }
+
+    pub fn build(self) -> Result<BlendJob> {
+        let blend_op = blend_op(&self.blend_mode)?;
+        self.adjustments.adjuster_chain()?;
+
+        Ok(BlendJob {
+            base: self.base,
+            top: self.top,
+            blend_mode: blend_op.name().to_string(),
+            swap_layers: self.swap_layers,
+            adjustments: self.adjustments,
+            georef_from: self.georef_from,
+            grid_mismatch: self.grid_mismatch,
+            output: self.output,
+            index: self.index,
+        })
+    }
+}
@@ -0,0 +1,39 @@
+use std::fs::File;
+
+use serde::{Deserialize, Serialize};
+
+use crate::blend_op::blend_op;
+use crate::error::{BlendError, Result};
+use crate::geotiff::TiffOptions;
+use crate::pipeline::Adjustments;
+
+/// 混合配方, 以 json 保存一组混合参数, 通过 --recipe 使用
+///
+/// ```json
+/// { "mode": "multiply", "swap_layers": false, "adjustments": { "gamma": 1.2 } }
+/// ```
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct Recipe {
+    /// 混合模式, 包括通过 register_blend_op 注册的自定义混合模式
+    pub mode: Option<String>,
+    pub swap_layers: bool,
+    /// 对底图的增强参数, 命令行中给出的增强参数优先, --adjust 接在 adjust 之后
+    pub adjustments: Option<Adjustments>,
+    /// tiff 输出的创建选项, 命令行中给出的选项优先
+    pub tiff: Option<TiffOptions>,
+}
+
+impl Recipe {
+    pub fn load(path: &str) -> Result<Recipe> {
+        let recipe: Recipe = serde_json::from_reader(File::open(path)?)
+            .map_err(|e| BlendError::InvalidParameter(format!("Invalid recipe {}: {}", path, e)))?;
+        if let Some(mode) = &recipe.mode {
+            blend_op(mode)?;
+        }
+        if let Some(tiff) = &recipe.tiff {
+            tiff.validate()?;
+        }
+        Ok(recipe)
+    }
+}
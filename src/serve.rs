@@ -0,0 +1,455 @@
+use std::collections::hash_map::DefaultHasher;
+use std::fs;
+use std::hash::{Hash, Hasher};
+use std::io;
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::time::SystemTime;
+
+use gdal::raster::GdalDataType;
+use gdal::spatial_ref::{CoordTransform, SpatialRef};
+use gdal::Dataset;
+use serde::Serialize;
+use tiny_http::{Header, Method, Request, Response, Server};
+
+use crate::adjuster::AdjustStep;
+use crate::argparse::Format;
+use crate::bands::BandMapping;
+use crate::blend::BlendImage;
+use crate::blend_op::blend_op;
+use crate::error::{BlendError, Result};
+use crate::pipeline::{Adjustments, BlendJob};
+use crate::tiles::{TileCoord, MAX_ZOOM};
+use crate::utils::makedirs;
+
+/// 统计拉伸范围时读取的缩小影像的最大边长
+const STRETCH_SAMPLE_SIZE: usize = 1024;
+
+/// 缓存瓦片临时文件的序号, 避免多个线程写同一瓦片时使用相同的临时文件
+static CACHE_TEMP_INDEX: AtomicUsize = AtomicUsize::new(0);
+
+/// 瓦片服务的图层, 每个瓦片只读取 GeoTIFF 中与瓦片相交的窗口
+#[derive(Debug, Clone)]
+pub struct WindowSource {
+    path: String,
+    mapping: Option<BandMapping>,
+    /// 按波段映射读取时 R G B 的拉伸范围, 对整幅影像统计
+    ranges: Vec<(f64, f64)>,
+}
+
+impl WindowSource {
+    /// 没有波段映射时, 1 个波段为灰度, 2 个为灰度加透明度, 3 个为 RGB, 4 个及以上为 RGBA
+    pub fn open(path: &str, mapping: Option<BandMapping>) -> Result<Self> {
+        let dataset = Dataset::open(path)?;
+        dataset
+            .geo_transform()
+            .map_err(|e| BlendError::Georef(format!("{} has no geotransform: {}", path, e)))?;
+        let ranges = match &mapping {
+            Some(mapping) => mapping.sample_ranges(&dataset, STRETCH_SAMPLE_SIZE)?,
+            None => Vec::new(),
+        };
+        Ok(Self {
+            path: path.to_string(),
+            mapping,
+            ranges,
+        })
+    }
+
+    fn reader(&self) -> Result<WindowReader<'_>> {
+        let dataset = Dataset::open(&self.path)?;
+        let source = dataset.spatial_ref()?;
+        let mercator = SpatialRef::from_epsg(3857)?;
+        for srs in [&source, &mercator] {
+            srs.set_axis_mapping_strategy(gdal_sys::OSRAxisMappingStrategy::OAMS_TRADITIONAL_GIS_ORDER);
+        }
+        let to_source = CoordTransform::new(&mercator, &source)?;
+
+        let (rgb, alpha) = match &self.mapping {
+            Some(mapping) => (mapping.rgb, mapping.alpha),
+            None => match dataset.raster_count() {
+                1 => ([1, 1, 1], None),
+                2 => ([1, 1, 1], Some(2)),
+                3 => ([1, 2, 3], None),
+                _ => ([1, 2, 3], Some(4)),
+            },
+        };
+        Ok(WindowReader {
+            source: self,
+            dataset,
+            to_source,
+            rgb,
+            alpha,
+        })
+    }
+}
+
+/// 一个线程中打开的图层
+struct WindowReader<'a> {
+    source: &'a WindowSource,
+    dataset: Dataset,
+    to_source: CoordTransform,
+    rgb: [isize; 3],
+    alpha: Option<isize>,
+}
+
+impl WindowReader<'_> {
+    /// 读取瓦片范围内的窗口并重采样为 size x size 的 RGBA, 没有数据的像素透明
+    fn read_tile(&self, tile: TileCoord, size: usize) -> Result<BlendImage> {
+        let (width, height) = self.dataset.raster_size();
+        let transform = self.dataset.geo_transform()?;
+        let [min_x, _, max_x, max_y] = tile.bounds();
+        let resolution = (max_x - min_x) / size as f64;
+
+        // 瓦片像素中心在源影像中的像素坐标
+        let mut xs = Vec::with_capacity(size * size);
+        let mut ys = Vec::with_capacity(size * size);
+        for row in 0..size {
+            for column in 0..size {
+                xs.push(min_x + (column as f64 + 0.5) * resolution);
+                ys.push(max_y - (row as f64 + 0.5) * resolution);
+            }
+        }
+        let valid = self.transform(&mut xs, &mut ys);
+        let det = transform[1] * transform[5] - transform[2] * transform[4];
+        let pixels: Vec<Option<(f64, f64)>> = xs
+            .iter()
+            .zip(&ys)
+            .zip(&valid)
+            .map(|((x, y), valid)| {
+                let (dx, dy) = (x - transform[0], y - transform[3]);
+                let px = (transform[5] * dx - transform[2] * dy) / det;
+                let py = (transform[1] * dy - transform[4] * dx) / det;
+                let inside = *valid && px >= 0.0 && py >= 0.0 && px < width as f64 && py < height as f64;
+                inside.then_some((px, py))
+            })
+            .collect();
+
+        let mut raw_pixels = vec![0u8; size * size * 4];
+        let covered = pixels.iter().flatten();
+        let (x0, y0, x1, y1) = covered.fold((f64::MAX, f64::MAX, f64::MIN, f64::MIN), |(x0, y0, x1, y1), (px, py)| {
+            (x0.min(*px), y0.min(*py), x1.max(*px), y1.max(*py))
+        });
+        if x0 > x1 {
+            return BlendImage::from_raw(size as u32, size as u32, raw_pixels);
+        }
+
+        // 窗口大于瓦片时缩小读取, 有金字塔时由 GDAL 使用金字塔
+        let (window_x, window_y) = (x0.floor() as isize, y0.floor() as isize);
+        let window = ((x1.floor() as isize - window_x + 1) as usize, (y1.floor() as isize - window_y + 1) as usize);
+        let buffer = (window.0.min(size * 2), window.1.min(size * 2));
+        let read_band = |index: isize| -> Result<(Vec<f64>, Option<f64>, GdalDataType)> {
+            let band = self.dataset.rasterband(index)?;
+            let data = band.read_as::<f64>((window_x, window_y), window, buffer, None)?.data;
+            Ok((data, band.no_data_value(), band.band_type()))
+        };
+        let sample = |px: f64, py: f64| -> usize {
+            let bx = (((px - window_x as f64) / window.0 as f64 * buffer.0 as f64) as usize).min(buffer.0 - 1);
+            let by = (((py - window_y as f64) / window.1 as f64 * buffer.1 as f64) as usize).min(buffer.1 - 1);
+            by * buffer.0 + bx
+        };
+
+        for (pixel, position) in raw_pixels.chunks_exact_mut(4).zip(&pixels) {
+            if position.is_some() {
+                pixel[3] = 255;
+            }
+        }
+        for (channel, index) in self.rgb.iter().enumerate() {
+            let (data, nodata, band_type) = read_band(*index)?;
+            let range = self.source.ranges.get(channel).copied();
+            for (pixel, position) in raw_pixels.chunks_exact_mut(4).zip(&pixels) {
+                let Some((px, py)) = position else { continue };
+                let value = data[sample(*px, *py)];
+                if value.is_nan() || Some(value) == nodata {
+                    pixel[3] = 0;
+                    continue;
+                }
+                pixel[channel] = to_byte(value, range, &band_type);
+            }
+        }
+        if let Some(index) = self.alpha {
+            let (data, _, band_type) = read_band(index)?;
+            for (pixel, position) in raw_pixels.chunks_exact_mut(4).zip(&pixels) {
+                if let Some((px, py)) = position {
+                    pixel[3] = pixel[3].min(to_byte(data[sample(*px, *py)], None, &band_type));
+                }
+            }
+        }
+        BlendImage::from_raw(size as u32, size as u32, raw_pixels)
+    }
+
+    /// 转换到源坐标系, 整体转换失败时逐点转换, 返回每个点是否转换成功
+    fn transform(&self, xs: &mut [f64], ys: &mut [f64]) -> Vec<bool> {
+        let (original_xs, original_ys) = (xs.to_vec(), ys.to_vec());
+        if self.to_source.transform_coords(xs, ys, &mut []).is_ok() {
+            return vec![true; xs.len()];
+        }
+        (0..xs.len())
+            .map(|i| {
+                let (mut x, mut y) = ([original_xs[i]], [original_ys[i]]);
+                let ok = self.to_source.transform_coords(&mut x, &mut y, &mut []).is_ok();
+                (xs[i], ys[i]) = (x[0], y[0]);
+                ok
+            })
+            .collect()
+    }
+}
+
+/// 波段值转为 0-255: 有拉伸范围时线性拉伸, 16 位数据按比例缩小, 其余截断
+fn to_byte(value: f64, range: Option<(f64, f64)>, band_type: &GdalDataType) -> u8 {
+    let value = match (range, band_type) {
+        (Some((min, max)), _) if max > min => (value - min) / (max - min) * 255.0,
+        (Some(_), _) => 0.0,
+        (None, GdalDataType::UInt16) => value / 257.0,
+        (None, _) => value,
+    };
+    value.clamp(0.0, 255.0).round() as u8
+}
+
+/// 每个瓦片使用的混合参数, 可以由查询参数覆盖
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct BlendParams {
+    pub blend_mode: String,
+    pub swap_layers: bool,
+    pub adjustments: Adjustments,
+}
+
+impl BlendParams {
+    /// 查询参数: mode, swap, brightness, contrast, gamma, saturation, adjust(可重复, 替换命令行中的调整链)
+    /// 其他参数被忽略, 已知参数的值无效时返回错误
+    pub fn with_query(&self, query: &[(String, String)]) -> Result<BlendParams> {
+        let mut params = self.clone();
+        let number = |key: &str, value: &str| -> Result<f32> {
+            value
+                .parse()
+                .map_err(|_| BlendError::InvalidParameter(format!("query parameter {} is not a number: {}", key, value)))
+        };
+        let mut adjust = Vec::new();
+        for (key, value) in query {
+            match key.as_str() {
+                "mode" => {
+                    // 在读取缓存和渲染之前检查混合模式, 包括长度限制
+                    blend_op(value)?;
+                    params.blend_mode = value.to_string();
+                }
+                "swap" => params.swap_layers = matches!(value.as_str(), "" | "1" | "true"),
+                "brightness" => params.adjustments.brightness = number(key, value)?,
+                "contrast" => params.adjustments.contrast = number(key, value)?,
+                "gamma" => params.adjustments.gamma = number(key, value)?,
+                "saturation" => params.adjustments.saturation = number(key, value)?,
+                "adjust" => adjust.push(AdjustStep::parse(value)?),
+                // 忽略其他参数, 例如前端用于绕过缓存的 v 或 _
+                _ => {}
+            }
+        }
+        if !adjust.is_empty() {
+            params.adjustments.adjust = adjust;
+        }
+        Ok(params)
+    }
+}
+
+/// 在本机提供 XYZ 瓦片, 每个瓦片读取两个图层的窗口后增强并混合
+///
+/// 请求路径为 `/{z}/{x}/{y}.{png|webp|jpg}`, 查询参数见 [`BlendParams::with_query`].
+pub struct TileServer {
+    base: WindowSource,
+    top: WindowSource,
+    params: BlendParams,
+    tile_size: usize,
+    cache_dir: Option<PathBuf>,
+}
+
+impl TileServer {
+    pub fn new(base: WindowSource, top: WindowSource, params: BlendParams) -> Self {
+        Self {
+            base,
+            top,
+            params,
+            tile_size: 256,
+            cache_dir: None,
+        }
+    }
+
+    pub fn with_tile_size(mut self, tile_size: usize) -> Self {
+        self.tile_size = tile_size;
+        self
+    }
+
+    /// 瓦片缓存的文件夹, 缓存按瓦片、混合参数和源文件修改时间区分
+    pub fn with_cache_dir(mut self, cache_dir: Option<String>) -> Self {
+        self.cache_dir = cache_dir.map(PathBuf::from);
+        self
+    }
+
+    /// 在 address 上监听, threads 个线程同时处理请求, 不会返回
+    pub fn serve(&self, address: &str, threads: usize) -> Result<()> {
+        let server = Server::http(address)
+            .map_err(|e| BlendError::Io(io::Error::other(format!("cannot listen on {}: {}", address, e))))?;
+        eprintln!("serving tiles on http://{}/{{z}}/{{x}}/{{y}}.png", address);
+        std::thread::scope(|scope| {
+            for _ in 0..threads.max(1) {
+                scope.spawn(|| self.worker(&server));
+            }
+        });
+        Ok(())
+    }
+
+    fn worker(&self, server: &Server) {
+        // 每个线程打开自己的数据集
+        let mut readers = None;
+        for request in server.incoming_requests() {
+            let (base, top) = match &readers {
+                Some(opened) => opened,
+                None => match self.base.reader().and_then(|base| Ok((base, self.top.reader()?))) {
+                    Ok(opened) => readers.insert(opened),
+                    Err(e) => {
+                        respond(request, Err(e));
+                        continue;
+                    }
+                },
+            };
+            let result = self.handle(&request, base, top);
+            respond(request, result);
+        }
+    }
+
+    fn handle(&self, request: &Request, base: &WindowReader, top: &WindowReader) -> Result<(Vec<u8>, Format)> {
+        if *request.method() != Method::Get {
+            return Err(BlendError::InvalidParameter(format!("unsupported method {}", request.method())));
+        }
+        let (path, query) = request.url().split_once('?').unwrap_or((request.url(), ""));
+        let (tile, format) = parse_tile_path(path)
+            .ok_or_else(|| BlendError::InvalidParameter(format!("expected /{{z}}/{{x}}/{{y}}.png, got {}", path)))?;
+        let params = self.params.with_query(&parse_query(query))?;
+
+        let cache_path = self.cache_path(tile, &format, &params);
+        if let Some(bytes) = cache_path.as_ref().and_then(|path| fs::read(path).ok()) {
+            return Ok((bytes, format));
+        }
+
+        let image = BlendJob::builder(base.read_tile(tile, self.tile_size)?, top.read_tile(tile, self.tile_size)?)
+            .blend_mode(&params.blend_mode)
+            .swap_layers(params.swap_layers)
+            .adjustments(params.adjustments)
+            .build()?
+            .run()?;
+        let bytes = BlendImage::encode_image(image, &format)?;
+
+        if let Some(path) = cache_path {
+            write_cache(&path, &bytes)?;
+        }
+        Ok((bytes, format))
+    }
+
+    fn cache_path(&self, tile: TileCoord, format: &Format, params: &BlendParams) -> Option<PathBuf> {
+        let cache_dir = self.cache_dir.as_ref()?;
+        let mut hasher = DefaultHasher::new();
+        serde_json::to_string(params).ok()?.hash(&mut hasher);
+        self.tile_size.hash(&mut hasher);
+        for path in [&self.base.path, &self.top.path] {
+            path.hash(&mut hasher);
+            fs::metadata(path).and_then(|m| m.modified()).unwrap_or(SystemTime::UNIX_EPOCH).hash(&mut hasher);
+        }
+        let file = format!("{}-{:016x}.{}", tile.y, hasher.finish(), format.format_name());
+        Some(cache_dir.join(tile.z.to_string()).join(tile.x.to_string()).join(file))
+    }
+}
+
+/// 解析 /{z}/{x}/{y}.{ext}, 坐标超出该级别的范围时为 None
+pub fn parse_tile_path(path: &str) -> Option<(TileCoord, Format)> {
+    let parts: Vec<&str> = path.trim_matches('/').split('/').collect();
+    let [z, x, file] = parts[..] else { return None };
+    let (y, _) = file.split_once('.')?;
+    let format = match Format::from_path(file)? {
+        Format::TIFF => return None,
+        format => format,
+    };
+    let z: u8 = z.parse().ok()?;
+    let (x, y): (u32, u32) = (x.parse().ok()?, y.parse().ok()?);
+    if z > MAX_ZOOM || x as u64 >= 1 << z || y as u64 >= 1 << z {
+        return None;
+    }
+    Some((TileCoord::new(z, x, y), format))
+}
+
+/// 解析查询字符串, 解码 %XX 和 +
+pub fn parse_query(query: &str) -> Vec<(String, String)> {
+    query
+        .split('&')
+        .filter(|pair| !pair.is_empty())
+        .map(|pair| {
+            let (key, value) = pair.split_once('=').unwrap_or((pair, ""));
+            (percent_decode(key), percent_decode(value))
+        })
+        .collect()
+}
+
+fn percent_decode(s: &str) -> String {
+    let bytes = s.as_bytes();
+    let mut decoded = Vec::with_capacity(bytes.len());
+    let mut i = 0;
+    while i < bytes.len() {
+        match bytes[i] {
+            b'+' => decoded.push(b' '),
+            b'%' => match (bytes.get(i + 1).and_then(hex_digit), bytes.get(i + 2).and_then(hex_digit)) {
+                (Some(high), Some(low)) => {
+                    decoded.push(high << 4 | low);
+                    i += 2;
+                }
+                _ => decoded.push(b'%'),
+            },
+            byte => decoded.push(byte),
+        }
+        i += 1;
+    }
+    String::from_utf8_lossy(&decoded).into_owned()
+}
+
+fn hex_digit(byte: &u8) -> Option<u8> {
+    (*byte as char).to_digit(16).map(|digit| digit as u8)
+}
+
+/// 先写入同一文件夹中的临时文件再重命名, 其他线程不会读到写了一半的缓存瓦片
+fn write_cache(path: &Path, bytes: &[u8]) -> Result<()> {
+    if let Some(parent) = path.parent().and_then(|p| p.to_str()) {
+        makedirs(parent)?;
+    }
+    let index = CACHE_TEMP_INDEX.fetch_add(1, Ordering::Relaxed);
+    let mut temp_path = path.as_os_str().to_owned();
+    temp_path.push(format!(".{}-{}.tmp", std::process::id(), index));
+    let temp_path = PathBuf::from(temp_path);
+    let result = fs::write(&temp_path, bytes).and_then(|_| fs::rename(&temp_path, path));
+    if result.is_err() {
+        let _ = fs::remove_file(&temp_path);
+    }
+    Ok(result?)
+}
+
+fn respond(request: Request, result: Result<(Vec<u8>, Format)>) {
+    let response = match result {
+        Ok((bytes, format)) => Response::from_data(bytes).with_header(content_type(mime_type(&format))),
+        Err(e) => {
+            let status = match e {
+                BlendError::InvalidParameter(_) | BlendError::UnsupportedFormat(_) => 400,
+                _ => 500,
+            };
+            Response::from_string(e.to_string()).with_status_code(status).with_header(content_type("text/plain"))
+        }
+    };
+    if let Err(e) = request.respond(response) {
+        eprintln!("failed to send response: {}", e);
+    }
+}
+
+pub(crate) fn mime_type(format: &Format) -> &'static str {
+    match format {
+        Format::PNG => "image/png",
+        Format::WEBP => "image/webp",
+        Format::JPEG => "image/jpeg",
+        Format::TIFF => "image/tiff",
+    }
+}
+
+pub(crate) fn content_type(value: &str) -> Header {
+    Header::from_bytes(&b"Content-Type"[..], value.as_bytes()).expect("valid header")
+}
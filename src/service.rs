@@ -0,0 +1,317 @@
+use std::io::Read;
+use std::net::SocketAddr;
+use std::path::{Component, Path, PathBuf};
+use std::sync::atomic::{AtomicUsize, Ordering};
+
+use clap::ValueEnum;
+use serde::Deserialize;
+use tiny_http::{Method, Request, Response, Server};
+
+use crate::argparse::Format;
+use crate::bands::BandMapping;
+use crate::error::{BlendError, Result};
+use crate::geotiff::TiffOptions;
+use crate::output::{OutputSpec, OutputTarget};
+use crate::pipeline::{Adjustments, BlendJob, LayerSource};
+use crate::recipe::Recipe;
+use crate::serve::{content_type, mime_type};
+
+/// 混合请求的参数, 与命令行参数和配方对应
+///
+/// multipart 上传时放在名为 `options` 的部分中, 图像放在 `base` 和 `overlay` 中;
+/// 使用服务器上的文件时直接作为 json 请求体, `base` 和 `overlay` 为相对于 --root 的路径.
+#[derive(Debug, Clone, Default, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct BlendRequest {
+    pub base: Option<String>,
+    pub overlay: Option<String>,
+    pub mode: Option<String>,
+    pub swap_layers: bool,
+    pub adjustments: Option<Adjustments>,
+    /// png, webp, jpeg 或 tiff, 默认为 png
+    pub format: Option<String>,
+    /// 不接受 co 中的 GDAL 创建选项
+    pub tiff: Option<TiffOptions>,
+    /// 按波段映射读取底图, 只用于服务器上的文件
+    pub bands: Option<BandMapping>,
+    pub bands2: Option<BandMapping>,
+}
+
+impl BlendRequest {
+    fn recipe(&self) -> Recipe {
+        Recipe {
+            mode: self.mode.clone(),
+            swap_layers: self.swap_layers,
+            adjustments: self.adjustments.clone(),
+            tiff: self.tiff.clone(),
+        }
+    }
+
+    fn format(&self) -> Result<Format> {
+        match &self.format {
+            Some(format) => Format::from_str(format, true)
+                .map_err(|_| BlendError::UnsupportedFormat(format!("{}, expected png, webp, jpeg or tiff", format))),
+            None => Ok(Format::PNG),
+        }
+    }
+}
+
+/// 混合服务的限制
+#[derive(Debug, Clone)]
+pub struct ServiceConfig {
+    /// 请求体的最大字节数, 超过时返回 413
+    pub max_body_size: usize,
+    /// 同时执行的混合任务数, 超过时返回 503
+    pub max_concurrent: usize,
+    /// 允许读取的服务器文件夹, 为 None 时只接受上传的图像
+    pub root: Option<PathBuf>,
+}
+
+impl Default for ServiceConfig {
+    fn default() -> Self {
+        Self {
+            max_body_size: 64 * 1024 * 1024,
+            max_concurrent: 4,
+            root: None,
+        }
+    }
+}
+
+/// 混合服务的 HTTP 响应: 状态码、Content-Type 和内容
+type Reply = (u16, &'static str, Vec<u8>);
+
+/// HTTP 混合服务
+///
+/// - `GET /health` 返回 `{"status":"ok"}`
+/// - `POST /blend` 接受 multipart 上传或 json 请求体, 返回编码后的混合结果
+pub struct BlendService {
+    server: Server,
+    config: ServiceConfig,
+    running: AtomicUsize,
+}
+
+impl BlendService {
+    pub fn bind(address: &str, config: ServiceConfig) -> Result<Self> {
+        let server = Server::http(address)
+            .map_err(|e| BlendError::Io(std::io::Error::other(format!("cannot listen on {}: {}", address, e))))?;
+        Ok(Self {
+            server,
+            config,
+            running: AtomicUsize::new(0),
+        })
+    }
+
+    /// 实际监听的地址, 绑定端口 0 时由系统分配
+    pub fn local_addr(&self) -> Option<SocketAddr> {
+        self.server.server_addr().to_ip()
+    }
+
+    /// 处理请求, 不会返回; 多出的两个线程保证任务满时仍能响应健康检查和 503
+    pub fn run(&self) {
+        std::thread::scope(|scope| {
+            for _ in 0..self.config.max_concurrent.max(1) + 2 {
+                scope.spawn(|| {
+                    for request in self.server.incoming_requests() {
+                        self.handle(request);
+                    }
+                });
+            }
+        });
+    }
+
+    fn handle(&self, mut request: Request) {
+        let path = request.url().split('?').next().unwrap_or("").to_string();
+        let (status, mime, body) = match (request.method(), path.as_str()) {
+            (Method::Get, "/health") => (200, "application/json", br#"{"status":"ok"}"#.to_vec()),
+            (Method::Post, "/blend") => self.blend(&mut request),
+            (_, "/health" | "/blend") => error_reply(405, "method not allowed"),
+            _ => error_reply(404, "not found"),
+        };
+        let response = Response::from_data(body).with_status_code(status).with_header(content_type(mime));
+        if let Err(e) = request.respond(response) {
+            eprintln!("failed to send response: {}", e);
+        }
+    }
+
+    fn blend(&self, request: &mut Request) -> Reply {
+        if request.body_length().is_some_and(|length| length > self.config.max_body_size) {
+            return error_reply(413, "request body too large");
+        }
+        if self.running.fetch_add(1, Ordering::SeqCst) >= self.config.max_concurrent {
+            self.running.fetch_sub(1, Ordering::SeqCst);
+            return error_reply(503, "too many blend requests running, retry later");
+        }
+        let reply = self.read_body(request).and_then(|body| {
+            let content_type = header(request, "Content-Type").unwrap_or_default();
+            match self.run_blend(&content_type, body) {
+                Ok((bytes, format)) => Ok((200, mime_type(&format), bytes)),
+                Err(e) => Err(error_reply(status_code(&e), &e.to_string())),
+            }
+        });
+        self.running.fetch_sub(1, Ordering::SeqCst);
+        reply.unwrap_or_else(|reply| reply)
+    }
+
+    fn read_body(&self, request: &mut Request) -> Result<Vec<u8>, Reply> {
+        let mut body = Vec::new();
+        request
+            .as_reader()
+            .take(self.config.max_body_size as u64 + 1)
+            .read_to_end(&mut body)
+            .map_err(|e| error_reply(400, &format!("failed to read request body: {}", e)))?;
+        if body.len() > self.config.max_body_size {
+            return Err(error_reply(413, "request body too large"));
+        }
+        Ok(body)
+    }
+
+    fn run_blend(&self, content_type: &str, body: Vec<u8>) -> Result<(Vec<u8>, Format)> {
+        let (options, mut base, mut top) = if content_type.starts_with("multipart/form-data") {
+            let boundary = content_type
+                .split(';')
+                .find_map(|param| param.trim().strip_prefix("boundary="))
+                .map(|boundary| boundary.trim_matches('"'))
+                .ok_or_else(|| BlendError::InvalidParameter("multipart request without boundary".to_string()))?;
+            let mut options = None;
+            let (mut base, mut top) = (None, None);
+            for part in parse_multipart(&body, boundary)? {
+                match part.name.as_str() {
+                    "options" => options = Some(parse_options(&part.data)?),
+                    "base" => base = Some(LayerSource::Bytes(part.data)),
+                    "overlay" => top = Some(LayerSource::Bytes(part.data)),
+                    name => return Err(BlendError::InvalidParameter(format!("unknown multipart field {}", name))),
+                }
+            }
+            (options.unwrap_or_default(), base, top)
+        } else {
+            (parse_options(&body)?, None, None)
+        };
+
+        if base.is_none() {
+            base = self.server_layer(&options.base, &options.bands)?;
+        }
+        if top.is_none() {
+            top = self.server_layer(&options.overlay, &options.bands2)?;
+        }
+        let (base, top) = match (base, top) {
+            (Some(base), Some(top)) => (base, top),
+            _ => return Err(BlendError::InvalidParameter("both base and overlay images are needed".to_string())),
+        };
+
+        let recipe = options.recipe();
+        if let Some(tiff) = &recipe.tiff {
+            // 任意的 GDAL 创建选项可能写出附属文件或占用所有 CPU, 服务只接受上面的字段
+            if !tiff.co.is_empty() {
+                return Err(BlendError::InvalidParameter("tiff.co is not accepted by the service".to_string()));
+            }
+            tiff.validate()?;
+        }
+        let format = options.format()?;
+        let output = OutputSpec::new(OutputTarget::Stdout)
+            .with_format(format.clone())
+            .with_tiff(recipe.tiff.clone().unwrap_or_default());
+        let bytes = BlendJob::builder(base, top).recipe(&recipe).output(output).build()?.encode()?;
+        Ok((bytes, format))
+    }
+
+    /// 服务器上的文件, 只能位于 root 之下
+    fn server_layer(&self, path: &Option<String>, bands: &Option<BandMapping>) -> Result<Option<LayerSource>> {
+        let Some(path) = path else { return Ok(None) };
+        let root = self.config.root.as_ref().ok_or_else(|| {
+            BlendError::InvalidParameter("reading files on the server is disabled, upload the images instead".to_string())
+        })?;
+        let relative = Path::new(path);
+        if !relative.components().all(|component| matches!(component, Component::Normal(_) | Component::CurDir)) {
+            return Err(BlendError::InvalidParameter(format!("path must be relative to the server root: {}", path)));
+        }
+        let full_path = root.join(relative).to_string_lossy().into_owned();
+        Ok(Some(match bands {
+            Some(mapping) => LayerSource::Bands(full_path, mapping.clone()),
+            None => LayerSource::Path(full_path),
+        }))
+    }
+}
+
+fn parse_options(data: &[u8]) -> Result<BlendRequest> {
+    if data.iter().all(u8::is_ascii_whitespace) {
+        return Ok(BlendRequest::default());
+    }
+    serde_json::from_slice(data).map_err(|e| BlendError::InvalidParameter(format!("invalid blend options: {}", e)))
+}
+
+fn header(request: &Request, name: &'static str) -> Option<String> {
+    request
+        .headers()
+        .iter()
+        .find(|header| header.field.equiv(name))
+        .map(|header| header.value.as_str().to_string())
+}
+
+/// 参数和输入图像的错误为 400, 其余为 500
+fn status_code(err: &BlendError) -> u16 {
+    match err {
+        BlendError::InvalidParameter(_)
+        | BlendError::UnsupportedFormat(_)
+        | BlendError::Decode(_)
+        | BlendError::SizeMismatch { .. } => 400,
+        BlendError::Io(e) if e.kind() == std::io::ErrorKind::NotFound => 404,
+        _ => 500,
+    }
+}
+
+fn error_reply(status: u16, message: &str) -> Reply {
+    let body = serde_json::json!({ "error": message }).to_string().into_bytes();
+    (status, "application/json", body)
+}
+
+/// multipart/form-data 中的一个部分
+pub struct Part {
+    pub name: String,
+    pub filename: Option<String>,
+    pub data: Vec<u8>,
+}
+
+/// 解析 multipart/form-data 请求体
+pub fn parse_multipart(body: &[u8], boundary: &str) -> Result<Vec<Part>> {
+    let delimiter = format!("--{}", boundary).into_bytes();
+    let invalid = |message: &str| BlendError::InvalidParameter(format!("invalid multipart body: {}", message));
+
+    let mut parts = Vec::new();
+    let mut position = find(body, &delimiter, 0).ok_or_else(|| invalid("boundary not found"))? + delimiter.len();
+    loop {
+        if body[position..].starts_with(b"--") {
+            return Ok(parts);
+        }
+        let start = position + if body[position..].starts_with(b"\r\n") { 2 } else { 0 };
+        let end = find(body, &delimiter, start).ok_or_else(|| invalid("missing closing boundary"))?;
+        let header_end = find(&body[..end], b"\r\n\r\n", start).ok_or_else(|| invalid("part without headers"))?;
+        let headers = String::from_utf8_lossy(&body[start..header_end]);
+        let data = &body[header_end + 4..end];
+        let data = data.strip_suffix(b"\r\n").unwrap_or(data);
+
+        let disposition = headers
+            .lines()
+            .find(|line| line.to_lowercase().starts_with("content-disposition:"))
+            .ok_or_else(|| invalid("part without content-disposition"))?;
+        let param = |key: &str| {
+            disposition
+                .split(';')
+                .find_map(|param| param.trim().strip_prefix(key)?.strip_prefix('='))
+                .map(|value| value.trim_matches('"').to_string())
+        };
+        parts.push(Part {
+            name: param("name").ok_or_else(|| invalid("part without name"))?,
+            filename: param("filename"),
+            data: data.to_vec(),
+        });
+        position = end + delimiter.len();
+    }
+}
+
+fn find(haystack: &[u8], needle: &[u8], from: usize) -> Option<usize> {
+    haystack
+        .get(from..)?
+        .windows(needle.len())
+        .position(|window| window == needle)
+        .map(|index| from + index)
+}
@@ -0,0 +1,72 @@
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+
+/// 太阳的位置, 方位角从正北顺时针, 高度角从地平线向上, 单位均为度
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+pub struct Sun {
+    pub azimuth: f64,
+    pub altitude: f64,
+}
+
+impl Default for Sun {
+    /// 与 gdaldem hillshade 相同, 西北方向 45 度
+    fn default() -> Self {
+        Self::new(315.0, 45.0)
+    }
+}
+
+impl Sun {
+    pub fn new(azimuth: f64, altitude: f64) -> Self {
+        Self { azimuth, altitude }
+    }
+
+    /// 由时间和经纬度计算太阳位置, NOAA 的算法, 不考虑大气折射
+    pub fn at(time: DateTime<Utc>, lon: f64, lat: f64) -> Self {
+        let julian_day = time.timestamp() as f64 / 86400.0 + 2440587.5;
+        let t = (julian_day - 2451545.0) / 36525.0;
+
+        let mean_longitude = (280.46646 + t * (36000.76983 + t * 0.0003032)).rem_euclid(360.0);
+        let mean_anomaly = 357.52911 + t * (35999.05029 - 0.0001537 * t);
+        let eccentricity = 0.016708634 - t * (0.000042037 + 0.0000001267 * t);
+        let m = mean_anomaly.to_radians();
+        let center = m.sin() * (1.914602 - t * (0.004817 + 0.000014 * t))
+            + (2.0 * m).sin() * (0.019993 - 0.000101 * t)
+            + (3.0 * m).sin() * 0.000289;
+        let omega = (125.04 - 1934.136 * t).to_radians();
+        let apparent_longitude = (mean_longitude + center - 0.00569 - 0.00478 * omega.sin()).to_radians();
+        let mean_obliquity = 23.0 + (26.0 + (21.448 - t * (46.815 + t * (0.00059 - t * 0.001813))) / 60.0) / 60.0;
+        let obliquity = (mean_obliquity + 0.00256 * omega.cos()).to_radians();
+        let declination = (obliquity.sin() * apparent_longitude.sin()).asin();
+
+        // 均时差, 单位为分钟
+        let y = (obliquity / 2.0).tan().powi(2);
+        let l0 = mean_longitude.to_radians();
+        let equation_of_time = 4.0
+            * (y * (2.0 * l0).sin() - 2.0 * eccentricity * m.sin()
+                + 4.0 * eccentricity * y * m.sin() * (2.0 * l0).cos()
+                - 0.5 * y * y * (4.0 * l0).sin()
+                - 1.25 * eccentricity * eccentricity * (2.0 * m).sin())
+            .to_degrees();
+
+        let minutes = time.timestamp().rem_euclid(86400) as f64 / 60.0;
+        let true_solar_time = (minutes + equation_of_time + 4.0 * lon).rem_euclid(1440.0);
+        let hour_angle = (true_solar_time / 4.0 - 180.0).to_radians();
+
+        let lat = lat.to_radians();
+        let cos_zenith = lat.sin() * declination.sin() + lat.cos() * declination.cos() * hour_angle.cos();
+        let altitude = 90.0 - cos_zenith.clamp(-1.0, 1.0).acos().to_degrees();
+        let azimuth = hour_angle
+            .sin()
+            .atan2(hour_angle.cos() * lat.sin() - declination.tan() * lat.cos())
+            .to_degrees()
+            + 180.0;
+        Self::new(azimuth.rem_euclid(360.0), altitude)
+    }
+
+    /// 指向太阳的单位向量 (东, 北, 上)
+    pub fn vector(&self) -> [f64; 3] {
+        let (sin_azimuth, cos_azimuth) = self.azimuth.to_radians().sin_cos();
+        let (sin_altitude, cos_altitude) = self.altitude.to_radians().sin_cos();
+        [sin_azimuth * cos_altitude, cos_azimuth * cos_altitude, sin_altitude]
+    }
+}
@@ -0,0 +1,573 @@
+use clap::ValueEnum;
+use gdal::spatial_ref::{CoordTransform, SpatialRef};
+use gdal::{Dataset, GeoTransform};
+use palette::{FromColor, Hsl, Srgb};
+use rayon::prelude::*;
+use serde::{Deserialize, Serialize};
+
+use crate::bands::Stretch;
+use crate::blend::BlendImage;
+use crate::calc::{CalcResult, DEFAULT_NODATA};
+use crate::error::{BlendError, Result};
+use crate::sun::Sun;
+use crate::texture::fractional_laplacian;
+
+/// 地理坐标系下每度对应的米数, 用于把经纬度的像素大小换算为米
+const METERS_PER_DEGREE: f64 = 111_320.0;
+
+/// 数字高程模型, 单波段, 按行存储
+#[derive(Debug, Clone)]
+pub struct Dem {
+    pub width: usize,
+    pub height: usize,
+    pub data: Vec<f64>,
+    pub nodata: Option<f64>,
+    /// 像素的宽和高, 与高程的单位相同
+    pub cell_size: (f64, f64),
+    pub geo_transform: Option<GeoTransform>,
+    pub projection: String,
+}
+
+impl Dem {
+    /// 读取 path 的第一个波段, 地理坐标系的像素大小按中心纬度换算为米
+    pub fn open(path: &str) -> Result<Dem> {
+        let dataset = Dataset::open(path)?;
+        let (width, height) = dataset.raster_size();
+        let band = dataset.rasterband(1)?;
+        let data = band.read_as::<f64>((0, 0), (width, height), (width, height), None)?.data;
+        let geo_transform = dataset
+            .geo_transform()
+            .map_err(|e| BlendError::Georef(format!("{} has no geotransform: {}", path, e)))?;
+        let projection = dataset.projection();
+
+        let (mut cell_width, mut cell_height) = (geo_transform[1].abs(), geo_transform[5].abs());
+        let geographic = SpatialRef::from_wkt(&projection).is_ok_and(|srs| srs.is_geographic());
+        if geographic {
+            let latitude = geo_transform[3] + geo_transform[5] * height as f64 / 2.0;
+            cell_width *= METERS_PER_DEGREE * latitude.to_radians().cos();
+            cell_height *= METERS_PER_DEGREE;
+        }
+
+        Ok(Dem {
+            width,
+            height,
+            data,
+            nodata: band.no_data_value(),
+            cell_size: (cell_width, cell_height),
+            geo_transform: Some(geo_transform),
+            projection,
+        })
+    }
+
+    /// 内存中的高程, 没有地理参考信息
+    pub fn from_raw(width: usize, height: usize, data: Vec<f64>, cell_size: (f64, f64)) -> Result<Dem> {
+        if data.len() != width * height {
+            return Err(BlendError::InvalidParameter(format!(
+                "{} values do not match a {}x{} DEM",
+                data.len(), width, height
+            )));
+        }
+        Ok(Dem {
+            width,
+            height,
+            data,
+            nodata: None,
+            cell_size,
+            geo_transform: None,
+            projection: String::new(),
+        })
+    }
+
+    pub fn with_nodata(mut self, nodata: Option<f64>) -> Self {
+        self.nodata = nodata;
+        self
+    }
+
+    /// DEM 中心的经纬度, 用于计算太阳位置
+    pub fn center_lon_lat(&self) -> Result<(f64, f64)> {
+        let geo_transform = self
+            .geo_transform
+            .ok_or_else(|| BlendError::Georef("the DEM has no geotransform".to_string()))?;
+        let source = SpatialRef::from_wkt(&self.projection)
+            .map_err(|e| BlendError::Georef(format!("the DEM has no coordinate system: {}", e)))?;
+        let wgs84 = SpatialRef::from_epsg(4326)?;
+        for srs in [&source, &wgs84] {
+            srs.set_axis_mapping_strategy(gdal_sys::OSRAxisMappingStrategy::OAMS_TRADITIONAL_GIS_ORDER);
+        }
+        let (px, py) = (self.width as f64 / 2.0, self.height as f64 / 2.0);
+        let mut xs = [geo_transform[0] + px * geo_transform[1] + py * geo_transform[2]];
+        let mut ys = [geo_transform[3] + px * geo_transform[4] + py * geo_transform[5]];
+        CoordTransform::new(&source, &wgs84)?.transform_coords(&mut xs, &mut ys, &mut [])?;
+        Ok((xs[0], ys[0]))
+    }
+
+    pub fn is_nodata(&self, value: f64) -> bool {
+        value.is_nan() || Some(value) == self.nodata
+    }
+
+    /// (x, y) 周围 3x3 窗口的高程, 按行排列; 超出边界或为 nodata 的邻居取中心的值
+    fn window(&self, x: usize, y: usize) -> Option<[f64; 9]> {
+        let center = self.data[y * self.width + x];
+        if self.is_nodata(center) {
+            return None;
+        }
+        let mut window = [center; 9];
+        for (i, value) in window.iter_mut().enumerate() {
+            let (nx, ny) = (x as isize + i as isize % 3 - 1, y as isize + i as isize / 3 - 1);
+            if nx < 0 || ny < 0 || nx >= self.width as isize || ny >= self.height as isize {
+                continue;
+            }
+            let neighbour = self.data[ny as usize * self.width + nx as usize];
+            if !self.is_nodata(neighbour) {
+                *value = neighbour;
+            }
+        }
+        Some(window)
+    }
+
+    /// 逐像素计算, f 返回 None 时为 nodata, 结果的地理参考信息与 DEM 相同
+    pub(crate) fn map_pixels<F>(&self, f: F) -> CalcResult
+    where
+        F: Fn(usize, usize) -> Option<f64> + Sync,
+    {
+        let mut data = vec![DEFAULT_NODATA; self.width * self.height];
+        if self.width > 0 {
+            data.par_chunks_mut(self.width).enumerate().for_each(|(y, row)| {
+                for (x, value) in row.iter_mut().enumerate() {
+                    if let Some(result) = f(x, y).filter(|result| result.is_finite()) {
+                        *value = result;
+                    }
+                }
+            });
+        }
+        CalcResult {
+            width: self.width,
+            height: self.height,
+            data,
+            nodata: DEFAULT_NODATA,
+            geo_transform: self.geo_transform,
+            projection: self.projection.clone(),
+        }
+    }
+}
+
+/// 地形因子
+#[derive(Debug, Clone, Copy, ValueEnum, Serialize, Deserialize, PartialEq, Eq)]
+#[serde(rename_all = "kebab-case")]
+pub enum Derivative {
+    /// 坡度, Horn 算法
+    Slope,
+    /// 坡向, 正北为 0, 顺时针 0-360 度, 平地为 nodata
+    Aspect,
+    /// 剖面曲率, 沿坡度方向, 负值为凸, Zevenbergen-Thorne 算法, 单位为 1/100 高程单位
+    ProfileCurvature,
+    /// 平面曲率, 垂直于坡度方向, 正值为凸(山脊), 负值为凹(山谷)
+    PlanCurvature,
+    /// 地形粗糙度指数, 中心与 8 个邻居高差的平方和的平方根
+    Tri,
+    /// 地形位置指数, 中心与 8 个邻居平均值的差
+    Tpi,
+    /// 粗糙度, 3x3 窗口内最大值与最小值的差
+    Roughness,
+    /// 天空可视因子, 1 减去各方向地平线高度角正弦的平均值, 0-1, 山谷和沟底小
+    SkyView,
+    /// 环境光遮蔽, 按余弦加权的可见天空比例, 1 减去各方向地平线高度角正弦平方的平均值, 0-1
+    AmbientOcclusion,
+    /// 山体阴影, 地表法线与太阳方向夹角的余弦, 0-1, 可以叠加地形投射的阴影
+    Hillshade,
+    /// 地形投射的阴影, 沿太阳方向逐像素追踪光线, 1 为照亮, 0 为阴影, 有半影时为可见的太阳比例
+    Shadow,
+    /// 纹理晕渲 (Leland Brown), 基于 FFT 的分数阶拉普拉斯变换, 山脊亮, 山谷暗
+    Texture,
+}
+
+/// 坡度的单位
+#[derive(Debug, Clone, Copy, Default, ValueEnum, Serialize, Deserialize, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum SlopeUnit {
+    #[default]
+    Degrees,
+    Percent,
+}
+
+/// 地形因子着色为 RGBA 图层的方式
+#[derive(Debug, Clone, Copy, ValueEnum, Serialize, Deserialize, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum TerrainStyle {
+    /// 线性拉伸为灰度, 小值为黑
+    Gray,
+    /// 线性拉伸为灰度, 小值为白, 如平地白、陡坡暗的坡度晕渲
+    Shade,
+    /// 以 0 为中心, 负值为蓝, 正值为红, 适合曲率和地形位置指数
+    Diverging,
+    /// 色相表示坡向, 饱和度随坡度增大, 只用于坡向
+    Aspect,
+}
+
+impl Derivative {
+    /// 默认的着色方式
+    pub fn default_style(&self) -> TerrainStyle {
+        match self {
+            Self::Slope | Self::Tri | Self::Roughness => TerrainStyle::Shade,
+            Self::Aspect => TerrainStyle::Aspect,
+            Self::ProfileCurvature | Self::PlanCurvature | Self::Tpi => TerrainStyle::Diverging,
+            Self::SkyView | Self::AmbientOcclusion | Self::Hillshade | Self::Shadow | Self::Texture => TerrainStyle::Gray,
+        }
+    }
+
+    /// 着色时默认的拉伸方式, 为 None 时使用 default_range
+    pub fn default_stretch(&self) -> Option<Stretch> {
+        match self {
+            Self::Texture => Some(Stretch::default()),
+            _ => None,
+        }
+    }
+
+    /// 着色时默认的值范围, 为 None 时使用数据的范围
+    pub fn default_range(&self) -> Option<(f64, f64)> {
+        match self {
+            Self::SkyView | Self::AmbientOcclusion | Self::Hillshade | Self::Shadow => Some((0.0, 1.0)),
+            _ => None,
+        }
+    }
+}
+
+/// 由 DEM 计算地形因子, 结果为 CalcResult, 可以保存为 GeoTIFF 或着色后作为混合图层
+///
+/// ```no_run
+/// use blend_images::terrain::{Dem, Derivative, Terrain};
+///
+/// let dem = Dem::open("dem.tif")?;
+/// let terrain = Terrain::new(Derivative::Slope);
+/// let slope = terrain.compute(&dem);
+/// let layer = terrain.style(&dem, &slope, None, None)?;
+/// # Ok::<(), blend_images::error::BlendError>(())
+/// ```
+#[derive(Debug, Clone)]
+pub struct Terrain {
+    derivative: Derivative,
+    slope_unit: SlopeUnit,
+    z_factor: f64,
+    radius: usize,
+    directions: usize,
+    sun: Sun,
+    penumbra: f64,
+    cast_shadows: bool,
+    detail: f64,
+    stretch: Option<Stretch>,
+}
+
+impl Terrain {
+    pub fn new(derivative: Derivative) -> Self {
+        Self {
+            derivative,
+            slope_unit: SlopeUnit::Degrees,
+            z_factor: 1.0,
+            radius: 10,
+            directions: 16,
+            sun: Sun::default(),
+            penumbra: 0.0,
+            cast_shadows: false,
+            detail: 0.5,
+            stretch: None,
+        }
+    }
+
+    pub fn with_slope_unit(mut self, slope_unit: SlopeUnit) -> Self {
+        self.slope_unit = slope_unit;
+        self
+    }
+
+    /// 高程的缩放系数, 用于高程与像素大小单位不同或夸大地形
+    pub fn with_z_factor(mut self, z_factor: f64) -> Self {
+        self.z_factor = z_factor;
+        self
+    }
+
+    /// 天空可视因子和环境光遮蔽搜索地平线的距离, 单位为像素, 至少为 1
+    pub fn with_radius(mut self, radius: usize) -> Self {
+        self.radius = radius.max(1);
+        self
+    }
+
+    /// 天空可视因子和环境光遮蔽搜索地平线的方向数, 至少为 1
+    pub fn with_directions(mut self, directions: usize) -> Self {
+        self.directions = directions.max(1);
+        self
+    }
+
+    /// 山体阴影和阴影使用的太阳位置
+    pub fn with_sun(mut self, sun: Sun) -> Self {
+        self.sun = sun;
+        self
+    }
+
+    /// 半影的角宽度, 单位为度, 为 0 时阴影只有 0 和 1; 太阳本身的角直径约为 0.53 度
+    pub fn with_penumbra(mut self, penumbra: f64) -> Self {
+        self.penumbra = penumbra.max(0.0);
+        self
+    }
+
+    /// 山体阴影是否乘以地形投射的阴影
+    pub fn with_cast_shadows(mut self, cast_shadows: bool) -> Self {
+        self.cast_shadows = cast_shadows;
+        self
+    }
+
+    /// 纹理晕渲的分数阶拉普拉斯的阶数, 0-2, 越大细节越多
+    pub fn with_detail(mut self, detail: f64) -> Self {
+        self.detail = detail.clamp(0.0, 2.0);
+        self
+    }
+
+    /// 着色时由数据统计值范围的方式, 没有给出 range 时使用
+    pub fn with_stretch(mut self, stretch: Stretch) -> Self {
+        self.stretch = Some(stretch);
+        self
+    }
+
+    pub fn compute(&self, dem: &Dem) -> CalcResult {
+        if self.derivative == Derivative::Texture {
+            return self.texture(dem);
+        }
+        let (dx, dy) = dem.cell_size;
+        let z = self.z_factor;
+        // 各方向每一步在 x 和 y 上的像素数
+        let directions: Vec<(f64, f64)> = (0..self.directions)
+            .map(|i| {
+                let (sin, cos) = (std::f64::consts::TAU * i as f64 / self.directions as f64).sin_cos();
+                (cos, sin)
+            })
+            .collect();
+        let highest = dem
+            .data
+            .iter()
+            .filter(|value| !dem.is_nodata(**value))
+            .fold(f64::MIN, |highest, value| highest.max(value * z));
+        dem.map_pixels(|x, y| {
+            let w = dem.window(x, y)?.map(|value| value * z);
+            let value = match self.derivative {
+                Derivative::Slope => {
+                    let (gx, gy) = horn_gradient(&w, dx, dy);
+                    let gradient = gx.hypot(gy);
+                    match self.slope_unit {
+                        SlopeUnit::Degrees => gradient.atan().to_degrees(),
+                        SlopeUnit::Percent => gradient * 100.0,
+                    }
+                }
+                Derivative::Aspect => aspect(&w, dx, dy)?,
+                Derivative::ProfileCurvature | Derivative::PlanCurvature => {
+                    let curvature = curvature(&w, dx, dy);
+                    if self.derivative == Derivative::ProfileCurvature { curvature.0 } else { curvature.1 }
+                }
+                Derivative::Tri => w.iter().map(|value| (value - w[4]).powi(2)).sum::<f64>().sqrt(),
+                Derivative::Tpi => w[4] - (w.iter().sum::<f64>() - w[4]) / 8.0,
+                Derivative::Roughness => {
+                    let max = w.iter().cloned().fold(f64::MIN, f64::max);
+                    let min = w.iter().cloned().fold(f64::MAX, f64::min);
+                    max - min
+                }
+                Derivative::SkyView | Derivative::AmbientOcclusion => {
+                    let sines = self.horizon_sines(dem, x, y, &directions);
+                    let power = if self.derivative == Derivative::SkyView { 1 } else { 2 };
+                    1.0 - sines.iter().map(|sin| sin.powi(power)).sum::<f64>() / sines.len() as f64
+                }
+                Derivative::Hillshade => {
+                    // 法线 (-dz/dx, dz/dy, 1), x 向东, y 向南
+                    let (gx, gy) = horn_gradient(&w, dx, dy);
+                    let [east, north, up] = self.sun.vector();
+                    let illumination = ((-gx * east + gy * north + up) / (gx * gx + gy * gy + 1.0).sqrt()).max(0.0);
+                    if self.cast_shadows && illumination > 0.0 {
+                        illumination * self.sun_visibility(dem, x, y, highest)
+                    } else {
+                        illumination
+                    }
+                }
+                Derivative::Shadow => self.sun_visibility(dem, x, y, highest),
+                // 在频域中整体计算, 见 texture
+                Derivative::Texture => unreachable!(),
+            };
+            Some(value)
+        })
+    }
+
+    /// 各方向在 radius 内地平线高度角的正弦, 低于水平面的按 0 计算
+    fn horizon_sines(&self, dem: &Dem, x: usize, y: usize, directions: &[(f64, f64)]) -> Vec<f64> {
+        let z = self.z_factor;
+        let (dx, dy) = dem.cell_size;
+        let center = dem.data[y * dem.width + x] * z;
+        directions
+            .iter()
+            .map(|&(step_x, step_y)| {
+                let mut max_tangent: f64 = 0.0;
+                for k in 1..=self.radius {
+                    let nx = (x as f64 + step_x * k as f64).round();
+                    let ny = (y as f64 + step_y * k as f64).round();
+                    if nx < 0.0 || ny < 0.0 || nx >= dem.width as f64 || ny >= dem.height as f64 {
+                        break;
+                    }
+                    let value = dem.data[ny as usize * dem.width + nx as usize];
+                    if !dem.is_nodata(value) {
+                        // 按取到的像素计算实际距离
+                        let distance = ((nx - x as f64) * dx).hypot((ny - y as f64) * dy);
+                        max_tangent = max_tangent.max((value * z - center) / distance);
+                    }
+                }
+                max_tangent.atan().sin()
+            })
+            .collect()
+    }
+
+    fn texture(&self, dem: &Dem) -> CalcResult {
+        let data: Vec<f64> = dem
+            .data
+            .iter()
+            .map(|&value| if dem.is_nodata(value) { f64::NAN } else { value * self.z_factor })
+            .collect();
+        let texture = fractional_laplacian(&data, dem.width, dem.height, dem.cell_size, self.detail);
+        dem.map_pixels(|x, y| {
+            let i = y * dem.width + x;
+            (!data[i].is_nan()).then(|| texture[i])
+        })
+    }
+
+    /// 沿太阳方向追踪光线, 返回可见的太阳比例; highest 为 DEM 的最高点, 光线高于它时停止
+    fn sun_visibility(&self, dem: &Dem, x: usize, y: usize, highest: f64) -> f64 {
+        let half = self.penumbra / 2.0;
+        if self.sun.altitude + half <= 0.0 {
+            return 0.0;
+        }
+        let (low, high) = ((self.sun.altitude - half).to_radians().tan(), (self.sun.altitude + half).min(89.9).to_radians().tan());
+        let (dx, dy) = dem.cell_size;
+        let z = self.z_factor;
+        let center = dem.data[y * dem.width + x] * z;
+
+        // 每一步在较长的轴上前进一个像素
+        let (sin, cos) = self.sun.azimuth.to_radians().sin_cos();
+        let scale = sin.abs().max(cos.abs());
+        let (step_x, step_y) = (sin / scale, -cos / scale);
+        let mut max_tangent = f64::MIN;
+        for k in 1.. {
+            let nx = (x as f64 + step_x * k as f64).round();
+            let ny = (y as f64 + step_y * k as f64).round();
+            if nx < 0.0 || ny < 0.0 || nx >= dem.width as f64 || ny >= dem.height as f64 {
+                break;
+            }
+            let distance = ((nx - x as f64) * dx).hypot((ny - y as f64) * dy);
+            if (highest - center) / distance < low || max_tangent >= high {
+                break;
+            }
+            let value = dem.data[ny as usize * dem.width + nx as usize];
+            if !dem.is_nodata(value) {
+                max_tangent = max_tangent.max((value * z - center) / distance);
+            }
+        }
+
+        let horizon = max_tangent.atan().to_degrees();
+        if self.penumbra > 0.0 {
+            (0.5 + (self.sun.altitude - horizon) / self.penumbra).clamp(0.0, 1.0)
+        } else if horizon > self.sun.altitude {
+            0.0
+        } else {
+            1.0
+        }
+    }
+
+    /// 着色为 RGBA 图层, nodata 为透明; style 为 None 时使用地形因子默认的着色方式,
+    /// range 为 None 时按拉伸方式统计, 没有拉伸方式时使用地形因子默认的范围或数据的范围, 发散色带使用绝对值的最大值
+    pub fn style(&self, dem: &Dem, result: &CalcResult, style: Option<TerrainStyle>, range: Option<(f64, f64)>) -> Result<BlendImage> {
+        let style = style.unwrap_or(self.derivative.default_style());
+        let (min, max) = match (range, self.stretch.or(self.derivative.default_stretch())) {
+            (Some(range), _) => range,
+            (None, Some(stretch)) => {
+                let values: Vec<f64> = result.data.iter().copied().filter(|value| *value != result.nodata).collect();
+                stretch.range(&values)
+            }
+            (None, None) => self.derivative.default_range().or_else(|| result.range()).unwrap_or((0.0, 1.0)),
+        };
+        if max < min {
+            return Err(BlendError::InvalidParameter(format!("invalid value range {}..{}", min, max)));
+        }
+        let unit = |value: f64| if max > min { ((value - min) / (max - min)).clamp(0.0, 1.0) } else { 0.0 };
+
+        let slope = match style {
+            TerrainStyle::Aspect if self.derivative != Derivative::Aspect => {
+                return Err(BlendError::InvalidParameter("the aspect style only applies to aspect".to_string()))
+            }
+            TerrainStyle::Aspect => Some(Terrain::new(Derivative::Slope).with_z_factor(self.z_factor).compute(dem)),
+            _ => None,
+        };
+        let extent = min.abs().max(max.abs());
+
+        let mut raw_pixels = Vec::with_capacity(result.data.len() * 4);
+        for (i, &value) in result.data.iter().enumerate() {
+            if value == result.nodata || value.is_nan() {
+                // 平地没有坡向, 着色为灰色
+                if style == TerrainStyle::Aspect && dem.window(i % dem.width, i / dem.width).is_some() {
+                    raw_pixels.extend_from_slice(&[128, 128, 128, 255]);
+                } else {
+                    raw_pixels.extend_from_slice(&[0, 0, 0, 0]);
+                }
+                continue;
+            }
+            let rgb = match style {
+                TerrainStyle::Gray => gray(unit(value)),
+                TerrainStyle::Shade => gray(1.0 - unit(value)),
+                TerrainStyle::Diverging => {
+                    let t = if extent > 0.0 { (value / extent).clamp(-1.0, 1.0) } else { 0.0 };
+                    let fade = (255.0 * (1.0 - t.abs())).round() as u8;
+                    if t < 0.0 { [fade, fade, 255] } else { [255, fade, fade] }
+                }
+                TerrainStyle::Aspect => {
+                    // 坡度达到 45 度时饱和度最大
+                    let slope = slope.as_ref().map_or(0.0, |slope| slope.data[i]);
+                    let saturation = (slope / 45.0).clamp(0.0, 1.0) as f32;
+                    let rgb = Srgb::from_color(Hsl::new(value as f32, saturation, 0.5));
+                    let (r, g, b) = rgb.into_format::<u8>().into_components();
+                    [r, g, b]
+                }
+            };
+            raw_pixels.extend_from_slice(&[rgb[0], rgb[1], rgb[2], 255]);
+        }
+        BlendImage::from_raw(result.width as u32, result.height as u32, raw_pixels)
+    }
+}
+
+fn gray(unit: f64) -> [u8; 3] {
+    let gray = (unit * 255.0).round() as u8;
+    [gray, gray, gray]
+}
+
+/// Horn 算法的梯度 (dz/dx, dz/dy), x 向东, y 向南
+pub(crate) fn horn_gradient(w: &[f64; 9], dx: f64, dy: f64) -> (f64, f64) {
+    let gx = ((w[2] + 2.0 * w[5] + w[8]) - (w[0] + 2.0 * w[3] + w[6])) / (8.0 * dx);
+    let gy = ((w[6] + 2.0 * w[7] + w[8]) - (w[0] + 2.0 * w[1] + w[2])) / (8.0 * dy);
+    (gx, gy)
+}
+
+/// 坡向, 与 gdaldem aspect 相同, 平地为 None
+fn aspect(w: &[f64; 9], dx: f64, dy: f64) -> Option<f64> {
+    let (gx, gy) = horn_gradient(w, dx, dy);
+    if gx == 0.0 && gy == 0.0 {
+        return None;
+    }
+    let angle = gy.atan2(-gx).to_degrees();
+    let aspect = if angle > 90.0 { 450.0 - angle } else { 90.0 - angle };
+    Some(if aspect >= 360.0 { aspect - 360.0 } else { aspect })
+}
+
+/// Zevenbergen-Thorne 算法的 (剖面曲率, 平面曲率), 与 ArcGIS 相同乘以 100
+fn curvature(w: &[f64; 9], dx: f64, dy: f64) -> (f64, f64) {
+    let d = ((w[3] + w[5]) / 2.0 - w[4]) / (dx * dx);
+    let e = ((w[1] + w[7]) / 2.0 - w[4]) / (dy * dy);
+    let f = (-w[0] + w[2] + w[6] - w[8]) / (4.0 * dx * dy);
+    let g = (w[5] - w[3]) / (2.0 * dx);
+    let h = (w[1] - w[7]) / (2.0 * dy);
+    let gradient = g * g + h * h;
+    if gradient == 0.0 {
+        return (0.0, 0.0);
+    }
+    let profile = -2.0 * (d * g * g + e * h * h + f * g * h) / gradient;
+    let plan = 2.0 * (d * h * h + e * g * g - f * g * h) / gradient;
+    (profile * 100.0, plan * 100.0)
+}
@@ -0,0 +1,108 @@
+use std::f64::consts::TAU;
+use std::sync::Arc;
+
+use rayon::prelude::*;
+use rustfft::num_complex::Complex;
+use rustfft::{Fft, FftPlanner};
+
+/// 按列做 FFT 时每个任务一次处理的列数
+const COLUMN_BATCH: usize = 16;
+
+/// 纹理晕渲 (Leland Brown) 的分数阶拉普拉斯变换
+///
+/// 在频域中乘以 `(2π|f|)^detail`, detail 为 0 时保持原样, 大于 0 时去除平均值, 为 2 时为拉普拉斯算子.
+/// 四周镜像扩展 margin 个像素以减少 FFT 周期边界的影响, nan 为 nodata, 计算前用有效值的平均值填充.
+pub fn fractional_laplacian(data: &[f64], width: usize, height: usize, cell_size: (f64, f64), detail: f64) -> Vec<f64> {
+    if width == 0 || height == 0 {
+        return Vec::new();
+    }
+    let (sum, count) = data
+        .iter()
+        .filter(|value| !value.is_nan())
+        .fold((0.0, 0_usize), |(sum, count), value| (sum + value, count + 1));
+    let mean = if count == 0 { 0.0 } else { sum / count as f64 };
+
+    let margin = |size: usize| (size / 8).clamp(1, 256).min(size - 1);
+    let (margin_x, margin_y) = (margin(width), margin(height));
+    let (padded_width, padded_height) = (width + 2 * margin_x, height + 2 * margin_y);
+    let mirror = |i: isize, size: usize| -> usize {
+        let size = size as isize;
+        let i = if i < 0 { -i } else { i };
+        (if i >= size { 2 * (size - 1) - i } else { i }) as usize
+    };
+    let mut buffer: Vec<Complex<f64>> = (0..padded_width * padded_height)
+        .map(|i| {
+            let x = mirror((i % padded_width) as isize - margin_x as isize, width);
+            let y = mirror((i / padded_width) as isize - margin_y as isize, height);
+            let value = data[y * width + x];
+            Complex::new(if value.is_nan() { mean } else { value }, 0.0)
+        })
+        .collect();
+
+    let mut planner = FftPlanner::new();
+    fft_2d(&mut buffer, padded_width, padded_height, planner.plan_fft_forward(padded_width), planner.plan_fft_forward(padded_height));
+
+    // 频率的单位为周期每单位距离; detail 大于 0 时直流分量为 0, 即去除平均值
+    let frequency = |i: usize, size: usize, cell: f64| {
+        let i = if i <= size / 2 { i as f64 } else { i as f64 - size as f64 };
+        i / (size as f64 * cell)
+    };
+    buffer.par_chunks_mut(padded_width).enumerate().for_each(|(y, row)| {
+        let fy = frequency(y, padded_height, cell_size.1);
+        for (x, value) in row.iter_mut().enumerate() {
+            let fx = frequency(x, padded_width, cell_size.0);
+            let magnitude = TAU * fx.hypot(fy);
+            *value *= if detail == 0.0 {
+                1.0
+            } else if magnitude == 0.0 {
+                0.0
+            } else {
+                magnitude.powf(detail)
+            };
+        }
+    });
+
+    fft_2d(&mut buffer, padded_width, padded_height, planner.plan_fft_inverse(padded_width), planner.plan_fft_inverse(padded_height));
+    let scale = (padded_width * padded_height) as f64;
+    (0..width * height)
+        .map(|i| {
+            let (x, y) = (i % width + margin_x, i / width + margin_y);
+            buffer[y * padded_width + x].re / scale
+        })
+        .collect()
+}
+
+/// 先对每行再对每列做一维 FFT, 各行各列并行计算
+///
+/// 按列计算时分组进行, 每组的列复制到连续的缓冲区中计算后写回,
+/// 额外的内存只有 线程数 * COLUMN_BATCH 列, 而不是整个栅格的副本.
+fn fft_2d(buffer: &mut [Complex<f64>], width: usize, height: usize, row_fft: Arc<dyn Fft<f64>>, column_fft: Arc<dyn Fft<f64>>) {
+    buffer.par_chunks_mut(width).for_each(|row| row_fft.process(row));
+
+    let group = (COLUMN_BATCH * rayon::current_num_threads()).min(width);
+    let mut columns = vec![Complex::default(); group * height];
+    for start in (0..width).step_by(group) {
+        let count = group.min(width - start);
+        let columns = &mut columns[..count * height];
+        columns.par_chunks_mut(COLUMN_BATCH * height).enumerate().for_each(|(batch, batch_columns)| {
+            let first = start + batch * COLUMN_BATCH;
+            let batch_width = batch_columns.len() / height;
+            for y in 0..height {
+                let row = &buffer[y * width + first..y * width + first + batch_width];
+                for (i, value) in row.iter().enumerate() {
+                    batch_columns[i * height + y] = *value;
+                }
+            }
+            let mut scratch = vec![Complex::default(); column_fft.get_inplace_scratch_len()];
+            for column in batch_columns.chunks_mut(height) {
+                column_fft.process_with_scratch(column, &mut scratch);
+            }
+        });
+        let columns = &*columns;
+        buffer.par_chunks_mut(width).enumerate().for_each(|(y, row)| {
+            for (i, value) in row[start..start + count].iter_mut().enumerate() {
+                *value = columns[i * height + y];
+            }
+        });
+    }
+}
@@ -0,0 +1,307 @@
+use std::f64::consts::PI;
+use std::str::FromStr;
+use std::sync::atomic::{AtomicUsize, Ordering};
+
+use clap::ValueEnum;
+use gdal::raster::reproject;
+use gdal::spatial_ref::{CoordTransform, SpatialRef};
+use gdal::{Dataset, DriverManager};
+use rayon::prelude::*;
+use serde::{Deserialize, Serialize};
+
+use crate::argparse::Format;
+use crate::blend::BlendImage;
+use crate::error::{BlendError, Result};
+use crate::geotiff::TiffOptions;
+use crate::tilestore::{TileInfo, TileOutput, TileStore};
+
+/// Web Mercator 的半周长, 米
+pub const MERCATOR_EXTENT: f64 = 20037508.342789244;
+
+/// Web Mercator 能表示的最大纬度
+pub const MAX_LATITUDE: f64 = 85.05112877980659;
+
+/// 支持的最大缩放级别
+pub const MAX_ZOOM: u8 = 24;
+
+/// 瓦片行号的方向
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, ValueEnum, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum TileScheme {
+    /// 行号从北向南递增, 即 Google / OSM 的 z/x/y
+    #[default]
+    Xyz,
+    /// 行号从南向北递增
+    Tms,
+}
+
+/// 瓦片坐标, y 为 XYZ 方向的行号
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct TileCoord {
+    pub z: u8,
+    pub x: u32,
+    pub y: u32,
+}
+
+impl TileCoord {
+    pub fn new(z: u8, x: u32, y: u32) -> Self {
+        Self { z, x, y }
+    }
+
+    /// 经纬度所在的瓦片, 超出 Web Mercator 范围时取边缘的瓦片
+    pub fn from_lon_lat(lon: f64, lat: f64, z: u8) -> Self {
+        let count = 1u64 << z;
+        let (x, y) = lon_lat_to_mercator(lon, lat);
+        let to_index = |unit: f64| ((unit * count as f64).floor().max(0.0) as u64).min(count - 1) as u32;
+        Self {
+            z,
+            x: to_index((x + MERCATOR_EXTENT) / (2.0 * MERCATOR_EXTENT)),
+            y: to_index((MERCATOR_EXTENT - y) / (2.0 * MERCATOR_EXTENT)),
+        }
+    }
+
+    /// 瓦片在 Web Mercator 中的范围 [min_x, min_y, max_x, max_y]
+    pub fn bounds(&self) -> [f64; 4] {
+        let size = 2.0 * MERCATOR_EXTENT / (1u64 << self.z) as f64;
+        let min_x = -MERCATOR_EXTENT + self.x as f64 * size;
+        let max_y = MERCATOR_EXTENT - self.y as f64 * size;
+        [min_x, max_y - size, min_x + size, max_y]
+    }
+
+    /// 按 scheme 的行号, TMS 的行号从南向北
+    pub fn row(&self, scheme: TileScheme) -> u32 {
+        match scheme {
+            TileScheme::Xyz => self.y,
+            TileScheme::Tms => ((1u64 << self.z) - 1 - self.y as u64) as u32,
+        }
+    }
+}
+
+/// 经纬度转为 Web Mercator 坐标, 纬度截断到 ±MAX_LATITUDE
+pub fn lon_lat_to_mercator(lon: f64, lat: f64) -> (f64, f64) {
+    let lat = lat.clamp(-MAX_LATITUDE, MAX_LATITUDE);
+    let x = lon.clamp(-180.0, 180.0) * MERCATOR_EXTENT / 180.0;
+    let y = (PI / 4.0 + lat.to_radians() / 2.0).tan().ln() * MERCATOR_EXTENT / PI;
+    (x, y)
+}
+
+/// 与经纬度范围 [west, south, east, north] 相交的 z 级瓦片
+pub fn tiles_in(bounds: [f64; 4], z: u8) -> Vec<TileCoord> {
+    let top_left = TileCoord::from_lon_lat(bounds[0], bounds[3], z);
+    let bottom_right = TileCoord::from_lon_lat(bounds[2], bounds[1], z);
+    (top_left.x..=bottom_right.x)
+        .flat_map(|x| (top_left.y..=bottom_right.y).map(move |y| TileCoord::new(z, x, y)))
+        .collect()
+}
+
+/// 缩放级别范围, 如 `8-14` 或 `12`
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub struct ZoomRange {
+    pub min: u8,
+    pub max: u8,
+}
+
+impl FromStr for ZoomRange {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, String> {
+        let parse = |v: &str| v.trim().parse::<u8>().map_err(|_| format!("invalid zoom level '{}'", v));
+        let (min, max) = match s.split_once('-') {
+            Some((min, max)) => (parse(min)?, parse(max)?),
+            None => (parse(s)?, parse(s)?),
+        };
+        if min > max || max > MAX_ZOOM {
+            return Err(format!("zoom range must be MIN-MAX within 0-{}, got '{}'", MAX_ZOOM, s));
+        }
+        Ok(Self { min, max })
+    }
+}
+
+/// 生成的瓦片数量
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub struct TileStats {
+    pub written: usize,
+    /// 完全透明而跳过的瓦片
+    pub empty: usize,
+    /// 数据的经纬度范围 [west, south, east, north]
+    pub bounds: [f64; 4],
+}
+
+/// Web Mercator 瓦片金字塔, 由带地理参考的混合结果逐级重投影切片
+///
+/// ```no_run
+/// use blend_images::pipeline::BlendJob;
+/// use blend_images::tiles::{TilePyramid, TileScheme};
+/// use blend_images::tilestore::{TileInfo, TileOutput};
+///
+/// let image = BlendJob::builder("dem.tif", "hillshade.tif").blend_mode("multiply").build()?.run()?;
+/// TilePyramid::new("8-12".parse()?)
+///     .with_scheme(TileScheme::Tms)
+///     .write(image, "dem.tif", &TileOutput::parse("relief.mbtiles"), &TileInfo::new("relief"))?;
+/// # Ok::<(), Box<dyn std::error::Error>>(())
+/// ```
+#[derive(Debug, Clone)]
+pub struct TilePyramid {
+    pub zoom: ZoomRange,
+    pub scheme: TileScheme,
+    pub format: Format,
+    pub tile_size: usize,
+}
+
+impl TilePyramid {
+    pub fn new(zoom: ZoomRange) -> Self {
+        Self {
+            zoom,
+            scheme: TileScheme::Xyz,
+            format: Format::PNG,
+            tile_size: 256,
+        }
+    }
+
+    pub fn with_scheme(mut self, scheme: TileScheme) -> Self {
+        self.scheme = scheme;
+        self
+    }
+
+    pub fn with_format(mut self, format: Format) -> Self {
+        self.format = format;
+        self
+    }
+
+    pub fn with_tile_size(mut self, tile_size: usize) -> Self {
+        self.tile_size = tile_size;
+        self
+    }
+
+    fn validate(&self) -> Result<()> {
+        if self.format == Format::TIFF {
+            return Err(BlendError::UnsupportedFormat("tiles must be png, webp or jpeg".to_string()));
+        }
+        if self.tile_size == 0 || self.tile_size > 4096 {
+            return Err(BlendError::InvalidParameter(format!("tile size must be 1-4096, got {}", self.tile_size)));
+        }
+        Ok(())
+    }
+
+    /// 瓦片文件的扩展名
+    pub fn extension(&self) -> String {
+        self.format.format_name()
+    }
+
+    /// 切片混合结果, 地理参考信息取自 georef_image, 写入瓦片文件夹、MBTiles 或 GeoPackage
+    pub fn write(&self, image: BlendImage, georef_image: &str, output: &TileOutput, info: &TileInfo) -> Result<TileStats> {
+        self.validate()?;
+        let store = TileStore::create(output, self)?;
+        let stats = self.render(image, georef_image, |tile, bytes| store.insert(tile, &bytes))?;
+        store.finish(&stats, info)?;
+        Ok(stats)
+    }
+
+    /// 并行生成所有瓦片, 每个非透明瓦片编码后交给 write, 瓦片坐标为 XYZ 方向
+    pub fn render<F>(&self, image: BlendImage, georef_image: &str, write: F) -> Result<TileStats>
+    where
+        F: Fn(TileCoord, Vec<u8>) -> Result<()> + Sync,
+    {
+        self.validate()?;
+        let mem_path = BlendImage::mem_path();
+        let result = BlendImage::save_tiff_with(image, &mem_path, Some(georef_image), &TiffOptions::default())
+            .and_then(|_| self.render_dataset(&mem_path, write));
+        let _ = gdal::vsi::unlink_mem_file(&mem_path);
+        result
+    }
+
+    /// 从 GDAL 可读的 RGBA 数据集生成瓦片
+    pub fn render_dataset<F>(&self, path: &str, write: F) -> Result<TileStats>
+    where
+        F: Fn(TileCoord, Vec<u8>) -> Result<()> + Sync,
+    {
+        self.validate()?;
+        let bounds = lon_lat_bounds(&Dataset::open(path)?)?;
+        let tiles: Vec<TileCoord> = (self.zoom.min..=self.zoom.max).flat_map(|z| tiles_in(bounds, z)).collect();
+        let mercator = SpatialRef::from_epsg(3857)?.to_wkt()?;
+
+        let written = AtomicUsize::new(0);
+        tiles
+            .par_iter()
+            .map_init(
+                || None,
+                |source: &mut Option<Dataset>, tile| -> Result<()> {
+                    // 每个线程打开一次数据集
+                    let source = match source {
+                        Some(source) => source,
+                        None => source.insert(Dataset::open(path)?),
+                    };
+                    if let Some(image) = self.render_tile(source, &mercator, *tile)? {
+                        write(*tile, BlendImage::encode_image(image, &self.format)?)?;
+                        written.fetch_add(1, Ordering::Relaxed);
+                    }
+                    Ok(())
+                },
+            )
+            .collect::<Result<Vec<_>>>()?;
+
+        let written = written.into_inner();
+        Ok(TileStats { written, empty: tiles.len() - written, bounds })
+    }
+
+    /// 重投影出一个瓦片, 完全透明时为 None
+    fn render_tile(&self, source: &Dataset, mercator: &str, tile: TileCoord) -> Result<Option<BlendImage>> {
+        let size = self.tile_size;
+        let driver = DriverManager::get_driver_by_name("MEM")?;
+        let mut dataset = driver.create_with_band_type::<u8, _>("", size as isize, size as isize, 4)?;
+        let [min_x, _, max_x, max_y] = tile.bounds();
+        let resolution = (max_x - min_x) / size as f64;
+        dataset.set_geo_transform(&[min_x, resolution, 0.0, max_y, 0.0, -resolution])?;
+        dataset.set_projection(mercator)?;
+        reproject(source, &dataset)?;
+
+        let bands = (1..=4)
+            .map(|index| Ok(dataset.rasterband(index)?.read_as::<u8>((0, 0), (size, size), (size, size), None)?.data))
+            .collect::<Result<Vec<_>>>()?;
+        if bands[3].iter().all(|alpha| *alpha == 0) {
+            return Ok(None);
+        }
+
+        let mut raw_pixels = Vec::with_capacity(size * size * 4);
+        for i in 0..size * size {
+            raw_pixels.extend(bands.iter().map(|band| band[i]));
+        }
+        Ok(Some(BlendImage::from_raw(size as u32, size as u32, raw_pixels)?))
+    }
+}
+
+/// 数据集的经纬度范围 [west, south, east, north], 沿边界取点转换以包含投影后弯曲的边
+pub fn lon_lat_bounds(dataset: &Dataset) -> Result<[f64; 4]> {
+    let transform = dataset
+        .geo_transform()
+        .map_err(|e| BlendError::Georef(format!("tiling needs a georeferenced input: {}", e)))?;
+    let source = dataset
+        .spatial_ref()
+        .map_err(|e| BlendError::Georef(format!("tiling needs an input with a coordinate system: {}", e)))?;
+    let wgs84 = SpatialRef::from_epsg(4326)?;
+    for srs in [&source, &wgs84] {
+        srs.set_axis_mapping_strategy(gdal_sys::OSRAxisMappingStrategy::OAMS_TRADITIONAL_GIS_ORDER);
+    }
+
+    const STEPS: usize = 20;
+    let (width, height) = dataset.raster_size();
+    let (width, height) = (width as f64, height as f64);
+    let (mut xs, mut ys) = (Vec::new(), Vec::new());
+    for i in 0..=STEPS {
+        let t = i as f64 / STEPS as f64;
+        for (px, py) in [(t * width, 0.0), (t * width, height), (0.0, t * height), (width, t * height)] {
+            xs.push(transform[0] + px * transform[1] + py * transform[2]);
+            ys.push(transform[3] + px * transform[4] + py * transform[5]);
+        }
+    }
+    CoordTransform::new(&source, &wgs84)?.transform_coords(&mut xs, &mut ys, &mut [])?;
+
+    let min = |values: &[f64]| values.iter().copied().fold(f64::INFINITY, f64::min);
+    let max = |values: &[f64]| values.iter().copied().fold(f64::NEG_INFINITY, f64::max);
+    Ok([
+        min(&xs).max(-180.0),
+        min(&ys).max(-MAX_LATITUDE),
+        max(&xs).min(180.0),
+        max(&ys).min(MAX_LATITUDE),
+    ])
+}
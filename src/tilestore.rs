@@ -0,0 +1,310 @@
+use std::fs;
+use std::path::Path;
+use std::sync::{Mutex, MutexGuard};
+
+use gdal::spatial_ref::SpatialRef;
+use rusqlite::{params, Connection};
+
+use crate::argparse::Format;
+use crate::error::{BlendError, Result};
+use crate::tiles::{lon_lat_to_mercator, TileCoord, TilePyramid, TileScheme, TileStats, MERCATOR_EXTENT};
+use crate::utils::makedirs;
+
+/// GeoPackage 中瓦片表的表名
+pub const GPKG_TILE_TABLE: &str = "tiles";
+
+/// 瓦片金字塔的输出位置
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum TileOutput {
+    /// 文件夹下的 z/x/y.ext
+    Directory(String),
+    /// MBTiles 1.3, 行号为 TMS 方向
+    MbTiles(String),
+    /// GeoPackage 瓦片表, 行号为 XYZ 方向
+    GeoPackage(String),
+}
+
+impl TileOutput {
+    /// 扩展名为 .mbtiles 或 .gpkg 时为单个文件, 其余为文件夹
+    pub fn parse(path: &str) -> Self {
+        let extension = Path::new(path)
+            .extension()
+            .and_then(|extension| extension.to_str())
+            .map(|extension| extension.to_lowercase());
+        match extension.as_deref() {
+            Some("mbtiles") => Self::MbTiles(path.to_string()),
+            Some("gpkg") => Self::GeoPackage(path.to_string()),
+            _ => Self::Directory(path.to_string()),
+        }
+    }
+
+    pub fn path(&self) -> &str {
+        match self {
+            Self::Directory(path) | Self::MbTiles(path) | Self::GeoPackage(path) => path,
+        }
+    }
+}
+
+/// 写入 MBTiles 和 GeoPackage 元数据的描述信息
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct TileInfo {
+    pub name: String,
+    pub attribution: Option<String>,
+    pub description: Option<String>,
+}
+
+impl TileInfo {
+    pub fn new(name: &str) -> Self {
+        Self {
+            name: name.to_string(),
+            ..Self::default()
+        }
+    }
+
+    pub fn with_attribution(mut self, attribution: Option<String>) -> Self {
+        self.attribution = attribution;
+        self
+    }
+
+    pub fn with_description(mut self, description: Option<String>) -> Self {
+        self.description = description;
+        self
+    }
+}
+
+/// 写入瓦片的目标, 可以在多个线程中同时写入
+pub struct TileStore {
+    output: TileOutput,
+    pyramid: TilePyramid,
+    connection: Option<Mutex<Connection>>,
+}
+
+/// 写入瓦片的线程 panic 后连接处于未知状态, 不再继续写入
+fn poisoned() -> BlendError {
+    BlendError::Io(std::io::Error::other("tile store is unusable after a writer panicked"))
+}
+
+fn lock(connection: &Mutex<Connection>) -> Result<MutexGuard<'_, Connection>> {
+    connection.lock().map_err(|_| poisoned())
+}
+
+impl TileStore {
+    /// 创建输出, 已存在的 MBTiles 或 GeoPackage 文件会被替换
+    pub fn create(output: &TileOutput, pyramid: &TilePyramid) -> Result<Self> {
+        let connection = match output {
+            TileOutput::Directory(folder) => {
+                makedirs(folder)?;
+                None
+            }
+            TileOutput::MbTiles(path) | TileOutput::GeoPackage(path) => {
+                if let Some(parent) = Path::new(path).parent() {
+                    fs::create_dir_all(parent)?;
+                }
+                if Path::new(path).exists() {
+                    fs::remove_file(path)?;
+                }
+                let connection = Connection::open(path)?;
+                let schema = match output {
+                    TileOutput::MbTiles(_) => MBTILES_SCHEMA,
+                    _ => GPKG_SCHEMA,
+                };
+                connection.execute_batch(schema)?;
+                // 所有瓦片在同一个事务中写入, finish 时提交
+                connection.execute_batch("BEGIN")?;
+                Some(Mutex::new(connection))
+            }
+        };
+        Ok(Self {
+            output: output.clone(),
+            pyramid: pyramid.clone(),
+            connection,
+        })
+    }
+
+    /// 写入一个瓦片, tile 为 XYZ 方向
+    pub fn insert(&self, tile: TileCoord, bytes: &[u8]) -> Result<()> {
+        match (&self.output, &self.connection) {
+            (TileOutput::Directory(folder), _) => {
+                let dir = Path::new(folder).join(tile.z.to_string()).join(tile.x.to_string());
+                fs::create_dir_all(&dir)?;
+                let file = format!("{}.{}", tile.row(self.pyramid.scheme), self.pyramid.extension());
+                fs::write(dir.join(file), bytes)?;
+            }
+            (TileOutput::MbTiles(_), Some(connection)) => {
+                lock(connection)?.execute(
+                    "INSERT INTO tiles (zoom_level, tile_column, tile_row, tile_data) VALUES (?1, ?2, ?3, ?4)",
+                    params![tile.z, tile.x, tile.row(TileScheme::Tms), bytes],
+                )?;
+            }
+            (TileOutput::GeoPackage(_), Some(connection)) => {
+                lock(connection)?.execute(
+                    &format!("INSERT INTO {} (zoom_level, tile_column, tile_row, tile_data) VALUES (?1, ?2, ?3, ?4)", GPKG_TILE_TABLE),
+                    params![tile.z, tile.x, tile.y, bytes],
+                )?;
+            }
+            _ => unreachable!("sqlite outputs always have a connection"),
+        }
+        Ok(())
+    }
+
+    /// 写入元数据并提交
+    pub fn finish(self, stats: &TileStats, info: &TileInfo) -> Result<()> {
+        let connection = match self.connection {
+            Some(connection) => connection.into_inner().map_err(|_| poisoned())?,
+            None => return Ok(()),
+        };
+        match self.output {
+            TileOutput::MbTiles(_) => write_mbtiles_metadata(&connection, &self.pyramid, stats, info)?,
+            _ => write_gpkg_metadata(&connection, &self.pyramid, stats, info)?,
+        }
+        connection.execute_batch("COMMIT")?;
+        Ok(())
+    }
+}
+
+const MBTILES_SCHEMA: &str = "
+    CREATE TABLE metadata (name TEXT, value TEXT);
+    CREATE UNIQUE INDEX metadata_name ON metadata (name);
+    CREATE TABLE tiles (zoom_level INTEGER, tile_column INTEGER, tile_row INTEGER, tile_data BLOB);
+    CREATE UNIQUE INDEX tile_index ON tiles (zoom_level, tile_column, tile_row);
+";
+
+const GPKG_SCHEMA: &str = "
+    PRAGMA application_id = 1196444487;
+    PRAGMA user_version = 10300;
+    CREATE TABLE gpkg_spatial_ref_sys (
+        srs_name TEXT NOT NULL,
+        srs_id INTEGER PRIMARY KEY,
+        organization TEXT NOT NULL,
+        organization_coordsys_id INTEGER NOT NULL,
+        definition TEXT NOT NULL,
+        description TEXT
+    );
+    CREATE TABLE gpkg_contents (
+        table_name TEXT NOT NULL PRIMARY KEY,
+        data_type TEXT NOT NULL,
+        identifier TEXT UNIQUE,
+        description TEXT DEFAULT '',
+        last_change DATETIME NOT NULL DEFAULT (strftime('%Y-%m-%dT%H:%M:%fZ', 'now')),
+        min_x DOUBLE,
+        min_y DOUBLE,
+        max_x DOUBLE,
+        max_y DOUBLE,
+        srs_id INTEGER REFERENCES gpkg_spatial_ref_sys (srs_id)
+    );
+    CREATE TABLE gpkg_tile_matrix_set (
+        table_name TEXT NOT NULL PRIMARY KEY REFERENCES gpkg_contents (table_name),
+        srs_id INTEGER NOT NULL REFERENCES gpkg_spatial_ref_sys (srs_id),
+        min_x DOUBLE NOT NULL,
+        min_y DOUBLE NOT NULL,
+        max_x DOUBLE NOT NULL,
+        max_y DOUBLE NOT NULL
+    );
+    CREATE TABLE gpkg_tile_matrix (
+        table_name TEXT NOT NULL REFERENCES gpkg_contents (table_name),
+        zoom_level INTEGER NOT NULL,
+        matrix_width INTEGER NOT NULL,
+        matrix_height INTEGER NOT NULL,
+        tile_width INTEGER NOT NULL,
+        tile_height INTEGER NOT NULL,
+        pixel_x_size DOUBLE NOT NULL,
+        pixel_y_size DOUBLE NOT NULL,
+        PRIMARY KEY (table_name, zoom_level)
+    );
+    CREATE TABLE gpkg_extensions (
+        table_name TEXT,
+        column_name TEXT,
+        extension_name TEXT NOT NULL,
+        definition TEXT NOT NULL,
+        scope TEXT NOT NULL,
+        UNIQUE (table_name, column_name, extension_name)
+    );
+    CREATE TABLE tiles (
+        id INTEGER PRIMARY KEY AUTOINCREMENT,
+        zoom_level INTEGER NOT NULL,
+        tile_column INTEGER NOT NULL,
+        tile_row INTEGER NOT NULL,
+        tile_data BLOB NOT NULL,
+        UNIQUE (zoom_level, tile_column, tile_row)
+    );
+";
+
+fn write_mbtiles_metadata(connection: &Connection, pyramid: &TilePyramid, stats: &TileStats, info: &TileInfo) -> Result<()> {
+    let [west, south, east, north] = stats.bounds;
+    let format = match pyramid.format {
+        Format::JPEG => "jpg".to_string(),
+        ref format => format.format_name(),
+    };
+    let mut metadata = vec![
+        ("name", info.name.clone()),
+        ("format", format),
+        ("type", "baselayer".to_string()),
+        ("version", "1.0".to_string()),
+        ("bounds", format!("{},{},{},{}", west, south, east, north)),
+        ("center", format!("{},{},{}", (west + east) / 2.0, (south + north) / 2.0, pyramid.zoom.min)),
+        ("minzoom", pyramid.zoom.min.to_string()),
+        ("maxzoom", pyramid.zoom.max.to_string()),
+    ];
+    if let Some(attribution) = &info.attribution {
+        metadata.push(("attribution", attribution.clone()));
+    }
+    if let Some(description) = &info.description {
+        metadata.push(("description", description.clone()));
+    }
+
+    let mut statement = connection.prepare("INSERT INTO metadata (name, value) VALUES (?1, ?2)")?;
+    for (name, value) in metadata {
+        statement.execute(params![name, value])?;
+    }
+    Ok(())
+}
+
+/// 瓦片矩阵集为整个 Web Mercator 范围, 与 XYZ 瓦片一一对应
+fn write_gpkg_metadata(connection: &Connection, pyramid: &TilePyramid, stats: &TileStats, info: &TileInfo) -> Result<()> {
+    let mut statement = connection.prepare(
+        "INSERT INTO gpkg_spatial_ref_sys (srs_name, srs_id, organization, organization_coordsys_id, definition, description)
+         VALUES (?1, ?2, ?3, ?4, ?5, ?6)",
+    )?;
+    statement.execute(params!["Undefined cartesian SRS", -1, "NONE", -1, "undefined", "undefined cartesian coordinate reference system"])?;
+    statement.execute(params!["Undefined geographic SRS", 0, "NONE", 0, "undefined", "undefined geographic coordinate reference system"])?;
+    statement.execute(params!["WGS 84 geodetic", 4326, "EPSG", 4326, SpatialRef::from_epsg(4326)?.to_wkt()?, "longitude/latitude coordinates in decimal degrees on the WGS 84 spheroid"])?;
+    statement.execute(params!["WGS 84 / Pseudo-Mercator", 3857, "EPSG", 3857, SpatialRef::from_epsg(3857)?.to_wkt()?, "Web Mercator"])?;
+
+    // 内容的范围为数据范围, 说明中包含版权信息
+    let [west, south, east, north] = stats.bounds;
+    let (min_x, min_y) = lon_lat_to_mercator(west, south);
+    let (max_x, max_y) = lon_lat_to_mercator(east, north);
+    let description = [info.description.as_deref(), info.attribution.as_deref()]
+        .into_iter()
+        .flatten()
+        .collect::<Vec<_>>()
+        .join("\n");
+    connection.execute(
+        "INSERT INTO gpkg_contents (table_name, data_type, identifier, description, min_x, min_y, max_x, max_y, srs_id)
+         VALUES (?1, 'tiles', ?2, ?3, ?4, ?5, ?6, ?7, 3857)",
+        params![GPKG_TILE_TABLE, info.name, description, min_x, min_y, max_x, max_y],
+    )?;
+    connection.execute(
+        "INSERT INTO gpkg_tile_matrix_set (table_name, srs_id, min_x, min_y, max_x, max_y) VALUES (?1, 3857, ?2, ?2, ?3, ?3)",
+        params![GPKG_TILE_TABLE, -MERCATOR_EXTENT, MERCATOR_EXTENT],
+    )?;
+
+    let mut statement = connection.prepare(
+        "INSERT INTO gpkg_tile_matrix (table_name, zoom_level, matrix_width, matrix_height, tile_width, tile_height, pixel_x_size, pixel_y_size)
+         VALUES (?1, ?2, ?3, ?3, ?4, ?4, ?5, ?5)",
+    )?;
+    for z in pyramid.zoom.min..=pyramid.zoom.max {
+        let count = 1u64 << z;
+        let pixel_size = 2.0 * MERCATOR_EXTENT / (count as f64 * pyramid.tile_size as f64);
+        statement.execute(params![GPKG_TILE_TABLE, z, count, pyramid.tile_size, pixel_size])?;
+    }
+
+    if pyramid.format == Format::WEBP {
+        connection.execute(
+            "INSERT INTO gpkg_extensions (table_name, column_name, extension_name, definition, scope)
+             VALUES (?1, 'tile_data', 'gpkg_webp', 'http://www.geopackage.org/spec/#extension_tiles_webp', 'read-write')",
+            params![GPKG_TILE_TABLE],
+        )?;
+    }
+    Ok(())
+}
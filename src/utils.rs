@@ -1,13 +1,16 @@
 use std::{fs::create_dir_all, path::Path};
 
-use anyhow::{anyhow, Result};
+use crate::error::{BlendError, Result};
 
 pub fn makedirs(path: &str) -> Result<()>{
     let p = Path::new(&path);
 
     if p.exists(){
         if p.is_file(){
-            return Err(anyhow!("{} is a file not a directory", path));
+            return Err(BlendError::Io(std::io::Error::new(
+                std::io::ErrorKind::AlreadyExists,
+                format!("{} is a file not a directory", path),
+            )));
         }
     }else {
         create_dir_all(path)?;  
@@ -0,0 +1,88 @@
+use std::path::Path;
+
+use gdal::{Dataset, GeoTransform};
+
+use crate::argparse::Format;
+use crate::error::{BlendError, Result};
+
+/// 输出图像的地理参考信息: 仿射变换和坐标系 WKT
+///
+/// png/jpeg/webp 不能保存地理参考信息, 保存时写为同名的 world file 和 GDAL 的 .aux.xml,
+/// QGIS 等软件打开图像时会自动读取.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Georeference {
+    pub geo_transform: GeoTransform,
+    /// 为空时只写仿射变换
+    pub projection: String,
+}
+
+impl Georeference {
+    pub fn new(geo_transform: GeoTransform, projection: String) -> Self {
+        Self { geo_transform, projection }
+    }
+
+    /// 读取 georef_image 的地理参考信息, GDAL 无法打开或没有仿射变换时返回 None
+    ///
+    /// 图像大小与 size 不一致时仿射变换不再适用, 返回 SizeMismatch.
+    pub fn open(georef_image: &str, size: (u32, u32)) -> Result<Option<Self>> {
+        let Ok(dataset) = Dataset::open(georef_image) else {
+            return Ok(None);
+        };
+        let Ok(geo_transform) = dataset.geo_transform() else {
+            return Ok(None);
+        };
+        let (width, height) = dataset.raster_size();
+        if (width as u32, height as u32) != size {
+            return Err(BlendError::SizeMismatch {
+                expected: (width as u32, height as u32),
+                actual: size,
+            });
+        }
+        Ok(Some(Self::new(geo_transform, dataset.projection())))
+    }
+
+    /// world file 的六行: 像素大小、旋转和左上角像素中心的坐标
+    pub fn world_file(&self) -> String {
+        let [x, a, b, y, d, e] = self.geo_transform;
+        [a, d, b, e, x + (a + b) / 2.0, y + (d + e) / 2.0]
+            .iter()
+            .map(|value| format!("{}\n", value))
+            .collect()
+    }
+
+    /// GDAL 的 PAM 文件内容, 包含坐标系和仿射变换
+    pub fn aux_xml(&self) -> String {
+        let mut xml = String::from("<PAMDataset>\n");
+        if !self.projection.is_empty() {
+            xml.push_str(&format!("  <SRS>{}</SRS>\n", escape_xml(&self.projection)));
+        }
+        let geo_transform = self.geo_transform.map(|value| format!("{:.16e}", value)).join(", ");
+        xml.push_str(&format!("  <GeoTransform>{}</GeoTransform>\n", geo_transform));
+        xml.push_str("</PAMDataset>\n");
+        xml
+    }
+
+    /// 在 image_path 旁写入 world file 和 .aux.xml
+    pub fn write_sidecars(&self, image_path: &str, format: &Format) -> Result<()> {
+        std::fs::write(world_file_path(image_path, format), self.world_file())?;
+        std::fs::write(format!("{}.aux.xml", image_path), self.aux_xml())?;
+        Ok(())
+    }
+}
+
+/// world file 的路径: png 为 .pgw, jpeg 为 .jgw, 其他格式为 .wld
+pub fn world_file_path(image_path: &str, format: &Format) -> String {
+    let extension = match format {
+        Format::PNG => "pgw",
+        Format::JPEG => "jgw",
+        _ => "wld",
+    };
+    Path::new(image_path).with_extension(extension).to_string_lossy().into_owned()
+}
+
+fn escape_xml(text: &str) -> String {
+    text.replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+        .replace('"', "&quot;")
+}
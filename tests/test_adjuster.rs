@@ -0,0 +1,55 @@
+use blend_images::adjuster::{register_adjuster, AdjustStep, Adjuster, AdjusterChain, LutAdjuster};
+use image::Rgba;
+
+struct Invert;
+
+impl Adjuster for Invert {
+    fn adjust_pixel(&self, pixel: &mut Rgba<u8>) {
+        pixel[0] = 255 - pixel[0];
+        pixel[1] = 255 - pixel[1];
+        pixel[2] = 255 - pixel[2];
+    }
+}
+
+#[test]
+fn test_lut_adjusters() {
+    let mut pixel = Rgba([0, 128, 255, 7]);
+    LutAdjuster::levels(0, 255, 1.0, 0, 255).unwrap().adjust_pixel(&mut pixel);
+    assert_eq!(pixel, Rgba([0, 128, 255, 7]));
+    assert!(LutAdjuster::levels(0, 255, 1.0, 0, 255).unwrap().is_noop());
+    assert!(LutAdjuster::levels(200, 100, 1.0, 0, 255).is_err());
+
+    let curves = LutAdjuster::curves(&[[0, 0], [100, 200], [200, 200]]).unwrap();
+    let mut pixel = Rgba([50, 100, 250, 7]);
+    curves.adjust_pixel(&mut pixel);
+    assert_eq!(pixel, Rgba([100, 200, 200, 7]));
+}
+
+#[test]
+fn test_adjuster_chain() {
+    let steps = vec![
+        AdjustStep::parse("levels:black=0;white=255").unwrap(),
+        AdjustStep::parse("curves:points=[[0,255],[255,0]]").unwrap(),
+    ];
+    let chain = AdjusterChain::from_steps(&steps).unwrap();
+    // 色阶没有改变像素, 被跳过
+    assert_eq!(chain.len(), 1);
+
+    let mut row = vec![0, 10, 255, 255, 100, 100, 100, 0];
+    chain.adjust_row(&mut row);
+    assert_eq!(row, vec![255, 245, 0, 255, 155, 155, 155, 0]);
+
+    assert!(AdjustStep::parse("unknown").is_err());
+    assert!(AdjustStep::parse("levels:black").is_err());
+}
+
+#[test]
+fn test_register_adjuster() {
+    register_adjuster("invert", |_| Ok(Box::new(Invert))).unwrap();
+    assert!(register_adjuster("invert", |_| Ok(Box::new(Invert))).is_err());
+
+    let chain = AdjusterChain::from_steps(&[AdjustStep::parse("invert").unwrap()]).unwrap();
+    let mut pixel = Rgba([0, 100, 255, 9]);
+    chain.adjust_pixel(&mut pixel);
+    assert_eq!(pixel, Rgba([255, 155, 0, 9]));
+}
@@ -0,0 +1,28 @@
+use blend_images::bands::{BandMapping, Stretch};
+
+#[test]
+fn test_parse_band_mapping() {
+    let mapping: BandMapping = "8,4,3".parse().unwrap();
+    assert_eq!(mapping.rgb, [8, 4, 3]);
+    assert_eq!(mapping.alpha, None);
+    assert_eq!(mapping.stretch, Stretch::Percentile(2.0, 98.0));
+
+    let mapping: BandMapping = "4, 3, 2, 5".parse().unwrap();
+    assert_eq!(mapping.alpha, Some(5));
+
+    assert!("8,4".parse::<BandMapping>().is_err());
+    assert!("8,0,3".parse::<BandMapping>().is_err());
+}
+
+#[test]
+fn test_parse_stretch() {
+    assert_eq!("none".parse::<Stretch>(), Ok(Stretch::None));
+    assert_eq!("MinMax".parse::<Stretch>(), Ok(Stretch::MinMax));
+    assert_eq!("percentile:1,99".parse::<Stretch>(), Ok(Stretch::Percentile(1.0, 99.0)));
+    assert_eq!("stddev".parse::<Stretch>(), Ok(Stretch::StdDev(2.0)));
+    assert_eq!("stddev:2.5".parse::<Stretch>(), Ok(Stretch::StdDev(2.5)));
+
+    assert!("percentile:98,2".parse::<Stretch>().is_err());
+    assert!("stddev:-1".parse::<Stretch>().is_err());
+    assert!("gamma".parse::<Stretch>().is_err());
+}
@@ -0,0 +1,187 @@
+use std::path::{Path, PathBuf};
+
+use blend_images::argparse::{ArgParse, BatchArgs, PairBy};
+use blend_images::batch::{load_manifest, pair_directories};
+use blend_images::core::run;
+use blend_images::error::{exit_code, BlendError};
+use clap::Parser;
+use image::{Rgba, RgbaImage};
+
+fn temp_folder(name: &str) -> PathBuf {
+    let folder = std::env::temp_dir().join(format!("blend_images_batch_{}_{}", name, std::process::id()));
+    let _ = std::fs::remove_dir_all(&folder);
+    std::fs::create_dir_all(&folder).unwrap();
+    folder
+}
+
+fn write_png(path: &Path, value: u8) {
+    std::fs::create_dir_all(path.parent().unwrap()).unwrap();
+    RgbaImage::from_pixel(4, 4, Rgba([value, value, value, 255])).save(path).unwrap();
+}
+
+#[test]
+fn test_csv_manifest() {
+    let folder = temp_folder("csv");
+    let manifest = folder.join("pairs.csv");
+    std::fs::write(&manifest, "base,overlay,mode,output\ndem/a.png, hs/a.png ,multiply,out/a.png\n/abs/b.png,hs/b.png,,\n").unwrap();
+
+    let pairs = load_manifest(manifest.to_str().unwrap()).unwrap();
+    assert_eq!(pairs.len(), 2);
+    assert_eq!(pairs[0].image, folder.join("dem/a.png").to_str().unwrap());
+    assert_eq!(pairs[0].image2, folder.join("hs/a.png").to_str().unwrap());
+    assert_eq!(pairs[0].blend_mode.as_deref(), Some("multiply"));
+    assert_eq!(pairs[0].output.as_deref(), folder.join("out/a.png").to_str());
+    // 绝对路径保持不变, 空列为 None
+    assert_eq!(pairs[1].image, "/abs/b.png");
+    assert_eq!(pairs[1].blend_mode, None);
+    assert_eq!(pairs[1].output, None);
+    std::fs::remove_dir_all(folder).unwrap();
+}
+
+#[test]
+fn test_json_manifest() {
+    let folder = temp_folder("json");
+    let manifest = folder.join("pairs.json");
+    std::fs::write(&manifest, r#"[{"base": "a.png", "overlay": "b.png", "output": "../out/ab.png"}, {"image": "c.png", "image2": "d.png", "blend_mode": "screen"}]"#).unwrap();
+
+    let pairs = load_manifest(manifest.to_str().unwrap()).unwrap();
+    assert_eq!(pairs.len(), 2);
+    assert_eq!(pairs[0].output.as_deref(), folder.join("../out/ab.png").to_str());
+    assert_eq!(pairs[1].image2, folder.join("d.png").to_str().unwrap());
+    assert_eq!(pairs[1].blend_mode.as_deref(), Some("screen"));
+
+    assert!(load_manifest(folder.join("pairs.txt").to_str().unwrap()).is_err());
+    std::fs::write(&manifest, r#"[{"base": "a.png"}]"#).unwrap();
+    assert!(load_manifest(manifest.to_str().unwrap()).is_err());
+    std::fs::remove_dir_all(folder).unwrap();
+}
+
+fn batch_args(pattern: &str, pair_by: PairBy, base_regex: Option<&str>, overlay_regex: Option<&str>) -> BatchArgs {
+    BatchArgs {
+        manifest: None,
+        base_dir: None,
+        overlay_dir: None,
+        pattern: pattern.to_string(),
+        pair_by,
+        base_regex: base_regex.map(str::to_string),
+        overlay_regex: overlay_regex.map(str::to_string),
+        min_overlap: 0.5,
+    }
+}
+
+/// 配对结果, 路径相对于 folder
+fn pairs(folder: &Path, batch: &BatchArgs) -> Vec<(String, String, Option<String>)> {
+    let relative = |path: &str| Path::new(path).strip_prefix(folder).unwrap().to_str().unwrap().to_string();
+    let base_dir = folder.join("dem");
+    let overlay_dir = folder.join("hs");
+    let mut pairs: Vec<_> = pair_directories(base_dir.to_str().unwrap(), overlay_dir.to_str().unwrap(), batch)
+        .unwrap()
+        .into_iter()
+        .map(|pair| (relative(&pair.image), relative(&pair.image2), pair.relative_dir))
+        .collect();
+    pairs.sort();
+    pairs
+}
+
+#[test]
+fn test_pair_directories() {
+    let folder = temp_folder("pair");
+    for name in ["dem/a.png", "dem/b.png", "dem/12/3370/1552.png", "dem/13/3370/1552.png", "dem/dem_J50E001.png"] {
+        write_png(&folder.join(name), 100);
+    }
+    for name in ["hs/a.png", "hs/c.png", "hs/12/3370/1552.png", "hs/13/3370/1552.png", "hs/hs_J50E001.png"] {
+        write_png(&folder.join(name), 200);
+    }
+    // 输出 png 时写入的附属文件不参与配对
+    std::fs::write(folder.join("hs/a.pgw"), "1\n0\n0\n-1\n0\n0\n").unwrap();
+    std::fs::write(folder.join("hs/a.png.aux.xml"), "<PAMDataset/>").unwrap();
+
+    let stem = pairs(&folder, &batch_args("*", PairBy::Stem, None, None));
+    assert_eq!(stem, [("dem/a.png".to_string(), "hs/a.png".to_string(), None)]);
+
+    let path = pairs(&folder, &batch_args("**/*", PairBy::Path, None, None));
+    assert_eq!(
+        path,
+        [
+            ("dem/12/3370/1552.png".to_string(), "hs/12/3370/1552.png".to_string(), Some("12/3370".to_string())),
+            ("dem/13/3370/1552.png".to_string(), "hs/13/3370/1552.png".to_string(), Some("13/3370".to_string())),
+            ("dem/a.png".to_string(), "hs/a.png".to_string(), None),
+        ]
+    );
+
+    let regex = pairs(&folder, &batch_args("*", PairBy::Regex, Some(r"dem_(\w+)\.png"), Some(r"hs_(\w+)\.png")));
+    assert_eq!(regex, [("dem/dem_J50E001.png".to_string(), "hs/hs_J50E001.png".to_string(), None)]);
+
+    // 各级目录中的同名瓦片按文件名配对时 key 重复
+    let base_dir = folder.join("dem");
+    let overlay_dir = folder.join("hs");
+    let err = pair_directories(base_dir.to_str().unwrap(), overlay_dir.to_str().unwrap(), &batch_args("**/*", PairBy::Stem, None, None))
+        .unwrap_err()
+        .to_string();
+    assert!(err.contains("12/3370/1552.png") && err.contains("13/3370/1552.png"), "{}", err);
+    std::fs::remove_dir_all(folder).unwrap();
+}
+
+#[test]
+fn test_batch_errors() {
+    let folder = temp_folder("errors");
+    write_png(&folder.join("dem/a.png"), 100);
+    write_png(&folder.join("hs/a.png"), 200);
+    let (base_dir, overlay_dir) = (folder.join("dem"), folder.join("hs"));
+    let (base_dir, overlay_dir) = (base_dir.to_str().unwrap(), overlay_dir.to_str().unwrap());
+
+    let missing = folder.join("missing.csv");
+    assert!(matches!(load_manifest(missing.to_str().unwrap()), Err(BlendError::Io(_))));
+    let batch = batch_args("*", PairBy::Regex, Some("a(.png"), None);
+    assert!(matches!(pair_directories(base_dir, overlay_dir, &batch), Err(BlendError::InvalidParameter(_))));
+    let batch = batch_args("[", PairBy::Stem, None, None);
+    assert!(matches!(pair_directories(base_dir, overlay_dir, &batch), Err(BlendError::InvalidParameter(_))));
+
+    // 命令行按错误类型返回退出码
+    let args = ArgParse::parse_from(["blend_images", "batch", "--manifest", missing.to_str().unwrap()]);
+    assert_eq!(exit_code(&run(&args).unwrap_err()), 3);
+    let args = ArgParse::parse_from([
+        "blend_images",
+        "batch",
+        "--base-dir",
+        base_dir,
+        "--overlay-dir",
+        overlay_dir,
+        "--pair-by",
+        "regex",
+        "--base-regex",
+        "a(.png",
+    ]);
+    assert_eq!(exit_code(&run(&args).unwrap_err()), 8);
+    std::fs::remove_dir_all(folder).unwrap();
+}
+
+#[test]
+fn test_batch_failure() {
+    let folder = temp_folder("failure");
+    write_png(&folder.join("a.png"), 200);
+    write_png(&folder.join("b.png"), 100);
+    let manifest = folder.join("pairs.csv");
+    std::fs::write(&manifest, "base,overlay,mode,output\na.png,b.png,,\na.png,missing.png,,\na.png,b.png,unknown,\n").unwrap();
+    let output = folder.join("out");
+
+    let args = ArgParse::parse_from([
+        "blend_images",
+        "-o",
+        &format!("{}/", output.to_str().unwrap()),
+        "--output-template",
+        "{stem}_{index}.{format}",
+        "-m",
+        "multiply",
+        "batch",
+        "--manifest",
+        manifest.to_str().unwrap(),
+    ]);
+    let err = run(&args).unwrap_err();
+    assert_eq!(err.to_string(), "2 of 3 batch items failed");
+    assert_ne!(exit_code(&err), 0);
+    // 失败的行不影响其他行
+    assert!(output.join("a_1.png").exists());
+    assert!(!output.join("a_2.png").exists());
+    std::fs::remove_dir_all(folder).unwrap();
+}
@@ -0,0 +1,35 @@
+use blend_images::blend::{BlendImage, BlendManager};
+use blend_images::blend_op::{blend_op, register_blend_op, BlendOp};
+use blend_images::pipeline::BlendJob;
+
+struct Average;
+
+impl BlendOp for Average {
+    fn name(&self) -> &str {
+        "average"
+    }
+
+    fn blend_pixel(&self, dst: [f32; 4], src: [f32; 4]) -> [f32; 4] {
+        [
+            (dst[0] + src[0]) / 2.0,
+            (dst[1] + src[1]) / 2.0,
+            (dst[2] + src[2]) / 2.0,
+            dst[3],
+        ]
+    }
+}
+
+#[test]
+fn test_register_blend_op() {
+    register_blend_op(Average).unwrap();
+    assert!(register_blend_op(Average).is_err());
+    assert_eq!(blend_op("AVERAGE").unwrap().name(), "average");
+
+    let mut image = BlendImage::from_raw(1, 1, vec![200, 0, 100, 255]).unwrap();
+    let image2 = BlendImage::from_raw(1, 1, vec![0, 200, 100, 128]).unwrap();
+    BlendManager::blend(&mut image, &image2, "average").unwrap();
+    assert_eq!(image.raw_pixels(), &[100, 100, 100, 255]);
+
+    assert!(BlendJob::builder("a.png", "b.png").blend_mode("average").build().is_ok());
+    assert!(BlendJob::builder("a.png", "b.png").blend_mode("unknown").build().is_err());
+}
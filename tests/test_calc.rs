@@ -0,0 +1,37 @@
+use blend_images::calc::CalcResult;
+use blend_images::expr::Expression;
+
+#[test]
+fn test_parse_with_slots() {
+    let resolve = |name: &str| match name {
+        "nir" => Some(0),
+        "red" => Some(1),
+        _ => None,
+    };
+    let ndvi = Expression::parse_with("(NIR - red) / (nir + red)", resolve).unwrap();
+    assert_eq!(ndvi.evaluate_slots(&[0.75, 0.25]), 0.5);
+
+    let err = Expression::parse_with("nir * base", resolve).unwrap_err();
+    assert_eq!(err.position, 6);
+    assert!(Expression::parse_with("nir * pi", resolve).is_ok());
+}
+
+#[test]
+fn test_calc_result_layer() {
+    let result = CalcResult {
+        width: 4,
+        height: 1,
+        data: vec![10.0, 20.0, -9999.0, 30.0],
+        nodata: -9999.0,
+        geo_transform: None,
+        projection: String::new(),
+    };
+    assert_eq!(result.range(), Some((10.0, 30.0)));
+
+    let image = result.to_blend_image(None).unwrap();
+    assert_eq!(image.raw_pixels(), &[0, 0, 0, 255, 128, 128, 128, 255, 0, 0, 0, 0, 255, 255, 255, 255]);
+
+    let image = result.to_blend_image(Some((0.0, 20.0))).unwrap();
+    assert_eq!(&image.raw_pixels()[12..], &[255, 255, 255, 255]);
+    assert!(result.to_blend_image(Some((1.0, 0.0))).is_err());
+}
@@ -0,0 +1,24 @@
+use blend_images::blend::{BlendImage, BlendManager};
+use blend_images::error::BlendError;
+
+#[test]
+fn test_from_raw_rejects_wrong_length() {
+    let err = BlendImage::from_raw(2, 2, vec![0; 15]).unwrap_err();
+    assert!(matches!(err, BlendError::InvalidParameter(_)));
+    assert_eq!(err.exit_code(), 8);
+}
+
+#[test]
+fn test_blend_size_mismatch() {
+    let mut image = BlendImage::from_raw(2, 2, vec![255; 16]).unwrap();
+    let image2 = BlendImage::from_raw(1, 2, vec![255; 8]).unwrap();
+
+    let err = BlendManager::blend(&mut image, &image2, "multiply").unwrap_err();
+    assert!(matches!(err, BlendError::SizeMismatch { expected: (2, 2), actual: (1, 2) }));
+}
+
+#[test]
+fn test_decode_error() {
+    let err = BlendImage::from_bytes(b"not an image").unwrap_err();
+    assert!(matches!(err, BlendError::UnsupportedFormat(_) | BlendError::Decode(_)));
+}
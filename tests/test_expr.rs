@@ -0,0 +1,77 @@
+use blend_images::blend::{BlendImage, BlendManager};
+use blend_images::expr::{Expression, PixelVars, MAX_LENGTH};
+
+fn vars(base: [f32; 4], top: [f32; 4]) -> PixelVars {
+    PixelVars { base, top, channel: 0, x: 3, y: 5 }
+}
+
+#[test]
+fn test_evaluate() {
+    let v = vars([0.5, 0.2, 0.0, 1.0], [1.0, 0.0, 0.0, 0.25]);
+    let eval = |source: &str| Expression::parse(source).unwrap().evaluate(&v);
+
+    assert_eq!(eval("base * (0.4 + 0.6 * top)"), 0.5);
+    assert_eq!(eval("a * b"), 0.5);
+    assert_eq!(eval("1 + 2 * 3 - 4 / 2"), 5.0);
+    assert_eq!(eval("-2^2"), -4.0);
+    assert_eq!(eval("2^3^2"), 512.0);
+    assert_eq!(eval("a.g + top.alpha"), 0.45);
+    assert_eq!(eval("a > 0.4 && b < 1 ? 1 : 0"), 0.0);
+    assert_eq!(eval("if(a >= 0.5, x, y)"), 3.0);
+    assert_eq!(eval("min(a, b) + max(1e-1, 0)"), 0.6);
+    assert_eq!(eval("mix(0, 10, .5)"), 5.0);
+}
+
+#[test]
+fn test_parse_errors() {
+    let err = Expression::parse("base * (0.4 + ").unwrap_err();
+    assert_eq!(err.position, 14);
+    assert!(err.message.contains("end of expression"));
+
+    let err = Expression::parse("base * foo").unwrap_err();
+    assert_eq!(err.position, 7);
+    assert!(err.message.contains("unknown variable 'foo'"));
+
+    let err = Expression::parse("min(a)").unwrap_err();
+    assert_eq!(err.position, 0);
+    assert!(err.message.contains("takes 2 arguments"));
+
+    let err = Expression::parse("a.q").unwrap_err();
+    assert_eq!(err.position, 2);
+
+    let err = Expression::parse("a $ b").unwrap_err();
+    assert_eq!(err.position, 2);
+
+    assert!(Expression::parse("a b").is_err());
+
+    // 过深的嵌套返回错误而不是栈溢出
+    let err = Expression::parse(&format!("{}a{}", "(".repeat(1000), ")".repeat(1000))).unwrap_err();
+    assert!(err.message.contains("nested"));
+    assert!(Expression::parse(&format!("{}a", "-".repeat(2000))).is_err());
+    assert!(Expression::parse(&format!("{}a", "a ? b : ".repeat(400))).is_err());
+    assert!(Expression::parse(&format!("{}a{}", "(".repeat(100), ")".repeat(100))).is_ok());
+
+    // 很长的 a+a+...+a 为左深的语法树, 同样受深度限制
+    let err = Expression::parse(&format!("a{}", "+a".repeat(1000))).unwrap_err();
+    assert!(err.message.contains("nested"));
+    assert!(Expression::parse(&format!("a{}", "*a-a".repeat(500))).is_err());
+    let sum = Expression::parse(&format!("a{}", "+a".repeat(200))).unwrap();
+    assert_eq!(sum.evaluate(&vars([0.5, 0.0, 0.0, 1.0], [0.0; 4])), 100.5);
+
+    // 过长的表达式在解析前被拒绝
+    let err = Expression::parse(&format!("a{}", " ".repeat(MAX_LENGTH))).unwrap_err();
+    assert!(err.message.contains("longer"));
+    assert!(err.source.len() < 100);
+    assert!(Expression::parse(&format!("a{}", " ".repeat(MAX_LENGTH - 1))).is_ok());
+}
+
+#[test]
+fn test_expr_blend_mode() {
+    let mut image = BlendImage::from_raw(2, 1, vec![255, 128, 0, 200, 255, 128, 0, 200]).unwrap();
+    let image2 = BlendImage::from_raw(2, 1, vec![0, 255, 255, 255, 0, 255, 255, 255]).unwrap();
+
+    BlendManager::blend(&mut image, &image2, "expr:x == 0 ? top : base").unwrap();
+    assert_eq!(image.raw_pixels(), &[0, 255, 255, 200, 255, 128, 0, 200]);
+
+    assert!(BlendManager::blend(&mut image, &image2, "expr:top *").is_err());
+}
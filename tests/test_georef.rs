@@ -0,0 +1,89 @@
+use blend_images::blend::BlendImage;
+use blend_images::error::BlendError;
+use blend_images::georef::{check_grid, GeorefSource, GridMismatch};
+use blend_images::pipeline::BlendJob;
+use gdal::raster::ColorInterpretation;
+use gdal::spatial_ref::SpatialRef;
+use gdal::{Dataset, DriverManager, Metadata};
+
+/// 在临时文件夹中创建 4x4 的 GeoTIFF, 像素大小为 10 米, 数据集关闭时写入文件
+fn create_geotiff(name: &str, bands: isize, origin: (f64, f64), epsg: u32) -> (String, Dataset) {
+    let path = std::env::temp_dir().join(format!("blend_images_georef_{}_{}", std::process::id(), name));
+    let path = path.to_str().unwrap().to_string();
+    let driver = DriverManager::get_driver_by_name("GTiff").unwrap();
+    let mut dataset = driver.create(&path, 4, 4, bands).unwrap();
+    dataset.set_geo_transform(&[origin.0, 10.0, 0.0, origin.1, 0.0, -10.0]).unwrap();
+    dataset.set_projection(&SpatialRef::from_epsg(epsg).unwrap().to_wkt().unwrap()).unwrap();
+    (path, dataset)
+}
+
+fn write_geotiff(name: &str, origin: (f64, f64), epsg: u32) -> String {
+    create_geotiff(name, 1, origin, epsg).0
+}
+
+#[test]
+fn test_georef_from() {
+    let job = BlendJob::builder("dem.tif", "hillshade.tif").swap_layers(true).build().unwrap();
+    assert_eq!(job.georef_path(), Some("dem.tif"));
+
+    let job = BlendJob::builder("dem.tif", "hillshade.tif").georef_from(GeorefSource::Overlay).build().unwrap();
+    assert_eq!(job.georef_path(), Some("hillshade.tif"));
+
+    let job = BlendJob::builder("-", "hillshade.tif").build().unwrap();
+    assert_eq!(job.georef_path(), None);
+}
+
+#[test]
+fn test_check_grid_without_georeferencing() {
+    // 没有地理参考信息的输入不参与比较
+    check_grid("missing_base.png", "missing_overlay.png", GridMismatch::Fail).unwrap();
+    check_grid("missing_base.tif", "missing_overlay.tif", GridMismatch::Ignore).unwrap();
+}
+
+#[test]
+fn test_check_grid_mismatch() {
+    let base = write_geotiff("base.tif", (500000.0, 4400000.0), 32650);
+    let same = write_geotiff("same.tif", (500000.0, 4400000.0), 32650);
+    let shifted = write_geotiff("shifted.tif", (500005.0, 4400000.0), 32650);
+    let other_crs = write_geotiff("other_crs.tif", (500000.0, 4400000.0), 32651);
+
+    check_grid(&base, &same, GridMismatch::Fail).unwrap();
+    assert!(matches!(check_grid(&base, &shifted, GridMismatch::Fail), Err(BlendError::Georef(_))));
+    assert!(matches!(check_grid(&base, &other_crs, GridMismatch::Fail), Err(BlendError::Georef(_))));
+    // warn 只输出警告
+    check_grid(&base, &shifted, GridMismatch::Warn).unwrap();
+    check_grid(&base, &other_crs, GridMismatch::Ignore).unwrap();
+
+    for path in [base, same, shifted, other_crs] {
+        std::fs::remove_file(path).unwrap();
+    }
+}
+
+#[test]
+fn test_copy_metadata() {
+    let (source, mut dataset) = create_geotiff("source.tif", 4, (500000.0, 4400000.0), 32650);
+    {
+        dataset.set_metadata_item("AREA_NAME", "beijing", "").unwrap();
+        dataset.set_metadata_item("SENSOR", "MSI", "SENTINEL2").unwrap();
+        let mut band = dataset.rasterband(4).unwrap();
+        band.set_color_interpretation(ColorInterpretation::AlphaBand).unwrap();
+        band.set_description("mask").unwrap();
+    }
+    dataset.close().unwrap();
+
+    let output = source.replace("source.tif", "output.tif");
+    let image = BlendImage::from_raw(4, 4, [10, 20, 30, 255].repeat(16)).unwrap();
+    BlendImage::save_tiff(image, &output, Some(&source)).unwrap();
+
+    let dataset = Dataset::open(&output).unwrap();
+    assert_eq!(dataset.metadata_item("AREA_NAME", "").as_deref(), Some("beijing"));
+    assert_eq!(dataset.metadata_item("SENSOR", "SENTINEL2").as_deref(), Some("MSI"));
+    let band = dataset.rasterband(4).unwrap();
+    assert_eq!(band.color_interpretation(), ColorInterpretation::AlphaBand);
+    assert_eq!(band.description().unwrap(), "mask");
+    assert_eq!(dataset.geo_transform().unwrap(), [500000.0, 10.0, 0.0, 4400000.0, 0.0, -10.0]);
+
+    for path in [source, output] {
+        std::fs::remove_file(path).unwrap();
+    }
+}
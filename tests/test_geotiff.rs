@@ -0,0 +1,128 @@
+use blend_images::blend::BlendImage;
+use blend_images::geotiff::{validate_cog, Compression, Photometric, Predictor, TiffOptions};
+use gdal::{Dataset, Metadata};
+
+const DEM: &str = "data/testdem.tif";
+
+fn cog() -> TiffOptions {
+    TiffOptions { cog: true, ..TiffOptions::default() }
+}
+
+#[test]
+fn test_cog_creation_options() {
+    let options = TiffOptions {
+        compress: Some(Compression::Zstd),
+        level: Some(15),
+        predictor: Some(Predictor::Horizontal),
+        ..cog()
+    };
+    options.validate().unwrap();
+
+    let creation_options = options.cog_options();
+    let has = |key: &str, value: &str| creation_options.contains(&(key.to_string(), value.to_string()));
+    assert!(has("BLOCKSIZE", "512"));
+    assert!(has("COMPRESS", "ZSTD"));
+    assert!(has("LEVEL", "15"));
+    assert!(has("PREDICTOR", "STANDARD"));
+    assert!(has("RESAMPLING", "AVERAGE"));
+}
+
+#[test]
+fn test_gtiff_creation_options() {
+    assert_eq!(TiffOptions::default().gtiff_options(None), vec![("COMPRESS".to_string(), "LZW".to_string())]);
+    assert_eq!(TiffOptions::default().gtiff_options(Some("DEFLATE".to_string()))[0].1, "DEFLATE");
+
+    let mut options = TiffOptions {
+        compress: Some(Compression::Deflate),
+        level: Some(9),
+        predictor: Some(Predictor::Horizontal),
+        block_size: Some(256),
+        ..TiffOptions::default()
+    };
+    options.co.insert("ZLEVEL".to_string(), "6".to_string());
+    options.co.insert("NUM_THREADS".to_string(), "ALL_CPUS".to_string());
+    options.validate().unwrap();
+
+    let creation_options = options.gtiff_options(None);
+    let has = |key: &str, value: &str| creation_options.contains(&(key.to_string(), value.to_string()));
+    assert!(has("COMPRESS", "DEFLATE"));
+    assert!(has("ZLEVEL", "6"));
+    assert!(!has("ZLEVEL", "9"));
+    assert!(has("PREDICTOR", "2"));
+    assert!(has("TILED", "YES"));
+    assert!(has("BLOCKXSIZE", "256"));
+    assert!(has("NUM_THREADS", "ALL_CPUS"));
+}
+
+#[test]
+fn test_invalid_tiff_options() {
+    let invalid = |options: TiffOptions| options.validate().is_err();
+
+    assert!(invalid(TiffOptions { block_size: Some(500), ..cog() }));
+    assert!(invalid(TiffOptions { level: Some(13), ..cog() }));
+    assert!(invalid(TiffOptions { quality: Some(80), ..cog() }));
+    // GTiff 沿用来源的压缩方式时无法确定级别和质量是否有效
+    assert!(invalid(TiffOptions { level: Some(6), ..TiffOptions::default() }));
+    assert!(invalid(TiffOptions { quality: Some(80), ..TiffOptions::default() }));
+    assert!(!invalid(TiffOptions { level: Some(6), ..cog() }));
+    assert!(invalid(TiffOptions { compress: Some(Compression::Webp), predictor: Some(Predictor::Horizontal), ..cog() }));
+    assert!(invalid(TiffOptions { photometric: Some(Photometric::YCbCr), ..TiffOptions::default() }));
+    assert!(invalid(TiffOptions { photometric: Some(Photometric::Rgb), ..cog() }));
+    assert!(!invalid(TiffOptions { compress: Some(Compression::Webp), quality: Some(80), ..cog() }));
+    assert!(!invalid(TiffOptions { compress: Some(Compression::Jpeg), photometric: Some(Photometric::YCbCr), ..TiffOptions::default() }));
+}
+
+/// 与 DEM 同样大小的混合结果
+fn blend_result() -> BlendImage {
+    let (width, height) = Dataset::open(DEM).unwrap().raster_size();
+    let raw_pixels = (0..width * height).flat_map(|i| [(i % 256) as u8, 128, 64, 255]).collect();
+    BlendImage::from_raw(width as u32, height as u32, raw_pixels).unwrap()
+}
+
+fn temp_path(name: &str) -> String {
+    let path = std::env::temp_dir().join(format!("blend_images_geotiff_{}_{}", std::process::id(), name));
+    path.to_str().unwrap().to_string()
+}
+
+#[test]
+fn test_write_gtiff() {
+    let path = temp_path("gtiff.tif");
+    let options = TiffOptions {
+        compress: Some(Compression::Deflate),
+        level: Some(6),
+        block_size: Some(256),
+        ..TiffOptions::default()
+    };
+    BlendImage::save_tiff_with(blend_result(), &path, Some(DEM), &options).unwrap();
+
+    let dataset = Dataset::open(&path).unwrap();
+    assert_eq!(dataset.raster_count(), 4);
+    assert_eq!(dataset.metadata_item("COMPRESSION", "IMAGE_STRUCTURE").as_deref(), Some("DEFLATE"));
+    assert_ne!(dataset.metadata_item("LAYOUT", "IMAGE_STRUCTURE").as_deref(), Some("COG"));
+    assert_eq!(dataset.rasterband(1).unwrap().block_size(), (256, 256));
+    assert_eq!(dataset.geo_transform().unwrap(), Dataset::open(DEM).unwrap().geo_transform().unwrap());
+    assert!(validate_cog(&path).is_err());
+    std::fs::remove_file(path).unwrap();
+}
+
+#[test]
+fn test_write_cog() {
+    let path = temp_path("cog.tif");
+    let options = TiffOptions { compress: Some(Compression::Lzw), block_size: Some(128), ..cog() };
+    BlendImage::save_tiff_with(blend_result(), &path, Some(DEM), &options).unwrap();
+
+    validate_cog(&path).unwrap();
+    let dataset = Dataset::open(&path).unwrap();
+    assert_eq!(dataset.metadata_item("LAYOUT", "IMAGE_STRUCTURE").as_deref(), Some("COG"));
+    assert_eq!(dataset.metadata_item("COMPRESSION", "IMAGE_STRUCTURE").as_deref(), Some("LZW"));
+    assert_eq!(dataset.rasterband(1).unwrap().block_size(), (128, 128));
+    assert_eq!(dataset.projection(), Dataset::open(DEM).unwrap().projection());
+
+    // 编码为字节流时同样经过 COG 驱动
+    let bytes = BlendImage::encode_tiff_with(blend_result(), Some(DEM), &cog()).unwrap();
+    std::fs::write(&path, bytes).unwrap();
+    validate_cog(&path).unwrap();
+    let dataset = Dataset::open(&path).unwrap();
+    assert_eq!(dataset.metadata_item("COMPRESSION", "IMAGE_STRUCTURE").as_deref(), Some("DEFLATE"));
+    std::fs::remove_file(path).unwrap();
+}
@@ -0,0 +1,56 @@
+use blend_images::argparse::Format;
+use blend_images::blend::BlendImage;
+use blend_images::error::BlendError;
+use blend_images::output::{OutputSpec, OutputTarget};
+
+fn gradient(width: u32, height: u32) -> BlendImage {
+    let raw_pixels = (0..width * height).flat_map(|i| [(i % 256) as u8, 64, 128, 255]).collect();
+    BlendImage::from_raw(width, height, raw_pixels).unwrap()
+}
+
+#[test]
+fn test_from_raw() {
+    let image = gradient(3, 2);
+    assert_eq!((image.get_width(), image.get_height()), (3, 2));
+    assert!(matches!(BlendImage::from_raw(3, 2, vec![0; 3 * 2 * 3]), Err(BlendError::InvalidParameter(_))));
+    assert!(matches!(BlendImage::from_raw(3, 2, vec![0; 3 * 2 * 4 + 1]), Err(BlendError::InvalidParameter(_))));
+}
+
+#[test]
+fn test_from_bytes() {
+    let bytes = BlendImage::encode_image(gradient(5, 4), &Format::PNG).unwrap();
+    let image = BlendImage::from_reader(bytes.as_slice()).unwrap();
+    assert_eq!(image.raw_pixels(), gradient(5, 4).raw_pixels());
+
+    assert!(BlendImage::from_bytes(b"not an image").is_err());
+}
+
+#[test]
+fn test_output_target() {
+    assert_eq!(OutputTarget::parse("-"), OutputTarget::Stdout);
+    assert_eq!(OutputTarget::parse("out.png"), OutputTarget::File("out.png".to_string()));
+    assert_eq!(OutputTarget::parse("dir/"), OutputTarget::Folder("dir/".to_string()));
+    assert_eq!(OutputTarget::parse("dir/blend"), OutputTarget::Folder("dir/blend".to_string()));
+}
+
+#[test]
+fn test_format_fallback() {
+    assert_eq!(Format::from_path("out.JPG"), Some(Format::JPEG));
+    assert_eq!(Format::from_path("out.img"), None);
+
+    // 扩展名不是已知格式时按 --format 编码
+    let folder = std::env::temp_dir().join(format!("blend_images_io_{}", std::process::id()));
+    let save_path = folder.join("blend.img");
+    let save_path = save_path.to_str().unwrap();
+    let spec = OutputSpec::new(OutputTarget::File(save_path.to_string())).with_format(Format::JPEG);
+    spec.save(gradient(8, 8), save_path, None).unwrap();
+    let bytes = std::fs::read(save_path).unwrap();
+    assert_eq!(image::guess_format(&bytes).unwrap(), image::ImageFormat::Jpeg);
+
+    // 已知扩展名优先于 --format
+    let png_path = folder.join("blend.png");
+    let png_path = png_path.to_str().unwrap();
+    spec.save(gradient(8, 8), png_path, None).unwrap();
+    assert_eq!(image::guess_format(&std::fs::read(png_path).unwrap()).unwrap(), image::ImageFormat::Png);
+    std::fs::remove_dir_all(folder).unwrap();
+}
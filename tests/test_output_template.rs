@@ -0,0 +1,43 @@
+use blend_images::output::{render_template, OutputSpec, OutputTarget, TemplateContext, DEFAULT_BATCH_TEMPLATE};
+use blend_images::pipeline::BlendJob;
+
+fn context() -> TemplateContext<'static> {
+    TemplateContext {
+        image: "data/dem/beijing.tif",
+        image2: "data/hillshade/beijing_hs.tif",
+        mode: "multiply",
+        index: 3,
+        format: "png",
+    }
+}
+
+#[test]
+fn test_render_template() {
+    let filename = render_template("{stem}_{stem2}_{mode}_{index}.{format}", &context()).unwrap();
+    assert_eq!(filename, "beijing_beijing_hs_multiply_3.png");
+
+    let filename = render_template("{mode}/{stem}", &context()).unwrap();
+    assert_eq!(filename, "multiply/beijing.png");
+}
+
+#[test]
+fn test_render_template_expr_mode() {
+    let expr = TemplateContext { mode: "expr:a / b * 2", ..context() };
+    assert_eq!(render_template(DEFAULT_BATCH_TEMPLATE, &expr).unwrap(), "beijing_expr.png");
+
+    let custom = TemplateContext { mode: "my:mode", ..context() };
+    assert_eq!(render_template("{mode}", &custom).unwrap(), "my_mode.png");
+
+    let job = BlendJob::builder("dem/a.png", "hs/a.png")
+        .blend_mode("expr:base / top * 2")
+        .output(OutputSpec::new(OutputTarget::Folder("out".to_string())).with_template(DEFAULT_BATCH_TEMPLATE))
+        .build()
+        .unwrap();
+    assert_eq!(job.save_path().unwrap().as_deref(), Some("out/a_expr.png"));
+}
+
+#[test]
+fn test_render_template_error() {
+    assert!(render_template("{name}.png", &context()).is_err());
+    assert!(render_template("{stem", &context()).is_err());
+}
@@ -0,0 +1,132 @@
+use blend_images::argparse::{ArgParse, ArgParseProcess, Format};
+use blend_images::adjuster::AdjustStep;
+use blend_images::blend::BlendImage;
+use blend_images::core::run;
+use blend_images::error::BlendError;
+use blend_images::output::{OutputSpec, OutputTarget};
+use blend_images::pipeline::{Adjustments, BlendJob};
+use blend_images::recipe::Recipe;
+use clap::Parser;
+use image::{Rgba, RgbaImage};
+
+fn solid(value: u8) -> BlendImage {
+    BlendImage::from_raw(4, 4, [value, value, value, 255].repeat(16)).unwrap()
+}
+
+#[test]
+fn test_default_mode() {
+    let job = BlendJob::builder("a.png", "b.png").build().unwrap();
+    assert_eq!(job.blend_mode(), "overlay");
+    assert!(!job.swap_layers());
+    assert_eq!(job.adjustments(), &Adjustments::default());
+    assert!(job.output().is_none());
+}
+
+#[test]
+fn test_recipe_precedence() {
+    let recipe = Recipe {
+        mode: Some("screen".to_string()),
+        adjustments: Some(Adjustments { gamma: 1.5, ..Default::default() }),
+        ..Default::default()
+    };
+
+    // 后调用的设置覆盖 recipe, 命令行参数在 recipe 之后设置
+    let job = BlendJob::builder("a.png", "b.png")
+        .recipe(&recipe)
+        .blend_mode("multiply")
+        .build()
+        .unwrap();
+    assert_eq!(job.blend_mode(), "multiply");
+    assert_eq!(job.adjustments().gamma, 1.5);
+
+    let job = BlendJob::builder("a.png", "b.png")
+        .blend_mode("multiply")
+        .swap_layers(true)
+        .recipe(&recipe)
+        .build()
+        .unwrap();
+    assert_eq!(job.blend_mode(), "screen");
+    // recipe 中没有设置的保持不变
+    assert!(job.swap_layers());
+
+    let job = BlendJob::builder("a.png", "b.png")
+        .blend_mode("multiply")
+        .recipe(&Recipe::default())
+        .build()
+        .unwrap();
+    assert_eq!(job.blend_mode(), "multiply");
+}
+
+#[test]
+fn test_recipe_and_arguments() {
+    let recipe = Recipe {
+        adjustments: Some(Adjustments {
+            gamma: 1.5,
+            saturation: 20.0,
+            adjust: vec![AdjustStep::parse("levels:black=10").unwrap()],
+            ..Default::default()
+        }),
+        ..Default::default()
+    };
+
+    // 命令行中给出的增强参数覆盖配方, 默认值不覆盖, --adjust 接在配方之后
+    let args = ArgParse::parse_from(["blend_images", "-g", "0.8", "--adjust", "curves:points=[[0,0],[255,255]]"]);
+    let adjustments = args.adjustments(&recipe).unwrap();
+    assert_eq!(adjustments.gamma, 0.8);
+    assert_eq!(adjustments.saturation, 20.0);
+    let names: Vec<&str> = adjustments.adjust.iter().map(|step| step.name.as_str()).collect();
+    assert_eq!(names, ["levels", "curves"]);
+
+    let args = ArgParse::parse_from(["blend_images"]);
+    assert_eq!(&args.adjustments(&recipe).unwrap(), recipe.adjustments.as_ref().unwrap());
+    assert_eq!(args.adjustments(&Recipe::default()).unwrap(), Adjustments::default());
+}
+
+#[test]
+fn test_recipe_file_and_arguments() {
+    let folder = std::env::temp_dir().join(format!("blend_images_recipe_{}", std::process::id()));
+    std::fs::create_dir_all(&folder).unwrap();
+    let path = |name: &str| folder.join(name).to_str().unwrap().to_string();
+    RgbaImage::from_pixel(4, 4, Rgba([120, 120, 120, 255])).save(path("a.png")).unwrap();
+    RgbaImage::from_pixel(4, 4, Rgba([200, 200, 200, 255])).save(path("b.png")).unwrap();
+    std::fs::write(path("recipe.json"), r#"{ "mode": "multiply", "adjustments": { "gamma": 2.0 } }"#).unwrap();
+
+    let blend = |output: &str, extra: &[&str]| {
+        let mut args = vec!["blend_images".to_string(), path("a.png"), path("b.png"), "-o".to_string(), path(output)];
+        args.extend(extra.iter().map(|arg| arg.to_string()));
+        run(&ArgParse::parse_from(args)).unwrap();
+        image::open(path(output)).unwrap().to_rgba8().get_pixel(0, 0).0
+    };
+    let recipe = path("recipe.json");
+    let with_recipe = blend("recipe.png", &["--recipe", &recipe, "-g", "0.5"]);
+    let without_recipe = blend("arguments.png", &["-m", "multiply", "-g", "0.5"]);
+    assert_eq!(with_recipe, without_recipe);
+    assert_ne!(with_recipe, blend("gamma.png", &["--recipe", &recipe]));
+    std::fs::remove_dir_all(folder).unwrap();
+}
+
+#[test]
+fn test_unknown_mode() {
+    let result = BlendJob::builder("a.png", "b.png").blend_mode("unknown").build();
+    assert!(matches!(result, Err(BlendError::InvalidParameter(_))));
+}
+
+#[test]
+fn test_encode_stdout() {
+    let job = BlendJob::builder(solid(200), solid(100))
+        .blend_mode("multiply")
+        .output(OutputSpec::new(OutputTarget::Stdout).with_format(Format::PNG))
+        .build()
+        .unwrap();
+    assert_eq!(job.save_path().unwrap(), None);
+
+    let image = BlendImage::from_bytes(&job.encode().unwrap()).unwrap();
+    assert_eq!((image.get_width(), image.get_height()), (4, 4));
+    let pixel = &image.raw_pixels()[..4];
+    // 200 * 100 / 255
+    assert!(pixel[..3].iter().all(|&value| value.abs_diff(78) <= 1), "{:?}", pixel);
+    assert_eq!(pixel[3], 255);
+
+    let job = BlendJob::builder(solid(200), solid(100)).build().unwrap();
+    assert!(matches!(job.encode(), Err(BlendError::InvalidParameter(_))));
+}
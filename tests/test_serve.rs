@@ -0,0 +1,41 @@
+use blend_images::argparse::Format;
+use blend_images::pipeline::Adjustments;
+use blend_images::serve::{parse_query, parse_tile_path, BlendParams};
+use blend_images::tiles::TileCoord;
+
+#[test]
+fn test_parse_tile_path() {
+    assert_eq!(parse_tile_path("/12/3370/1552.png"), Some((TileCoord::new(12, 3370, 1552), Format::PNG)));
+    assert_eq!(parse_tile_path("/3/1/2.jpg"), Some((TileCoord::new(3, 1, 2), Format::JPEG)));
+    assert_eq!(parse_tile_path("/3/8/2.png"), None);
+    assert_eq!(parse_tile_path("/3/1/2.tif"), None);
+    assert_eq!(parse_tile_path("/favicon.ico"), None);
+}
+
+#[test]
+fn test_query_overrides_params() {
+    let params = BlendParams {
+        blend_mode: "overlay".to_string(),
+        swap_layers: false,
+        adjustments: Adjustments::default(),
+    };
+    let query = parse_query("mode=multiply&gamma=1.5&swap&adjust=levels%3Ablack%3D10%3Bwhite%3D240");
+    assert_eq!(query[0], ("mode".to_string(), "multiply".to_string()));
+
+    let overridden = params.with_query(&query).unwrap();
+    assert_eq!(overridden.blend_mode, "multiply");
+    assert!(overridden.swap_layers);
+    assert_eq!(overridden.adjustments.gamma, 1.5);
+    assert_eq!(overridden.adjustments.adjust.len(), 1);
+
+    assert!(params.with_query(&parse_query("gamma=high")).is_err());
+    assert!(params.with_query(&parse_query("adjust=unknown")).is_err());
+    assert!(params.with_query(&parse_query("mode=unknown")).is_err());
+    let query = [("mode".to_string(), format!("expr:{}", "base+".repeat(100_000)))];
+    let err = params.with_query(&query).unwrap_err().to_string();
+    assert!(err.contains("longer") && err.len() < 1024, "{}", err.len());
+    // 未知的参数被忽略
+    assert_eq!(params.with_query(&parse_query("v=2&_=1700000000")).unwrap(), params);
+    let gamma = params.with_query(&parse_query("gamma=1.5&v=2")).unwrap();
+    assert_eq!(gamma.adjustments.gamma, 1.5);
+}
@@ -0,0 +1,120 @@
+use std::io::{Read, Write};
+use std::net::{SocketAddr, TcpStream};
+
+use blend_images::argparse::Format;
+use blend_images::blend::BlendImage;
+use blend_images::service::{parse_multipart, BlendService, ServiceConfig};
+
+fn start(config: ServiceConfig) -> SocketAddr {
+    let service = BlendService::bind("127.0.0.1:0", config).unwrap();
+    let address = service.local_addr().unwrap();
+    std::thread::spawn(move || service.run());
+    address
+}
+
+/// 发送请求, 返回状态码和响应体
+fn request(address: SocketAddr, head: &str, body: &[u8]) -> (u16, Vec<u8>) {
+    let mut stream = TcpStream::connect(address).unwrap();
+    write!(stream, "{}\r\nContent-Length: {}\r\nConnection: close\r\n\r\n", head, body.len()).unwrap();
+    stream.write_all(body).unwrap();
+    let mut response = Vec::new();
+    stream.read_to_end(&mut response).unwrap();
+
+    let split = response.windows(4).position(|w| w == b"\r\n\r\n").unwrap();
+    let status = String::from_utf8_lossy(&response[9..12]).parse().unwrap();
+    (status, response[split + 4..].to_vec())
+}
+
+fn png(value: u8) -> Vec<u8> {
+    let image = BlendImage::from_raw(2, 2, [value, value, value, 255].repeat(4)).unwrap();
+    BlendImage::encode_image(image, &Format::PNG).unwrap()
+}
+
+fn multipart(parts: &[(&str, &[u8])]) -> Vec<u8> {
+    let mut body = Vec::new();
+    for (name, data) in parts {
+        write!(body, "--XYZ\r\nContent-Disposition: form-data; name=\"{}\"; filename=\"{}.png\"\r\n\r\n", name, name).unwrap();
+        body.extend_from_slice(data);
+        body.extend_from_slice(b"\r\n");
+    }
+    body.extend_from_slice(b"--XYZ--\r\n");
+    body
+}
+
+#[test]
+fn test_parse_multipart() {
+    let body = multipart(&[("options", b"{}"), ("base", b"\r\nbinary\r\n")]);
+    let parts = parse_multipart(&body, "XYZ").unwrap();
+    assert_eq!(parts.len(), 2);
+    assert_eq!(parts[0].name, "options");
+    assert_eq!(parts[1].filename.as_deref(), Some("base.png"));
+    assert_eq!(parts[1].data, b"\r\nbinary\r\n");
+}
+
+#[test]
+fn test_health() {
+    let address = start(ServiceConfig::default());
+    let (status, body) = request(address, "GET /health HTTP/1.1\r\nHost: localhost", b"");
+    assert_eq!(status, 200);
+    assert_eq!(body, br#"{"status":"ok"}"#);
+}
+
+#[test]
+fn test_blend_upload() {
+    let address = start(ServiceConfig::default());
+    let body = multipart(&[("options", br#"{"mode": "multiply"}"#), ("base", &png(128)), ("overlay", &png(128))]);
+    let head = "POST /blend HTTP/1.1\r\nHost: localhost\r\nContent-Type: multipart/form-data; boundary=XYZ";
+    let (status, body) = request(address, head, &body);
+    assert_eq!(status, 200);
+
+    let image = BlendImage::from_bytes(&body).unwrap();
+    assert_eq!((image.get_width(), image.get_height()), (2, 2));
+    assert_eq!(image.raw_pixels()[0], 64);
+}
+
+#[test]
+fn test_blend_rejected() {
+    let address = start(ServiceConfig { max_body_size: 1024, ..ServiceConfig::default() });
+    let head = "POST /blend HTTP/1.1\r\nHost: localhost\r\nContent-Type: application/json";
+
+    // 没有 --root 时不能读取服务器上的文件
+    let (status, _) = request(address, head, br#"{"base": "a.png", "overlay": "b.png"}"#);
+    assert_eq!(status, 400);
+
+    let (status, _) = request(address, head, &[b' '; 2048]);
+    assert_eq!(status, 413);
+}
+
+#[test]
+fn test_deep_expression() {
+    let address = start(ServiceConfig::default());
+    let head = "POST /blend HTTP/1.1\r\nHost: localhost\r\nContent-Type: multipart/form-data; boundary=XYZ";
+    for mode in [format!("expr:{}base{}", "(".repeat(1000), ")".repeat(1000)), format!("expr:{}top", "-".repeat(2000))] {
+        let options = serde_json::json!({ "mode": mode }).to_string();
+        let body = multipart(&[("options", options.as_bytes()), ("base", &png(128)), ("overlay", &png(128))]);
+        let (status, _) = request(address, head, &body);
+        assert_eq!(status, 400);
+    }
+    // 服务仍可用
+    let (status, _) = request(address, "GET /health HTTP/1.1\r\nHost: localhost", b"");
+    assert_eq!(status, 200);
+}
+
+#[test]
+fn test_rejected_options() {
+    let address = start(ServiceConfig::default());
+    let head = "POST /blend HTTP/1.1\r\nHost: localhost\r\nContent-Type: multipart/form-data; boundary=XYZ";
+    let options = [
+        serde_json::json!({ "mode": format!("expr:{}", "base+".repeat(1_000_000)) }),
+        serde_json::json!({ "mode": "x".repeat(1_000_000) }),
+        serde_json::json!({ "format": "tiff", "tiff": { "co": { "TFW": "YES" } } }),
+    ];
+    for options in options {
+        let options = options.to_string();
+        let body = multipart(&[("options", options.as_bytes()), ("base", &png(128)), ("overlay", &png(128))]);
+        let (status, body) = request(address, head, &body);
+        assert_eq!(status, 400);
+        // 错误信息不包含整个混合模式
+        assert!(body.len() < 1024, "{}", body.len());
+    }
+}
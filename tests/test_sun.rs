@@ -0,0 +1,29 @@
+use blend_images::sun::Sun;
+use chrono::{DateTime, Utc};
+
+fn time(s: &str) -> DateTime<Utc> {
+    DateTime::parse_from_rfc3339(s).unwrap().with_timezone(&Utc)
+}
+
+#[test]
+fn test_sun_position() {
+    // 伦敦夏至正午
+    let sun = Sun::at(time("2024-06-21T12:00:00Z"), -0.1275, 51.5072);
+    assert!((sun.altitude - 61.9).abs() < 0.5, "{:?}", sun);
+    assert!((sun.azimuth - 180.0).abs() < 2.0, "{:?}", sun);
+
+    // 傍晚在西北方
+    let sun = Sun::at(time("2024-06-21T20:00:00+01:00"), -0.1275, 51.5072);
+    assert!(sun.azimuth > 270.0 && sun.azimuth < 310.0, "{:?}", sun);
+    assert!(sun.altitude > 5.0 && sun.altitude < 25.0, "{:?}", sun);
+
+    // 春分时赤道正午接近天顶, 半夜在地平线以下
+    assert!(Sun::at(time("2024-03-20T12:00:00Z"), 0.0, 0.0).altitude > 88.0);
+    assert!(Sun::at(time("2024-03-20T00:00:00Z"), 0.0, 0.0).altitude < -80.0);
+}
+
+#[test]
+fn test_sun_vector() {
+    let [east, north, up] = Sun::new(90.0, 0.0).vector();
+    assert!((east - 1.0).abs() < 1e-12 && north.abs() < 1e-12 && up.abs() < 1e-12);
+}
@@ -0,0 +1,160 @@
+use blend_images::sun::Sun;
+use blend_images::terrain::{Dem, Derivative, SlopeUnit, Terrain, TerrainStyle};
+use blend_images::texture::fractional_laplacian;
+
+/// 向东升高的斜面, 坡度 45 度, 朝西
+fn plane() -> Dem {
+    let data = (0..25).map(|i| (i % 5) as f64 * 10.0).collect();
+    Dem::from_raw(5, 5, data, (10.0, 10.0)).unwrap()
+}
+
+fn center(dem: &Dem, terrain: Terrain) -> f64 {
+    terrain.compute(dem).data[dem.width * dem.height / 2]
+}
+
+#[test]
+fn test_slope_and_aspect() {
+    let dem = plane();
+    assert!((center(&dem, Terrain::new(Derivative::Slope)) - 45.0).abs() < 1e-9);
+    assert!((center(&dem, Terrain::new(Derivative::Slope).with_slope_unit(SlopeUnit::Percent)) - 100.0).abs() < 1e-9);
+    assert!((center(&dem, Terrain::new(Derivative::Aspect)) - 270.0).abs() < 1e-9);
+    assert!(center(&dem, Terrain::new(Derivative::Slope).with_z_factor(2.0)) > 60.0);
+
+    // 平地没有坡向
+    let flat = Dem::from_raw(3, 3, vec![5.0; 9], (1.0, 1.0)).unwrap();
+    assert_eq!(center(&flat, Terrain::new(Derivative::Aspect)), blend_images::calc::DEFAULT_NODATA);
+}
+
+#[test]
+fn test_curvature_and_position() {
+    let dem = plane();
+    assert_eq!(center(&dem, Terrain::new(Derivative::ProfileCurvature)), 0.0);
+    assert_eq!(center(&dem, Terrain::new(Derivative::Tpi)), 0.0);
+    assert_eq!(center(&dem, Terrain::new(Derivative::Roughness)), 20.0);
+
+    // 山峰: 凸起, 高于周围
+    let mut data = vec![0.0; 9];
+    data[4] = 9.0;
+    let peak = Dem::from_raw(3, 3, data, (1.0, 1.0)).unwrap();
+    assert_eq!(center(&peak, Terrain::new(Derivative::Tpi)), 9.0);
+    assert!((center(&peak, Terrain::new(Derivative::Tri)) - 8f64.sqrt() * 9.0).abs() < 1e-9);
+}
+
+#[test]
+fn test_nodata_and_style() {
+    let mut data = vec![1.0; 9];
+    data[0] = -32768.0;
+    let dem = Dem::from_raw(3, 3, data, (1.0, 1.0)).unwrap().with_nodata(Some(-32768.0));
+    let terrain = Terrain::new(Derivative::Slope);
+    let slope = terrain.compute(&dem);
+    // nodata 邻居按中心的值计算
+    assert_eq!(slope.data[4], 0.0);
+    assert_eq!(slope.data[0], slope.nodata);
+
+    let image = terrain.style(&dem, &slope, None, Some((0.0, 60.0))).unwrap();
+    assert_eq!(&image.raw_pixels()[..4], &[0, 0, 0, 0]);
+    assert_eq!(&image.raw_pixels()[16..20], &[255, 255, 255, 255]);
+    assert!(terrain.style(&dem, &slope, Some(TerrainStyle::Aspect), None).is_err());
+}
+
+#[test]
+fn test_sky_view() {
+    // 平地可以看到整个天空, 坑底被四周遮挡
+    let flat = Dem::from_raw(9, 9, vec![0.0; 81], (1.0, 1.0)).unwrap();
+    assert_eq!(center(&flat, Terrain::new(Derivative::SkyView)), 1.0);
+
+    let pit = Dem::from_raw(9, 9, (0..81).map(|i| if i == 40 { 0.0 } else { 4.0 }).collect(), (1.0, 1.0)).unwrap();
+    let terrain = Terrain::new(Derivative::SkyView).with_radius(4).with_directions(8);
+    let sky_view = center(&pit, terrain);
+    assert!(sky_view > 0.0 && sky_view < 1.0);
+    let occlusion = center(&pit, Terrain::new(Derivative::AmbientOcclusion).with_radius(4).with_directions(8));
+    assert!(occlusion > sky_view && occlusion < 1.0);
+    // 四个正方向的地平线高度角为 atan(4), 对角线方向的距离更远
+    let sin = 4f64.atan().sin();
+    let diagonal = (4.0 / 2f64.sqrt()).atan().sin();
+    assert!((sky_view - (1.0 - (sin + diagonal) / 2.0)).abs() < 1e-9);
+}
+
+/// 平地东侧第 5 列有一堵 3 米高的墙
+fn wall() -> Dem {
+    let data = (0..100).map(|i| if i % 10 == 5 { 3.0 } else { 0.0 }).collect();
+    Dem::from_raw(10, 10, data, (1.0, 1.0)).unwrap()
+}
+
+#[test]
+fn test_cast_shadow() {
+    let dem = wall();
+    let terrain = Terrain::new(Derivative::Shadow).with_sun(Sun::new(90.0, 45.0));
+    let shadow = terrain.compute(&dem);
+    let row: Vec<f64> = shadow.data[40..50].to_vec();
+    assert_eq!(row, [1.0, 1.0, 1.0, 0.0, 0.0, 1.0, 1.0, 1.0, 1.0, 1.0]);
+
+    // 墙顶高度角正好等于太阳高度角时一半可见
+    let soft = terrain.with_penumbra(2.0).compute(&dem);
+    assert_eq!(soft.data[42], 0.5);
+    assert_eq!(soft.data[43], 0.0);
+
+    // 太阳在地平线以下
+    let night = Terrain::new(Derivative::Shadow).with_sun(Sun::new(90.0, -5.0)).compute(&dem);
+    assert_eq!(night.data[0], 0.0);
+}
+
+#[test]
+fn test_hillshade() {
+    let flat = Dem::from_raw(3, 3, vec![0.0; 9], (1.0, 1.0)).unwrap();
+    let hillshade = center(&flat, Terrain::new(Derivative::Hillshade).with_sun(Sun::new(0.0, 30.0)));
+    assert!((hillshade - 0.5).abs() < 1e-9);
+
+    // 向东升高的斜面朝西, 西边的太阳更亮
+    let west = center(&plane(), Terrain::new(Derivative::Hillshade).with_sun(Sun::new(270.0, 45.0)));
+    let east = center(&plane(), Terrain::new(Derivative::Hillshade).with_sun(Sun::new(90.0, 45.0)));
+    assert!((west - 1.0).abs() < 1e-9);
+    assert_eq!(east, 0.0);
+
+    let dem = wall();
+    let terrain = Terrain::new(Derivative::Hillshade).with_sun(Sun::new(90.0, 45.0));
+    assert!(terrain.compute(&dem).data[43] > 0.5);
+    assert_eq!(terrain.with_cast_shadows(true).compute(&dem).data[43], 0.0);
+}
+
+#[test]
+fn test_texture_shading() {
+    // 平面没有纹理
+    let flat = fractional_laplacian(&[7.0; 64], 8, 8, (1.0, 1.0), 0.5);
+    assert!(flat.iter().all(|value| value.abs() < 1e-9));
+
+    // detail 为 0 时保持原样, nodata 用平均值填充
+    let data: Vec<f64> = (0..48).map(|i| ((i * 7) % 13) as f64).collect();
+    let same = fractional_laplacian(&data, 8, 6, (1.0, 1.0), 0.0);
+    assert!(same.iter().zip(&data).all(|(a, b)| (a - b).abs() < 1e-9));
+    let mut holes = data.clone();
+    holes[9] = f64::NAN;
+    let mean = holes.iter().filter(|value| !value.is_nan()).sum::<f64>() / 47.0;
+    assert!((fractional_laplacian(&holes, 8, 6, (1.0, 1.0), 0.0)[9] - mean).abs() < 1e-9);
+    // detail 大于 0 时去除平均值, 整体抬高不改变结果
+    let texture = fractional_laplacian(&data, 8, 6, (1.0, 1.0), 1.0);
+    let raised: Vec<f64> = data.iter().map(|value| value + 100.0).collect();
+    let raised = fractional_laplacian(&raised, 8, 6, (1.0, 1.0), 1.0);
+    assert!(texture.iter().zip(&raised).all(|(a, b)| (a - b).abs() < 1e-9));
+
+    // 山峰亮, 山谷暗
+    let size = 33;
+    let mut data: Vec<f64> = (0..size * size)
+        .map(|i| {
+            let (x, y) = ((i % size) as f64 - 16.0, (i / size) as f64 - 16.0);
+            100.0 * (-(x * x + y * y) / 20.0).exp()
+        })
+        .collect();
+    data[0] = -32768.0;
+    let dem = Dem::from_raw(size, size, data, (10.0, 10.0)).unwrap().with_nodata(Some(-32768.0));
+    let terrain = Terrain::new(Derivative::Texture).with_detail(0.8);
+    let texture = terrain.compute(&dem);
+    let peak = size * size / 2;
+    assert!(texture.data[peak] > 0.0);
+    assert!(texture.data[peak] > texture.data[peak + 6]);
+    assert_eq!(texture.data[0], texture.nodata);
+
+    let image = terrain.style(&dem, &texture, None, None).unwrap();
+    assert_eq!(image.raw_pixels()[peak * 4], 255);
+    assert_eq!(image.raw_pixels()[3], 0);
+}
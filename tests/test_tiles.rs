@@ -0,0 +1,96 @@
+use blend_images::output::{check_folder_exists, ExistsPolicy};
+use blend_images::tiles::{tiles_in, TileCoord, TilePyramid, TileScheme, TileStats, ZoomRange, MERCATOR_EXTENT};
+use blend_images::tilestore::{TileInfo, TileOutput, TileStore};
+
+#[test]
+fn test_tile_coord() {
+    assert_eq!(TileCoord::from_lon_lat(0.0, 0.0, 0), TileCoord::new(0, 0, 0));
+    // 伦敦
+    assert_eq!(TileCoord::from_lon_lat(-0.1275, 51.5072, 10), TileCoord::new(10, 511, 340));
+    assert_eq!(TileCoord::from_lon_lat(180.0, -90.0, 2), TileCoord::new(2, 3, 3));
+
+    let bounds = TileCoord::new(1, 1, 0).bounds();
+    assert_eq!(bounds, [0.0, 0.0, MERCATOR_EXTENT, MERCATOR_EXTENT]);
+}
+
+#[test]
+fn test_tile_scheme_row() {
+    let tile = TileCoord::new(3, 2, 1);
+    assert_eq!(tile.row(TileScheme::Xyz), 1);
+    assert_eq!(tile.row(TileScheme::Tms), 6);
+    assert_eq!(TileCoord::new(0, 0, 0).row(TileScheme::Tms), 0);
+}
+
+#[test]
+fn test_tiles_in_bounds() {
+    let tiles = tiles_in([-10.0, -10.0, 10.0, 10.0], 1);
+    assert_eq!(tiles.len(), 4);
+    assert_eq!(tiles_in([10.0, 10.0, 20.0, 20.0], 1), vec![TileCoord::new(1, 1, 0)]);
+}
+
+#[test]
+fn test_parse_zoom_range() {
+    assert_eq!("8-14".parse(), Ok(ZoomRange { min: 8, max: 14 }));
+    assert_eq!("12".parse(), Ok(ZoomRange { min: 12, max: 12 }));
+    assert!("14-8".parse::<ZoomRange>().is_err());
+    assert!("0-30".parse::<ZoomRange>().is_err());
+}
+
+#[test]
+fn test_mbtiles_store() {
+    let path = std::env::temp_dir().join(format!("blend_images_test_{}.mbtiles", std::process::id()));
+    let path = path.to_str().unwrap();
+    let output = TileOutput::parse(path);
+    assert_eq!(output, TileOutput::MbTiles(path.to_string()));
+
+    let pyramid = TilePyramid::new("3-4".parse().unwrap());
+    let store = TileStore::create(&output, &pyramid).unwrap();
+    store.insert(TileCoord::new(3, 2, 1), b"tile").unwrap();
+    let stats = TileStats { written: 1, empty: 0, bounds: [-90.0, 0.0, 0.0, 45.0] };
+    store.finish(&stats, &TileInfo::new("relief").with_attribution(Some("© relief".to_string()))).unwrap();
+
+    let connection = rusqlite::Connection::open(path).unwrap();
+    let row: u32 = connection
+        .query_row("SELECT tile_row FROM tiles WHERE zoom_level = 3 AND tile_column = 2", [], |row| row.get(0))
+        .unwrap();
+    assert_eq!(row, 6);
+    let metadata = |name: &str| -> String {
+        connection.query_row("SELECT value FROM metadata WHERE name = ?1", [name], |row| row.get(0)).unwrap()
+    };
+    assert_eq!(metadata("bounds"), "-90,0,0,45");
+    assert_eq!(metadata("center"), "-45,22.5,3");
+    assert_eq!(metadata("maxzoom"), "4");
+    assert_eq!(metadata("format"), "png");
+    assert_eq!(metadata("attribution"), "© relief");
+    std::fs::remove_file(path).unwrap();
+}
+
+#[test]
+fn test_existing_tile_folder() {
+    let folder = std::env::temp_dir().join(format!("blend_images_tiles_{}", std::process::id()));
+    std::fs::create_dir_all(&folder).unwrap();
+    let folder_path = folder.to_str().unwrap();
+    assert!(check_folder_exists(folder_path, ExistsPolicy::Fail).unwrap());
+
+    std::fs::create_dir_all(folder.join("8/210")).unwrap();
+    assert!(check_folder_exists(folder_path, ExistsPolicy::Fail).is_err());
+    assert!(!check_folder_exists(folder_path, ExistsPolicy::Skip).unwrap());
+    assert!(check_folder_exists(folder_path, ExistsPolicy::Overwrite).unwrap());
+    std::fs::remove_dir_all(folder).unwrap();
+}
+
+#[test]
+fn test_directory_store() {
+    let folder = std::env::temp_dir().join(format!("blend_images_tile_store_{}", std::process::id()));
+    let pyramid = TilePyramid::new("3".parse().unwrap());
+    let store = TileStore::create(&TileOutput::Directory(folder.to_str().unwrap().to_string()), &pyramid).unwrap();
+    store.insert(TileCoord::new(3, 2, 1), b"tile").unwrap();
+    assert_eq!(std::fs::read(folder.join("3/2/1.png")).unwrap(), b"tile");
+
+    // 不存在的上级文件夹会被创建
+    let path = folder.join("nested/relief.mbtiles");
+    let store = TileStore::create(&TileOutput::parse(path.to_str().unwrap()), &pyramid).unwrap();
+    store.insert(TileCoord::new(3, 2, 1), b"tile").unwrap();
+    assert!(path.exists());
+    std::fs::remove_dir_all(folder).unwrap();
+}
@@ -0,0 +1,36 @@
+use blend_images::argparse::Format;
+use blend_images::worldfile::{world_file_path, Georeference};
+
+#[test]
+fn test_world_file() {
+    let georeference = Georeference::new([500000.0, 10.0, 0.0, 4600000.0, 0.0, -10.0], String::new());
+    // 左上角像素中心
+    assert_eq!(georeference.world_file(), "10\n0\n0\n-10\n500005\n4599995\n");
+    assert!(!georeference.aux_xml().contains("<SRS>"));
+}
+
+#[test]
+fn test_world_file_path() {
+    assert_eq!(world_file_path("out/relief.png", &Format::PNG), "out/relief.pgw");
+    assert_eq!(world_file_path("relief.jpeg", &Format::JPEG), "relief.jgw");
+    assert_eq!(world_file_path("relief.webp", &Format::WEBP), "relief.wld");
+}
+
+#[test]
+fn test_write_sidecars() {
+    let folder = std::env::temp_dir().join(format!("blend_images_worldfile_{}", std::process::id()));
+    std::fs::create_dir_all(&folder).unwrap();
+    let image_path = folder.join("relief.png");
+    let image_path = image_path.to_str().unwrap();
+
+    let wkt = r#"GEOGCS["WGS 84",AUTHORITY["EPSG","4326"]]"#;
+    let georeference = Georeference::new([100.0, 0.5, 0.0, 30.0, 0.0, -0.5], wkt.to_string());
+    georeference.write_sidecars(image_path, &Format::PNG).unwrap();
+
+    let world_file = std::fs::read_to_string(folder.join("relief.pgw")).unwrap();
+    assert_eq!(world_file.lines().collect::<Vec<_>>(), ["0.5", "0", "0", "-0.5", "100.25", "29.75"]);
+    let aux_xml = std::fs::read_to_string(format!("{}.aux.xml", image_path)).unwrap();
+    assert!(aux_xml.contains("<SRS>GEOGCS[&quot;WGS 84&quot;,AUTHORITY[&quot;EPSG&quot;,&quot;4326&quot;]]</SRS>"));
+    assert!(aux_xml.contains("<GeoTransform>1.0000000000000000e2, 5.0000000000000000e-1,"));
+    std::fs::remove_dir_all(folder).unwrap();
+}